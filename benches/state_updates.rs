@@ -0,0 +1,87 @@
+//! What a state update costs with a long history: the store sends the state
+//! out and the router moves every pane to it. Next to that, what the same
+//! update cost when the history was copied each time instead of shared.
+//!
+//! ```sh
+//! cargo bench --bench state_updates
+//! ```
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use chatty::state::{Chat, Contact, ConversationList, Message, MessageDirection, State};
+use chatty::ui::{AppRouter, Component};
+use chrono::NaiveDateTime;
+use tokio::sync::mpsc;
+
+const UPDATES: u32 = 1000;
+
+fn history(conversations: usize, messages: usize) -> State {
+    let contacts: Vec<Contact> = (0..conversations)
+        .map(|i| Contact::new(format!("Contact {i}"), format!("{i:010}")))
+        .collect();
+    let messages = (0..messages)
+        .map(|i| {
+            Message::new(
+                contacts[0].clone(),
+                format!("message {i}, long enough to look like a real one"),
+                NaiveDateTime::default(),
+                MessageDirection::From,
+            )
+        })
+        .collect();
+
+    State::new(
+        Chat::new(contacts[0].clone(), messages),
+        ConversationList::new(contacts),
+    )
+}
+
+/// The time one update takes, averaged over `UPDATES`
+fn time(mut update: impl FnMut()) -> Duration {
+    let started = Instant::now();
+    for _ in 0..UPDATES {
+        update();
+    }
+    started.elapsed() / UPDATES
+}
+
+fn main() {
+    println!(
+        "{:>17} {:>16} {:>12} {:>12} {:>12}",
+        "", "", "shared", "copied", "router"
+    );
+
+    for (conversations, messages) in [(50, 100), (500, 1_000), (5_000, 10_000)] {
+        let state = history(conversations, messages);
+
+        // What the store sends and the panes take, with the history shared
+        let shared = time(|| {
+            let sent = black_box(state.clone());
+            black_box(sent.chat.messages.clone());
+            black_box(sent.conversations.contacts.clone());
+        });
+
+        // The same with every message and contact copied, as before sharing
+        let copied = time(|| {
+            let sent = black_box(state.clone());
+            black_box(sent.chat.messages.to_vec());
+            black_box(sent.conversations.contacts.to_vec());
+        });
+
+        // The store's copy going through every pane
+        let (action_tx, _action_rx) = mpsc::unbounded_channel();
+        let mut router = Some(AppRouter::new(&state, action_tx));
+        let routed = time(|| {
+            let sent = black_box(state.clone());
+            router = router.take().map(|router| router.move_with_state(&sent));
+        });
+        black_box(router);
+
+        println!(
+            "{conversations:>5} conversations {messages:>6} messages {shared:>12.1?} {copied:>12.1?} {routed:>12.1?}"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! The reference plugin for docs/plugin-protocol.md: a service where every
+//! message is answered with the same text.
+//!
+//! ```sh
+//! cargo build --example echo_plugin
+//! chatty --backend plugin:target/debug/examples/echo_plugin
+//! ```
+//!
+//! Messages reading "fail" are refused, to see how failed sends look. Any
+//! name can be joined, which starts another echoing conversation.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+const PROTOCOL_VERSION: u64 = 1;
+
+struct Echo {
+    /// Messages by conversation id
+    conversations: BTreeMap<String, Vec<Value>>,
+    next_id: u64,
+}
+
+impl Echo {
+    fn message(&mut self, body: &str, outgoing: bool) -> Value {
+        self.next_id += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        json!({
+            "id": self.next_id.to_string(),
+            "body": body,
+            "timestamp": timestamp,
+            "outgoing": outgoing,
+        })
+    }
+
+    /// Answers a request, notifications go out through `notify`
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &Value,
+        notify: &mut dyn FnMut(&str, Value),
+    ) -> Result<Value, String> {
+        match method {
+            "initialize" => {
+                if params["protocol_version"] != PROTOCOL_VERSION {
+                    return Err(format!(
+                        "only protocol version {} is supported",
+                        PROTOCOL_VERSION
+                    ));
+                }
+                Ok(json!({
+                    "protocol_version": PROTOCOL_VERSION,
+                    "name": "Echo",
+                    "capabilities": ["send", "join"],
+                }))
+            }
+            "list_conversations" => Ok(self
+                .conversations
+                .keys()
+                .map(|id| json!({ "id": id, "name": id }))
+                .collect()),
+            "get_messages" => {
+                let id = params["conversation_id"].as_str().unwrap_or_default();
+                let limit = params["limit"].as_u64().unwrap_or(u64::MAX) as usize;
+                let messages = self.conversations.get(id).ok_or("no such conversation")?;
+                let skip = messages.len().saturating_sub(limit);
+                Ok(Value::from(messages[skip..].to_vec()))
+            }
+            "send" => {
+                let id = params["conversation_id"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                let body = params["body"].as_str().unwrap_or_default();
+                if !self.conversations.contains_key(&id) {
+                    return Err(String::from("no such conversation"));
+                }
+                if body == "fail" {
+                    return Err(String::from("refusing to echo \"fail\""));
+                }
+
+                let sent = self.message(body, true);
+                let echo = self.message(body, false);
+                let messages = self.conversations.entry(id.clone()).or_default();
+                messages.push(sent.clone());
+                messages.push(echo.clone());
+
+                notify(
+                    "delivery",
+                    json!({ "message_id": sent["id"], "status": "delivered" }),
+                );
+                notify(
+                    "message_received",
+                    json!({ "conversation": { "id": id, "name": id }, "message": echo }),
+                );
+                Ok(json!({ "message_id": sent["id"] }))
+            }
+            "join" => {
+                let name = params["name"].as_str().unwrap_or_default().trim();
+                if name.is_empty() {
+                    return Err(String::from("name is empty"));
+                }
+                self.conversations.entry(name.to_string()).or_default();
+                Ok(json!({ "id": name, "name": name }))
+            }
+            "shutdown" => Ok(Value::Null),
+            _ => Err(format!("unknown method {}", method)),
+        }
+    }
+}
+
+fn write_line(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    writeln!(out, "{}", value)?;
+    out.flush()
+}
+
+fn main() -> io::Result<()> {
+    let mut echo = Echo {
+        conversations: BTreeMap::new(),
+        next_id: 0,
+    };
+    let welcome = echo.message("Say something and I will say it back", false);
+    echo.conversations
+        .insert(String::from("echo"), vec![welcome]);
+
+    let stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let request: Value = match serde_json::from_str(&line?) {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("ignoring line that is not JSON: {}", e);
+                continue;
+            }
+        };
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+
+        // Notifications go out after the response, which they refer to
+        let mut notifications = Vec::new();
+        let result = echo.handle(&method, &request["params"], &mut |method, params| {
+            notifications.push(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+        });
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+            Err(message) => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": 1, "message": message },
+            }),
+        };
+
+        let mut out = stdout.lock();
+        write_line(&mut out, &response)?;
+        for notification in &notifications {
+            write_line(&mut out, notification)?;
+        }
+        if method == "shutdown" {
+            break;
+        }
+    }
+
+    Ok(())
+}
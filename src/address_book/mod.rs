@@ -0,0 +1,176 @@
+//! Names for the handles backends report, read from vCard files
+
+use std::{collections::HashMap, fs, path::PathBuf, time::SystemTime};
+
+use anyhow::Context;
+use tracing::{event, Level};
+
+use crate::state::{normalize_handle, Contact};
+
+mod vcard;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AddressBook {
+    /// Everyone with a name and a handle, one entry per handle, in the order
+    /// of the files
+    entries: Vec<Contact>,
+    /// Names by normalized handle
+    names: HashMap<String, String>,
+}
+
+impl AddressBook {
+    /// Reads the cards in the files, failing if any of them can not be read
+    pub fn read(paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let mut book = Self::default();
+        for path in paths {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("could not read contacts from {}", path.display()))?;
+            for card in vcard::parse(&text) {
+                let Some(name) = card.name else {
+                    continue;
+                };
+                for handle in card.handles {
+                    book.add(name.clone(), handle);
+                }
+            }
+        }
+
+        event!(
+            Level::INFO,
+            "Read {} handles from {} contact files",
+            book.names.len(),
+            paths.len()
+        );
+        Ok(book)
+    }
+
+    fn add(&mut self, name: String, handle: String) {
+        let key = normalize_handle(&handle);
+        if let Some(known) = self.names.get(&key) {
+            // The first card to claim a handle keeps it
+            event!(
+                Level::DEBUG,
+                "{} is already {}, not naming it {}",
+                handle,
+                known,
+                name
+            );
+            return;
+        }
+        self.names.insert(key, name.clone());
+        self.entries.push(Contact::new(name, handle));
+    }
+
+    pub fn name(&self, handle: &str) -> Option<&str> {
+        self.names
+            .get(&normalize_handle(handle))
+            .map(String::as_str)
+    }
+
+    /// Everyone in the book, as contacts to start a conversation with
+    pub fn entries(&self) -> &[Contact] {
+        &self.entries
+    }
+
+    /// The handles whose name differs from an older book, with the name
+    /// they have now if any
+    pub fn changes<'a>(&'a self, old: &'a Self) -> Vec<(&'a str, Option<&'a str>)> {
+        let named = self
+            .entries
+            .iter()
+            .filter(|entry| old.name(&entry.phone) != Some(entry.name.as_str()))
+            .map(|entry| (entry.phone.as_str(), Some(entry.name.as_str())));
+        let forgotten = old
+            .entries
+            .iter()
+            .filter(|entry| self.name(&entry.phone).is_none())
+            .map(|entry| (entry.phone.as_str(), None));
+        named.chain(forgotten).collect()
+    }
+}
+
+/// When each file was last changed, to tell when to read them again
+pub fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(entries: &[(&str, &str)]) -> AddressBook {
+        let mut book = AddressBook::default();
+        for (name, handle) in entries {
+            book.add(name.to_string(), handle.to_string());
+        }
+        book
+    }
+
+    #[test]
+    fn reads_named_handles() {
+        let directory = tempfile::tempdir().unwrap();
+        let fixture = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/contacts.vcf");
+        let other = directory.path().join("other.vcf");
+        fs::write(
+            &other,
+            "BEGIN:VCARD\nFN:Someone Else\nTEL:+15551234567\nTEL:+15554443333\nEND:VCARD\n",
+        )
+        .unwrap();
+
+        let book = AddressBook::read(&[fixture, other]).unwrap();
+        let handles: Vec<&str> = book.entries().iter().map(|c| c.phone.as_str()).collect();
+        assert_eq!(
+            handles,
+            [
+                "+1 (555) 123-4567",
+                "555-000-1111",
+                "joe@example.com",
+                "joe.smith@example.org",
+                "+15559876543",
+                "jane@example.com",
+                "+1-555-222-3333",
+                "ann@example.com",
+                "+15554443333",
+            ]
+        );
+        // However it is written, and the first file to name it wins
+        assert_eq!(book.name("15551234567"), Some("Smith, Joe"));
+        assert_eq!(book.name("JOE@example.com"), Some("Smith, Joe"));
+        assert_eq!(book.name("+15554443333"), Some("Someone Else"));
+        assert_eq!(book.name("+15550000000"), None);
+
+        let missing = directory.path().join("missing.vcf");
+        assert!(AddressBook::read(&[missing]).is_err());
+    }
+
+    #[test]
+    fn lists_changes() {
+        let old = book(&[
+            ("Joe", "+1 555 123 4567"),
+            ("Jane", "+15559876543"),
+            ("Ann", "ann@example.com"),
+        ]);
+        let new = book(&[
+            ("Joseph", "+15551234567"),
+            ("Ann", "ann@example.com"),
+            ("Bob", "+15552223333"),
+        ]);
+
+        assert_eq!(
+            new.changes(&old),
+            [
+                ("+15551234567", Some("Joseph")),
+                ("+15552223333", Some("Bob")),
+                ("+15559876543", None),
+            ]
+        );
+        assert_eq!(new.changes(&new), []);
+    }
+}
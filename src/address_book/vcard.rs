@@ -0,0 +1,232 @@
+/// The parts of a vCard chatty uses
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Card {
+    pub name: Option<String>,
+    /// Phone numbers and email addresses, as written in the card
+    pub handles: Vec<String>,
+}
+
+/// Reads the cards in a vCard 3.0 or 4.0 file. Cards without a name or a
+/// handle are kept, it is up to the caller what to do with them.
+pub fn parse(text: &str) -> Vec<Card> {
+    let mut cards = Vec::new();
+    let mut card: Option<Card> = None;
+    // Used when a card has no FN
+    let mut structured_name = None;
+
+    for line in unfold(text.trim_start_matches('\u{feff}')) {
+        let Some((name, value)) = split_property(&line) else {
+            continue;
+        };
+
+        match (name.as_str(), card.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VCARD") => {
+                card = Some(Card::default());
+                structured_name = None;
+            }
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VCARD") => {
+                let mut done = card.take().unwrap_or_default();
+                if done.name.is_none() {
+                    done.name = structured_name.take();
+                }
+                cards.push(done);
+            }
+            ("FN", Some(card)) => {
+                let name = unescape(value).trim().to_string();
+                if !name.is_empty() {
+                    card.name = Some(name);
+                }
+            }
+            ("N", Some(_)) => structured_name = join_structured_name(value),
+            // vCard 4.0 can write numbers as tel: URIs
+            ("TEL", Some(card)) => push_handle(card, unescape(strip_scheme(value, "tel:"))),
+            ("EMAIL", Some(card)) => push_handle(card, unescape(strip_scheme(value, "mailto:"))),
+            _ => {}
+        }
+    }
+    cards
+}
+
+fn push_handle(card: &mut Card, handle: String) {
+    let handle = handle.trim();
+    if !handle.is_empty() && !card.handles.iter().any(|h| h == handle) {
+        card.handles.push(handle.to_string());
+    }
+}
+
+fn strip_scheme<'a>(value: &'a str, scheme: &str) -> &'a str {
+    match value.get(..scheme.len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(scheme) => &value[scheme.len()..],
+        _ => value,
+    }
+}
+
+/// Joins lines folded by starting them with a space or tab
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Splits `[group.]NAME[;parameters]:value` into the uppercased name and the
+/// value. Parameters can quote colons.
+fn split_property(line: &str) -> Option<(String, &str)> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+
+    let (key, value) = (&line[..colon], &line[colon + 1..]);
+    let name = key.split(';').next().unwrap_or(key);
+    let name = name.rsplit('.').next().unwrap_or(name);
+    Some((name.trim().to_ascii_uppercase(), value))
+}
+
+/// `Family;Given;Additional;Prefixes;Suffixes` as it would be written out
+fn join_structured_name(value: &str) -> Option<String> {
+    let parts: Vec<String> = split_unescaped(value, ';')
+        .iter()
+        .map(|p| unescape(p))
+        .collect();
+    let part = |i: usize| parts.get(i).map(|p| p.trim()).unwrap_or_default();
+    let name = [part(3), part(1), part(2), part(0), part(4)]
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!name.is_empty()).then_some(name)
+}
+
+/// Splits at separators that are not escaped with a backslash
+fn split_unescaped(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                parts.last_mut().unwrap().push(c);
+                parts.last_mut().unwrap().extend(chars.next());
+            }
+            c if c == separator => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Names and handles are shown on one line
+            Some('n' | 'N') => unescaped.push(' '),
+            Some(escaped) => unescaped.push(escaped),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/contacts.vcf");
+
+    fn card(name: Option<&str>, handles: &[&str]) -> Card {
+        Card {
+            name: name.map(String::from),
+            handles: handles.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn parses_the_fixture() {
+        assert_eq!(
+            parse(FIXTURE),
+            [
+                // Escapes, several numbers and emails, one written twice
+                card(
+                    Some("Smith, Joe"),
+                    &[
+                        "+1 (555) 123-4567",
+                        "+1 555 123 4567",
+                        "555-000-1111",
+                        "joe@example.com",
+                        "joe.smith@example.org",
+                    ]
+                ),
+                // Grouped properties, a folded line and no FN
+                card(
+                    Some("Dr. Jane Q. Doe PhD"),
+                    &["+15559876543", "jane@example.com"]
+                ),
+                // vCard 4.0 URIs, quoted parameters and a folded FN
+                card(Some("Ann Lee"), &["+1-555-222-3333", "ann@example.com"]),
+                card(Some("Nobody Reachable"), &[]),
+                card(None, &["+15550000000"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unfolds_lines() {
+        assert_eq!(unfold("FN:Jo\n hn\n\tDoe\nTEL:1"), ["FN:JohnDoe", "TEL:1"]);
+        // Nothing to join a leading continuation to, it stays a line
+        assert_eq!(unfold(" FN:Joe"), [" FN:Joe"]);
+    }
+
+    #[test]
+    fn unescapes_values() {
+        assert_eq!(unescape(r"a\,b\;c\\d\ne\Nf"), r"a,b;c\d e f");
+        assert_eq!(unescape("trailing\\"), "trailing");
+        assert_eq!(
+            split_unescaped(r"Doe\;Smith;John", ';'),
+            [r"Doe\;Smith", "John"]
+        );
+        assert_eq!(
+            join_structured_name(r"Doe\;Smith;John;;;"),
+            Some(String::from("John Doe;Smith"))
+        );
+        assert_eq!(join_structured_name(";;;;"), None);
+    }
+
+    #[test]
+    fn splits_properties() {
+        assert_eq!(
+            split_property("item1.tel;type=CELL:+1555"),
+            Some((String::from("TEL"), "+1555"))
+        );
+        assert_eq!(
+            split_property(r#"TEL;X-A="b:c":tel:+1555"#),
+            Some((String::from("TEL"), "tel:+1555"))
+        );
+        assert_eq!(split_property("no colon"), None);
+        assert_eq!(strip_scheme("TEL:+1555", "tel:"), "+1555");
+        assert_eq!(strip_scheme("+1555", "tel:"), "+1555");
+    }
+
+    #[test]
+    fn keeps_cards_that_do_not_end() {
+        // A card cut off halfway is dropped, the ones before are kept
+        let text = "BEGIN:VCARD\nFN:Joe\nEND:VCARD\nBEGIN:VCARD\nFN:Jane\n";
+        assert_eq!(parse(text), [card(Some("Joe"), &[])]);
+        // Properties outside of a card are ignored
+        assert_eq!(parse("FN:Joe\nTEL:+1555\n"), []);
+    }
+}
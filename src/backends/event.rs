@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::{Contact, Message};
+
+/// Something that happened on the messaging service that the rest of the app
+/// needs to hear about. Backends push these as they happen, the state store
+/// then refreshes whatever they touched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BackendEvent {
+    MessageReceived(Message),
+    Typing {
+        contact: Contact,
+        is_typing: bool,
+    },
+    DeliveryFailed {
+        message_id: String,
+        reason: String,
+    },
+    DeliveryReceipt {
+        message_id: String,
+    },
+    ReadReceipt {
+        message_id: String,
+    },
+    Reaction {
+        message_id: String,
+        from: Contact,
+        reaction: String,
+    },
+    ContactRenamed {
+        phone: String,
+        name: String,
+    },
+    Disconnected {
+        reason: String,
+    },
+    Connected,
+}
+
+/// A simulated change to feed into a backend through `EventInjector`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Injection {
+    /// Behave as though the service reported the event
+    Event(BackendEvent),
+    /// Receive `count` messages from `contact`, one every `interval`
+    Burst {
+        contact: Contact,
+        count: usize,
+        interval: Duration,
+    },
+}
+
+/// Test-injection interface for backends that can simulate service events.
+///
+/// The backend applies the injection to its own data exactly as it would a
+/// real event and then reports it through its event stream, so the UI sees
+/// the same thing it would for the real service.
+pub trait EventInjector {
+    fn inject(&mut self, injection: Injection) -> anyhow::Result<()>;
+}
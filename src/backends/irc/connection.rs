@@ -0,0 +1,344 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+use tokio_rustls::{
+    rustls::{self, pki_types::ServerName},
+    TlsConnector,
+};
+use tracing::{event, Level};
+
+use super::protocol::{ctcp_action, is_channel, is_ctcp, privmsg_lines, IrcMessage};
+use super::{Conversations, IrcConfig};
+use crate::backends::BackendEvent;
+use crate::state::{Contact, DeliveryStatus, Message, MessageDirection};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+
+/// Token of the ping sent before quitting
+const QUIT_PING: &str = "chatty-quit";
+
+/// Requests from the backend to the connection task
+#[derive(Debug)]
+pub enum Outgoing {
+    Privmsg {
+        target: String,
+        message_id: String,
+        text: String,
+    },
+    Join(String),
+    /// Leave the server and stop reconnecting
+    Quit,
+}
+
+/// Why a session with the server ended without an error
+enum SessionEnd {
+    Quit,
+    ClosedByServer,
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub struct Connection {
+    config: IrcConfig,
+    conversations: Arc<Mutex<Conversations>>,
+    outgoing: UnboundedReceiver<Outgoing>,
+    event_tx: UnboundedSender<BackendEvent>,
+    /// Channels to be in, rejoined after reconnecting
+    channels: BTreeSet<String>,
+    /// Our nick as the server knows it, which may differ from the configured
+    /// one when that was taken
+    nick: String,
+    /// The last message sent to each lowercased target, which is what a
+    /// "no such nick" or "cannot send" reply refers to
+    last_sent: HashMap<String, String>,
+    backoff: Duration,
+}
+
+impl Connection {
+    pub fn new(
+        config: IrcConfig,
+        conversations: Arc<Mutex<Conversations>>,
+        outgoing: UnboundedReceiver<Outgoing>,
+        event_tx: UnboundedSender<BackendEvent>,
+    ) -> Self {
+        Self {
+            channels: config.channels.iter().cloned().collect(),
+            nick: config.nick.clone(),
+            config,
+            conversations,
+            outgoing,
+            event_tx,
+            last_sent: HashMap::new(),
+            backoff: MIN_BACKOFF,
+        }
+    }
+
+    /// Keeps a session with the server going until the backend quits
+    pub async fn run(mut self) {
+        loop {
+            let reason = match self.session().await {
+                Ok(SessionEnd::Quit) => {
+                    event!(Level::INFO, "Disconnected from {}", self.config);
+                    return;
+                }
+                Ok(SessionEnd::ClosedByServer) => String::from("connection closed by server"),
+                Err(e) => format!("{:#}", e),
+            };
+
+            event!(
+                Level::WARN,
+                "Lost connection to {}: {}, retrying in {:?}",
+                self.config,
+                reason,
+                self.backoff
+            );
+            let _ = self.event_tx.send(BackendEvent::Disconnected { reason });
+
+            if self.wait_to_reconnect().await.is_err() {
+                return;
+            }
+            self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Sleeps through the backoff, failing messages sent in the meantime.
+    /// Errors when the backend quits while waiting.
+    async fn wait_to_reconnect(&mut self) -> Result<(), ()> {
+        let sleep = tokio::time::sleep(self.backoff);
+        tokio::pin!(sleep);
+
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return Ok(()),
+                outgoing = self.outgoing.recv() => match outgoing {
+                    Some(Outgoing::Privmsg { message_id, .. }) => {
+                        self.fail_message(message_id, String::from("not connected to the server"));
+                    }
+                    Some(Outgoing::Join(channel)) => {
+                        self.channels.insert(channel);
+                    }
+                    Some(Outgoing::Quit) | None => return Err(()),
+                },
+            }
+        }
+    }
+
+    async fn connect(&self) -> anyhow::Result<Box<dyn Stream>> {
+        let address = (self.config.host.as_str(), self.config.port);
+        let tcp = TcpStream::connect(address)
+            .await
+            .with_context(|| format!("could not connect to {}", self.config))?;
+
+        if !self.config.tls {
+            return Ok(Box::new(tcp));
+        }
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let tls_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let server_name = ServerName::try_from(self.config.host.clone())?;
+        let tls = TlsConnector::from(Arc::new(tls_config))
+            .connect(server_name, tcp)
+            .await
+            .context("TLS handshake failed")?;
+        Ok(Box::new(tls))
+    }
+
+    async fn session(&mut self) -> anyhow::Result<SessionEnd> {
+        event!(Level::INFO, "Connecting to {}", self.config);
+        let stream = self.connect().await?;
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        self.nick = self.config.nick.clone();
+        if let Some(password) = &self.config.password {
+            send_line(&mut writer, &format!("PASS {}", password)).await?;
+        }
+        send_line(&mut writer, &format!("NICK {}", self.nick)).await?;
+        send_line(
+            &mut writer,
+            &format!("USER {} 0 * :chatty", self.config.nick),
+        )
+        .await?;
+
+        // Messages queued before the server welcomes us wait in the channel
+        let mut registered = false;
+        let mut quitting = false;
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        return Ok(SessionEnd::ClosedByServer);
+                    };
+                    event!(Level::TRACE, "<< {}", line);
+
+                    let Some(message) = IrcMessage::parse(&line) else {
+                        event!(Level::DEBUG, "Ignoring malformed line {:?}", line);
+                        continue;
+                    };
+                    if message.command == "001" {
+                        registered = true;
+                    }
+                    if quitting && message.command == "PONG" && message.params.last().is_some_and(|token| token == QUIT_PING) {
+                        send_line(&mut writer, "QUIT :Leaving").await?;
+                        writer.shutdown().await?;
+                        return Ok(SessionEnd::Quit);
+                    }
+                    for reply in self.handle_message(message)? {
+                        send_line(&mut writer, &reply).await?;
+                    }
+                }
+                outgoing = self.outgoing.recv(), if registered && !quitting => match outgoing {
+                    Some(Outgoing::Privmsg { target, message_id, text }) => {
+                        for line in privmsg_lines(&target, &text) {
+                            send_line(&mut writer, &line).await?;
+                        }
+                        self.last_sent.insert(target.to_lowercase(), message_id);
+                    }
+                    Some(Outgoing::Join(channel)) => {
+                        if self.channels.insert(channel.clone()) {
+                            send_line(&mut writer, &format!("JOIN {}", channel)).await?;
+                        }
+                    }
+                    Some(Outgoing::Quit) | None => {
+                        // The server answers in order, so once it replies to
+                        // this ping any errors about our messages are in
+                        send_line(&mut writer, &format!("PING :{}", QUIT_PING)).await?;
+                        quitting = true;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Handles a line from the server, returning the lines to reply with
+    fn handle_message(&mut self, message: IrcMessage) -> anyhow::Result<Vec<String>> {
+        let mut replies = Vec::new();
+
+        match message.command.as_str() {
+            "PING" => replies.push(format!("PONG :{}", message.param(0).unwrap_or_default())),
+            // Welcome, registration is done
+            "001" => {
+                if let Some(nick) = message.param(0) {
+                    self.nick = nick.to_string();
+                }
+                event!(Level::INFO, "Connected to {} as {}", self.config, self.nick);
+                self.backoff = MIN_BACKOFF;
+                let _ = self.event_tx.send(BackendEvent::Connected);
+
+                replies.extend(
+                    self.channels
+                        .iter()
+                        .map(|channel| format!("JOIN {}", channel)),
+                );
+            }
+            // Nick in use, only retried before registration
+            "433" if message.param(0) == Some("*") => {
+                self.nick.push('_');
+                replies.push(format!("NICK {}", self.nick));
+            }
+            "NICK" if message.source_nick() == Some(self.nick.as_str()) => {
+                if let Some(nick) = message.param(0) {
+                    self.nick = nick.to_string();
+                }
+            }
+            "JOIN" if message.source_nick() == Some(self.nick.as_str()) => {
+                if let Some(channel) = message.param(0) {
+                    self.conversations.lock().unwrap().open(channel);
+                }
+            }
+            "PRIVMSG" => self.handle_privmsg(&message),
+            // No such nick or channel, or not allowed to send to the channel
+            "401" | "403" | "404" => {
+                let target = message.param(1).unwrap_or_default().to_lowercase();
+                let reason = message.param(2).unwrap_or("could not send").to_string();
+                if let Some(message_id) = self.last_sent.remove(&target) {
+                    self.fail_message(message_id, reason);
+                }
+            }
+            "ERROR" => {
+                return Err(anyhow!(
+                    "server error: {}",
+                    message.param(0).unwrap_or_default()
+                ))
+            }
+            _ => {}
+        }
+
+        Ok(replies)
+    }
+
+    fn handle_privmsg(&mut self, message: &IrcMessage) {
+        let (Some(nick), Some(target), Some(text)) =
+            (message.source_nick(), message.param(0), message.param(1))
+        else {
+            return;
+        };
+
+        let content = match ctcp_action(text) {
+            Some(action) => format!("* {} {}", nick, action),
+            None if is_ctcp(text) => return,
+            None => text.to_string(),
+        };
+
+        let sender = Contact::new(nick.to_string(), nick.to_string());
+        // Private messages are addressed to us, the conversation is the sender
+        let conversation = if is_channel(target) { target } else { nick };
+        let mut received = Message::new(
+            Contact::new(conversation.to_string(), conversation.to_string()),
+            content,
+            chrono::offset::Local::now().naive_local(),
+            MessageDirection::From,
+        );
+        if is_channel(target) {
+            received = received.with_sender(sender);
+        }
+
+        let mut conversations = self.conversations.lock().unwrap();
+        // Keep the name as first seen rather than however this line spells it
+        received.contact = conversations.open(conversation).contact.clone();
+        conversations.push(received.clone());
+        drop(conversations);
+
+        let _ = self.event_tx.send(BackendEvent::MessageReceived(received));
+    }
+
+    fn fail_message(&self, message_id: String, reason: String) {
+        if let Some(message) = self.conversations.lock().unwrap().find_message(&message_id) {
+            message.status = DeliveryStatus::Failed(reason.clone());
+        }
+        let _ = self
+            .event_tx
+            .send(BackendEvent::DeliveryFailed { message_id, reason });
+    }
+}
+
+async fn send_line(writer: &mut (impl AsyncWrite + Unpin), line: &str) -> anyhow::Result<()> {
+    if line.starts_with("PASS ") {
+        event!(Level::TRACE, ">> PASS ****");
+    } else {
+        event!(Level::TRACE, ">> {}", line);
+    }
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\r\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,118 @@
+/// IRC lines may not be longer than this, including the trailing CRLF
+const MAX_LINE_LENGTH: usize = 512;
+
+/// Room left for the prefix the server adds when relaying a message to others
+const PREFIX_ALLOWANCE: usize = 100;
+
+/// A single parsed line from the server, see RFC 1459 section 2.3.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    /// Parses a line without its CRLF, message tags are dropped
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1.trim_start();
+        }
+
+        let mut prefix = None;
+        if let Some(without_colon) = rest.strip_prefix(':') {
+            let (source, remainder) = without_colon.split_once(' ')?;
+            prefix = Some(source.to_string());
+            rest = remainder.trim_start();
+        }
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            let (param, remainder) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = remainder;
+        }
+
+        Some(Self {
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+
+    /// The nick of whoever sent the message, from a `nick!user@host` prefix
+    pub fn source_nick(&self) -> Option<&str> {
+        let prefix = self.prefix.as_deref()?;
+        Some(prefix.split_once('!').map_or(prefix, |(nick, _)| nick))
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+}
+
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+/// Unwraps a CTCP ACTION (`/me`) into its text
+pub fn ctcp_action(text: &str) -> Option<&str> {
+    text.strip_prefix("\u{1}ACTION ")
+        .map(|action| action.trim_end_matches('\u{1}'))
+}
+
+pub fn is_ctcp(text: &str) -> bool {
+    text.starts_with('\u{1}')
+}
+
+/// Builds the PRIVMSG lines needed to send the text, one per line of text and
+/// split further where a line would be too long for the server
+pub fn privmsg_lines(target: &str, text: &str) -> Vec<String> {
+    let header = format!("PRIVMSG {} :", target);
+    let max_text = MAX_LINE_LENGTH
+        .saturating_sub(header.len() + PREFIX_ALLOWANCE + 2)
+        .max(1);
+
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .flat_map(|line| split_at_char_boundaries(line, max_text))
+        .map(|chunk| format!("{}{}", header, chunk))
+        .collect()
+}
+
+fn split_at_char_boundaries(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > max_bytes {
+        let mut split = max_bytes;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        if split == 0 {
+            // A single character wider than the limit, send it whole
+            split = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (chunk, remainder) = rest.split_at(split);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+
+    chunks.push(rest);
+    chunks
+}
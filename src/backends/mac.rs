@@ -1,22 +1,23 @@
-use chrono::NaiveDateTime;
+use chrono::DateTime;
 
 use crate::state::{Contact, Message, MessageDirection};
 use super::MsgBackend;
 
+#[derive(Default)]
 pub struct MacBackend {
 
 }
 
 impl MsgBackend for MacBackend {
-    fn send_message(&mut self, message: Message) {
+    fn send_message(&mut self, _message: Message) {
 
     }
 
-    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+    fn get_messages(&self, contact: &Contact, _n: Option<u8>) -> Vec<Message> {
         vec![
-            Message::new(contact.clone(), String::from("hey"), NaiveDateTime::from_timestamp(1724895116, 0), MessageDirection::To),
-            Message::new(contact.clone(), String::from("hi"),  NaiveDateTime::from_timestamp(1724895126, 0), MessageDirection::From),
-            Message::new(contact.clone(), String::from("hello"), NaiveDateTime::from_timestamp(1724895136, 0), MessageDirection::From),
+            Message::new(contact.clone(), String::from("hey"), DateTime::from_timestamp(1724895116, 0).unwrap().naive_utc(), MessageDirection::To),
+            Message::new(contact.clone(), String::from("hi"),  DateTime::from_timestamp(1724895126, 0).unwrap().naive_utc(), MessageDirection::From),
+            Message::new(contact.clone(), String::from("hello"), DateTime::from_timestamp(1724895136, 0).unwrap().naive_utc(), MessageDirection::From),
         ]
     }
 
@@ -0,0 +1,240 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{anyhow, Context};
+use reqwest::{Method, RequestBuilder, Response, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::session::Session;
+
+/// How long the server may hold a sync request open waiting for events
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Only the newest messages of each room are loaded on the first sync
+const INITIAL_TIMELINE_LIMIT: u32 = 50;
+
+/// The parts of the client-server API the backend uses
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    homeserver: Url,
+    access_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    access_token: String,
+    user_id: String,
+    device_id: String,
+}
+
+#[derive(Deserialize)]
+struct WhoamiResponse {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+struct SendResponse {
+    event_id: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    errcode: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SyncResponse {
+    pub next_batch: String,
+    pub rooms: SyncRooms,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SyncRooms {
+    pub join: HashMap<String, JoinedRoom>,
+    pub leave: HashMap<String, Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct JoinedRoom {
+    pub summary: RoomSummary,
+    pub state: Events,
+    pub timeline: Events,
+    pub ephemeral: Events,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RoomSummary {
+    /// Members to name the room after when it has no name
+    #[serde(rename = "m.heroes")]
+    pub heroes: Vec<String>,
+    #[serde(rename = "m.joined_member_count")]
+    pub joined_member_count: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Events {
+    pub events: Vec<RoomEvent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RoomEvent {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub event_id: Option<String>,
+    pub sender: Option<String>,
+    /// Milliseconds since the epoch
+    pub origin_server_ts: Option<i64>,
+    pub state_key: Option<String>,
+    pub content: Value,
+    pub unsigned: Unsigned,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Unsigned {
+    /// Set on our own events, to the id we sent them with
+    pub transaction_id: Option<String>,
+}
+
+impl Client {
+    pub fn new(homeserver: Url) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("chatty/", env!("CARGO_PKG_VERSION")))
+            .timeout(SYNC_TIMEOUT * 2)
+            .build()?;
+
+        Ok(Self {
+            http,
+            homeserver,
+            access_token: None,
+        })
+    }
+
+    /// Logs in with a password, as the device of an earlier login when given
+    /// one
+    pub async fn login(
+        &mut self,
+        user: &str,
+        password: &str,
+        device_id: Option<&str>,
+    ) -> anyhow::Result<Session> {
+        let mut body = json!({
+            "type": "m.login.password",
+            "identifier": { "type": "m.id.user", "user": user },
+            "password": password,
+            "initial_device_display_name": "chatty",
+        });
+        if let Some(device_id) = device_id {
+            body["device_id"] = json!(device_id);
+        }
+
+        let response: LoginResponse = self
+            .call(self.request(Method::POST, &["login"])?.json(&body))
+            .await
+            .context("login failed")?;
+
+        self.access_token = Some(response.access_token.clone());
+        Ok(Session {
+            user_id: response.user_id,
+            access_token: response.access_token,
+            device_id: response.device_id,
+        })
+    }
+
+    /// Uses the token of an earlier login, checking that it is still valid
+    pub async fn resume(&mut self, session: &Session) -> anyhow::Result<()> {
+        self.access_token = Some(session.access_token.clone());
+        let response: WhoamiResponse = self
+            .call(self.request(Method::GET, &["account", "whoami"])?)
+            .await
+            .context("the saved session was rejected")?;
+
+        if response.user_id != session.user_id {
+            return Err(anyhow!(
+                "the saved session is for {}, not {}",
+                response.user_id,
+                session.user_id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Waits for new events, or fetches the recent history without `since`
+    pub async fn sync(&self, since: Option<&str>) -> anyhow::Result<SyncResponse> {
+        let mut request = self.request(Method::GET, &["sync"])?;
+        request = match since {
+            Some(since) => request.query(&[
+                ("since", since.to_string()),
+                ("timeout", SYNC_TIMEOUT.as_millis().to_string()),
+            ]),
+            None => {
+                let filter = json!({
+                    "room": { "timeline": { "limit": INITIAL_TIMELINE_LIMIT } }
+                });
+                request.query(&[("filter", filter.to_string())])
+            }
+        };
+
+        self.call(request).await
+    }
+
+    /// Sends a text message, returning the id the server gave the event
+    pub async fn send_text(
+        &self,
+        room_id: &str,
+        transaction_id: &str,
+        body: &str,
+    ) -> anyhow::Result<String> {
+        let content = json!({ "msgtype": "m.text", "body": body });
+        let path = ["rooms", room_id, "send", "m.room.message", transaction_id];
+
+        let response: SendResponse = self
+            .call(self.request(Method::PUT, &path)?.json(&content))
+            .await?;
+        Ok(response.event_id)
+    }
+
+    fn request(&self, method: Method, path: &[&str]) -> anyhow::Result<RequestBuilder> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("{} can not be a homeserver", self.homeserver))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(path);
+
+        let request = self.http.request(method, url);
+        Ok(match &self.access_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(&self, request: RequestBuilder) -> anyhow::Result<T> {
+        let response = request.send().await?;
+        if response.status().is_success() {
+            return Ok(response.json().await?);
+        }
+
+        Err(error_from(response).await)
+    }
+}
+
+async fn error_from(response: Response) -> anyhow::Error {
+    let status = response.status();
+    match response.json::<ErrorResponse>().await {
+        Ok(ErrorResponse {
+            errcode,
+            error: Some(error),
+        }) => anyhow!("{} ({})", error, errcode),
+        Ok(ErrorResponse { errcode, .. }) => anyhow!("{} ({})", status, errcode),
+        Err(_) => anyhow!("homeserver replied {}", status),
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde_json::Value;
+
+use super::api::{JoinedRoom, RoomEvent, SyncResponse};
+use crate::backends::BackendEvent;
+use crate::state::{Contact, DeliveryStatus, Message, MessageDirection};
+
+/// The joined rooms as far as the syncs so far tell, shared between the
+/// backend and the sync task
+pub struct Rooms {
+    /// Our own user id, to tell our messages apart
+    user_id: String,
+    by_id: HashMap<String, Room>,
+}
+
+pub struct Room {
+    /// Named after the room, with the room id as the phone number
+    pub contact: Contact,
+    pub messages: Vec<Message>,
+    pub last_activity: NaiveDateTime,
+    name: Option<String>,
+    canonical_alias: Option<String>,
+    heroes: Vec<String>,
+    /// Display names of the joined and invited members by user id
+    members: HashMap<String, String>,
+    joined_member_count: Option<u64>,
+    /// Whether anyone but us was typing as of the last sync
+    typing: bool,
+}
+
+impl Rooms {
+    pub fn new(user_id: String) -> Self {
+        Self {
+            user_id,
+            by_id: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, room_id: &str) -> Option<&Room> {
+        self.by_id.get(room_id)
+    }
+
+    pub fn get_mut(&mut self, room_id: &str) -> Option<&mut Room> {
+        self.by_id.get_mut(room_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Room> {
+        self.by_id.values()
+    }
+
+    pub fn find_message(&mut self, message_id: &str) -> Option<&mut Message> {
+        self.by_id
+            .values_mut()
+            .flat_map(|room| room.messages.iter_mut())
+            .find(|message| message.id == message_id)
+    }
+
+    /// Applies a sync response, returning the events to pass on. Nothing is
+    /// passed on for the first sync, which only loads the history.
+    pub fn apply_sync(&mut self, sync: SyncResponse, notify: bool) -> Vec<BackendEvent> {
+        let mut events = Vec::new();
+
+        for room_id in sync.rooms.leave.keys() {
+            self.by_id.remove(room_id);
+        }
+
+        for (room_id, joined) in sync.rooms.join {
+            let room = self
+                .by_id
+                .entry(room_id.clone())
+                .or_insert_with(|| Room::new(&room_id));
+            room.apply(joined, &self.user_id, &mut events);
+        }
+
+        if notify {
+            events
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Room {
+    fn new(room_id: &str) -> Self {
+        Self {
+            contact: Contact::new(room_id.to_string(), room_id.to_string()),
+            messages: Vec::new(),
+            last_activity: NaiveDateTime::default(),
+            name: None,
+            canonical_alias: None,
+            heroes: Vec::new(),
+            members: HashMap::new(),
+            joined_member_count: None,
+            typing: false,
+        }
+    }
+
+    fn apply(&mut self, joined: JoinedRoom, user_id: &str, events: &mut Vec<BackendEvent>) {
+        let timeline_state = joined
+            .timeline
+            .events
+            .iter()
+            .filter(|event| event.state_key.is_some());
+        for event in joined.state.events.iter().chain(timeline_state) {
+            self.apply_state(event);
+        }
+
+        if !joined.summary.heroes.is_empty() {
+            self.heroes = joined.summary.heroes;
+        }
+        if joined.summary.joined_member_count.is_some() {
+            self.joined_member_count = joined.summary.joined_member_count;
+        }
+
+        let name = self.display_name(user_id);
+        if name != self.contact.name {
+            self.contact.name = name.clone();
+            events.push(BackendEvent::ContactRenamed {
+                phone: self.contact.phone.clone(),
+                name,
+            });
+        }
+
+        for event in &joined.timeline.events {
+            if event.kind == "m.room.message" {
+                if let Some(message) = self.add_message(event, user_id) {
+                    events.push(BackendEvent::MessageReceived(message));
+                }
+            }
+        }
+
+        for event in &joined.ephemeral.events {
+            match event.kind.as_str() {
+                "m.typing" => self.apply_typing(event, user_id, events),
+                "m.receipt" => self.apply_receipt(event, user_id, events),
+                _ => {}
+            }
+        }
+    }
+
+    fn apply_state(&mut self, event: &RoomEvent) {
+        let text = |key: &str| {
+            event.content[key]
+                .as_str()
+                .filter(|text| !text.is_empty())
+                .map(String::from)
+        };
+
+        match event.kind.as_str() {
+            "m.room.name" => self.name = text("name"),
+            "m.room.canonical_alias" => self.canonical_alias = text("alias"),
+            "m.room.member" => {
+                let Some(member) = &event.state_key else {
+                    return;
+                };
+                match event.content["membership"].as_str() {
+                    Some("join") | Some("invite") => {
+                        let name = text("displayname").unwrap_or_else(|| member.clone());
+                        self.members.insert(member.clone(), name);
+                    }
+                    _ => {
+                        self.members.remove(member);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Follows the room naming rules of the spec: the name, then the alias,
+    /// then the other members
+    fn display_name(&self, user_id: &str) -> String {
+        if let Some(name) = self.name.as_ref().or(self.canonical_alias.as_ref()) {
+            return name.clone();
+        }
+
+        let mut others: Vec<&String> = if self.heroes.is_empty() {
+            self.members.keys().collect()
+        } else {
+            self.heroes.iter().collect()
+        };
+        others.retain(|member| member.as_str() != user_id);
+        others.sort();
+
+        if others.is_empty() {
+            return self.contact.phone.clone();
+        }
+        others
+            .into_iter()
+            .map(|member| self.member_name(member))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn member_name(&self, user_id: &str) -> String {
+        self.members
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
+    fn is_group(&self) -> bool {
+        self.joined_member_count
+            .map_or(self.members.len() > 2, |count| count > 2)
+    }
+
+    /// Adds a message event, returning it when it is new and not from us
+    fn add_message(&mut self, event: &RoomEvent, user_id: &str) -> Option<Message> {
+        let (Some(event_id), Some(sender)) = (&event.event_id, &event.sender) else {
+            return None;
+        };
+        let content = &event.content;
+
+        // Edits replace the text of the message they refer to
+        if content["m.relates_to"]["rel_type"] == "m.replace" {
+            let original_id = content["m.relates_to"]["event_id"].as_str()?;
+            let body = content["m.new_content"]["body"].as_str()?;
+            if let Some(original) = self.messages.iter_mut().find(|m| m.id == original_id) {
+                original.content = body.to_string();
+            }
+            return None;
+        }
+
+        // Our own messages come back with the transaction id we sent them with
+        let transaction_id = event.unsigned.transaction_id.as_deref();
+        if let Some(sent) = self
+            .messages
+            .iter_mut()
+            .find(|m| m.id == *event_id || Some(m.id.as_str()) == transaction_id)
+        {
+            sent.id = event_id.clone();
+            return None;
+        }
+
+        let body = content["body"].as_str().unwrap_or_default();
+        let text = match content["msgtype"].as_str() {
+            Some("m.emote") => format!("* {} {}", self.member_name(sender), body),
+            _ => body.to_string(),
+        };
+        let timestamp = event
+            .origin_server_ts
+            .and_then(DateTime::from_timestamp_millis)
+            .map(|time| time.with_timezone(&Local).naive_local())
+            .unwrap_or_else(|| Local::now().naive_local());
+        let direction = if sender == user_id {
+            MessageDirection::To
+        } else {
+            MessageDirection::From
+        };
+
+        let mut message = Message::new(self.contact.clone(), text, timestamp, direction)
+            .with_id(event_id.clone());
+        if let Some(url) = content["url"].as_str() {
+            message.attachments.push(url.to_string());
+        }
+        if !message.sent_by_me() && self.is_group() {
+            message = message.with_sender(Contact::new(self.member_name(sender), sender.clone()));
+        }
+
+        self.last_activity = self.last_activity.max(timestamp);
+        self.messages.push(message.clone());
+
+        (!message.sent_by_me()).then_some(message)
+    }
+
+    fn apply_typing(&mut self, event: &RoomEvent, user_id: &str, events: &mut Vec<BackendEvent>) {
+        let typing = event.content["user_ids"]
+            .as_array()
+            .is_some_and(|users| users.iter().any(|user| user != user_id));
+
+        if typing != self.typing {
+            self.typing = typing;
+            events.push(BackendEvent::Typing {
+                contact: self.contact.clone(),
+                is_typing: typing,
+            });
+        }
+    }
+
+    /// A read receipt from someone else marks our messages up to that event
+    /// as read
+    fn apply_receipt(&mut self, event: &RoomEvent, user_id: &str, events: &mut Vec<BackendEvent>) {
+        let Value::Object(receipts) = &event.content else {
+            return;
+        };
+
+        for (event_id, receipt) in receipts {
+            let read_by_others = receipt["m.read"]
+                .as_object()
+                .is_some_and(|readers| readers.keys().any(|reader| reader != user_id));
+            let Some(position) = self.messages.iter().position(|m| m.id == *event_id) else {
+                continue;
+            };
+            if !read_by_others {
+                continue;
+            }
+
+            for message in &mut self.messages[..=position] {
+                if message.sent_by_me() && message.status != DeliveryStatus::Read {
+                    message.status = DeliveryStatus::Read;
+                    events.push(BackendEvent::ReadReceipt {
+                        message_id: message.id.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use super::MatrixConfig;
+
+/// What a password login gave us. Saved so that later runs keep using the
+/// same access token and device instead of registering a new one each time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub user_id: String,
+    pub access_token: String,
+    pub device_id: String,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Option<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                event!(Level::DEBUG, "No Matrix session in {:?}: {}", path, e);
+                return None;
+            }
+        };
+
+        match serde_json::from_str(&text) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                event!(Level::WARN, "Ignoring Matrix session {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Writes the session readable only by the user, it holds the token
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("could not save the Matrix session to {:?}", path))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// One file per account on each homeserver, under `data_dir`
+pub fn session_path(data_dir: &Path, config: &MatrixConfig) -> PathBuf {
+    let host = config.homeserver.host_str().unwrap_or_default();
+    let name = match config.homeserver.port() {
+        Some(port) => format!("{}@{}_{}", config.user, host, port),
+        None => format!("{}@{}", config.user, host),
+    };
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c => c,
+        })
+        .collect();
+    data_dir.join("matrix").join(format!("{}.json", name))
+}
@@ -1,58 +1,196 @@
-use chrono::NaiveDateTime;
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use chrono::DateTime;
 use itertools::Itertools;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
-use super::MsgBackend;
-use crate::state::{Contact, Message, MessageDirection};
+use super::{BackendEvent, EventInjector, Injection, MsgBackend};
+use crate::state::{Contact, DeliveryStatus, Message, MessageDirection, Reaction};
 
 pub struct MockBackend {
-    messages: Vec<Message>,
+    // Shared with the tasks spawned for message bursts
+    messages: Arc<Mutex<Vec<Message>>>,
+    connected: bool,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
 }
 
 impl Default for MockBackend {
+    /// The same messages with the same ids every time, so that recordings
+    /// referring to them play back
     fn default() -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+
         Self {
-            messages: vec![
+            messages: Arc::new(Mutex::new(vec![
                 Message::new(
                     Contact::new(String::from("Joe Smith"), String::from("111-111-1111")),
                     String::from("hey from joe smith"),
-                    NaiveDateTime::from_timestamp(1724895116, 0),
+                    DateTime::from_timestamp(1724895116, 0).unwrap().naive_utc(),
                     MessageDirection::From,
-                ),
+                )
+                .with_id(String::from("mock-1")),
                 Message::new(
                     Contact::new(String::from("Ben Boy"), String::from("222-222-2222")),
                     String::from("hi it is benny boy"),
-                    NaiveDateTime::from_timestamp(1724895126, 0),
+                    DateTime::from_timestamp(1724895126, 0).unwrap().naive_utc(),
                     MessageDirection::From,
-                ),
+                )
+                .with_id(String::from("mock-2")),
                 Message::new(
                     Contact::new(String::from("Becky Sue"), String::from("333-333-3333")),
                     String::from("how do you do its becky sue"),
-                    NaiveDateTime::from_timestamp(1724895136, 0),
+                    DateTime::from_timestamp(1724895136, 0).unwrap().naive_utc(),
                     MessageDirection::From,
-                ),
-            ],
+                )
+                .with_id(String::from("mock-3")),
+            ])),
+            connected: true,
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+}
+
+impl MockBackend {
+    /// Applies the event to the mock's messages the way the real service would
+    fn apply(&mut self, event: &BackendEvent) -> anyhow::Result<()> {
+        let mut messages = self.messages.lock().unwrap();
+
+        match event {
+            BackendEvent::MessageReceived(message) => messages.push(message.clone()),
+            BackendEvent::DeliveryFailed { message_id, reason } => {
+                find_message(&mut messages, message_id)?.status =
+                    DeliveryStatus::Failed(reason.clone());
+            }
+            BackendEvent::DeliveryReceipt { message_id } => {
+                find_message(&mut messages, message_id)?.status = DeliveryStatus::Delivered;
+            }
+            BackendEvent::ReadReceipt { message_id } => {
+                find_message(&mut messages, message_id)?.status = DeliveryStatus::Read;
+            }
+            BackendEvent::Reaction {
+                message_id,
+                from,
+                reaction,
+            } => {
+                find_message(&mut messages, message_id)?
+                    .reactions
+                    .push(Reaction {
+                        from: from.clone(),
+                        reaction: reaction.clone(),
+                    });
+            }
+            BackendEvent::ContactRenamed { phone, name } => {
+                messages
+                    .iter_mut()
+                    .filter(|m| &m.contact.phone == phone)
+                    .for_each(|m| m.contact.name = name.clone());
+            }
+            BackendEvent::Disconnected { .. } => self.connected = false,
+            BackendEvent::Connected => self.connected = true,
+            BackendEvent::Typing { .. } => {}
         }
+
+        Ok(())
+    }
+
+    fn spawn_burst(&self, contact: Contact, count: usize, interval: std::time::Duration) {
+        let messages = self.messages.clone();
+        let event_tx = self.event_tx.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            for i in 1..=count {
+                ticker.tick().await;
+
+                let message = Message::new(
+                    contact.clone(),
+                    format!("burst message {}/{}", i, count),
+                    chrono::offset::Local::now().naive_local(),
+                    MessageDirection::From,
+                );
+                messages.lock().unwrap().push(message.clone());
+
+                if event_tx
+                    .send(BackendEvent::MessageReceived(message))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
     }
 }
 
+fn find_message<'a>(
+    messages: &'a mut [Message],
+    message_id: &str,
+) -> anyhow::Result<&'a mut Message> {
+    messages
+        .iter_mut()
+        .find(|m| m.id == message_id)
+        .ok_or_else(|| anyhow!("no message with id {}", message_id))
+}
+
 impl MsgBackend for MockBackend {
-    fn send_message(&mut self, message: Message) {
-        self.messages.push(message);
+    fn send_message(&mut self, mut message: Message) {
+        if !self.connected {
+            let reason = String::from("mock backend is disconnected");
+            message.status = DeliveryStatus::Failed(reason.clone());
+            let _ = self.event_tx.send(BackendEvent::DeliveryFailed {
+                message_id: message.id.clone(),
+                reason,
+            });
+        }
+
+        self.messages.lock().unwrap().push(message);
     }
 
     fn get_messages(&self, contact: &Contact, _n: Option<u8>) -> Vec<Message> {
         self.messages
+            .lock()
+            .unwrap()
             .iter()
-            .filter(|x| contact.eq(&x.contact))
+            .filter(|x| contact.phone == x.contact.phone)
             .cloned()
             .collect()
     }
 
     fn get_recent_contacts(&self) -> Vec<Contact> {
         self.messages
+            .lock()
+            .unwrap()
             .iter()
-            .unique_by(|x| &x.contact)
+            .unique_by(|x| &x.contact.phone)
             .map(|x| x.contact.clone())
             .collect()
     }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        self.event_rx.take()
+    }
+
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        Some(self)
+    }
+}
+
+impl EventInjector for MockBackend {
+    fn inject(&mut self, injection: Injection) -> anyhow::Result<()> {
+        match injection {
+            Injection::Event(event) => {
+                self.apply(&event)?;
+                self.event_tx.send(event)?;
+            }
+            Injection::Burst {
+                contact,
+                count,
+                interval,
+            } => self.spawn_burst(contact, count, interval),
+        }
+
+        Ok(())
+    }
 }
@@ -1,12 +1,256 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use anyhow::bail;
+use tokio::{sync::mpsc::UnboundedReceiver, task::JoinHandle};
+use tracing::Level;
+
 use crate::state::{Contact, Message};
 
+mod event;
+mod irc;
 mod mac;
+mod matrix;
 mod mock;
+mod modem;
+mod multi;
+mod name_resolver;
+mod plugin;
+mod rpc;
+mod send_command;
+mod signal;
+mod sms_backup;
 
+pub use event::{BackendEvent, EventInjector, Injection};
+pub use irc::{IrcBackend, IrcConfig};
+pub use mac::MacBackend;
+pub use matrix::{MatrixBackend, MatrixConfig};
 pub use mock::MockBackend;
+pub use modem::{ModemBackend, ModemConfig};
+pub use multi::MultiBackend;
+pub use name_resolver::NameResolver;
+pub use plugin::{PluginBackend, PluginConfig};
+pub use send_command::{CommandSender, SendCommand};
+pub use signal::{SignalBackend, SignalConfig};
+pub use sms_backup::SmsBackupBackend;
+
+/// A backend selected on the command line, along with whatever it needs to
+/// connect, written as `kind[:argument]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendSpec {
+    Mock,
+    Mac,
+    /// An XML file written by the Android app SMS Backup & Restore
+    SmsBackup(PathBuf),
+    Irc(IrcConfig),
+    Matrix(MatrixConfig),
+    Signal(SignalConfig),
+    /// A GSM or LTE modem on a serial port, driven with AT commands
+    Modem(ModemConfig),
+    /// An external program speaking the plugin protocol
+    Plugin(PluginConfig),
+}
+
+impl FromStr for BackendSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("irc://") || s.starts_with("ircs://") {
+            return s.parse().map(BackendSpec::Irc);
+        }
+        if s.starts_with("matrix://") || s.starts_with("matrix+http://") {
+            return s.parse().map(BackendSpec::Matrix);
+        }
+        if s.starts_with("signal:") {
+            return s.parse().map(BackendSpec::Signal);
+        }
+        if s.starts_with("modem:") {
+            return s.parse().map(BackendSpec::Modem);
+        }
+        if s.starts_with("plugin:") {
+            return s.parse().map(BackendSpec::Plugin);
+        }
+
+        let (kind, argument) = match s.split_once(':') {
+            Some((kind, argument)) => (kind, Some(argument)),
+            None => (s, None),
+        };
+
+        match (kind, argument) {
+            ("mock", None) => Ok(BackendSpec::Mock),
+            ("mac", None) => Ok(BackendSpec::Mac),
+            ("sms-backup", Some(path)) if !path.is_empty() => {
+                Ok(BackendSpec::SmsBackup(PathBuf::from(path)))
+            }
+            ("sms-backup", _) => Err(String::from("expected sms-backup:<file>")),
+            _ => Err(format!(
+                "unknown backend '{s}', expected one of: mock, mac, sms-backup:<file>, irc[s]://nick@host[/#channel], matrix://user@host, signal:<number>[@<socket>], modem:<device>[@<baud>], plugin:<command>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BackendSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendSpec::Mock => write!(f, "mock"),
+            BackendSpec::Mac => write!(f, "mac"),
+            BackendSpec::SmsBackup(path) => write!(f, "sms-backup:{}", path.display()),
+            BackendSpec::Irc(config) => write!(f, "{}", config),
+            BackendSpec::Matrix(config) => write!(f, "{}", config),
+            BackendSpec::Signal(config) => write!(f, "{}", config),
+            BackendSpec::Modem(config) => write!(f, "{}", config),
+            BackendSpec::Plugin(config) => write!(f, "{}", config),
+        }
+    }
+}
+
+impl BackendSpec {
+    /// The kind of backend, as written before the colon
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BackendSpec::Mock => "mock",
+            BackendSpec::Mac => "mac",
+            BackendSpec::SmsBackup(_) => "sms-backup",
+            BackendSpec::Irc(_) => "irc",
+            BackendSpec::Matrix(_) => "matrix",
+            BackendSpec::Signal(_) => "signal",
+            BackendSpec::Modem(_) => "modem",
+            BackendSpec::Plugin(_) => "plugin",
+        }
+    }
+}
+
+/// Starts the backends given on the command line, several of them behind one
+/// unified inbox
+pub async fn create_backends(specs: &[BackendSpec]) -> anyhow::Result<Box<dyn MsgBackend>> {
+    match specs {
+        [spec] => create_backend(spec).await,
+        specs => Ok(Box::new(MultiBackend::connect(specs).await?)),
+    }
+}
+
+pub async fn create_backend(spec: &BackendSpec) -> anyhow::Result<Box<dyn MsgBackend>> {
+    Ok(match spec {
+        BackendSpec::Mock => Box::new(MockBackend::default()),
+        BackendSpec::Mac => Box::new(MacBackend::default()),
+        BackendSpec::SmsBackup(path) => Box::new(SmsBackupBackend::load(path)?),
+        BackendSpec::Irc(config) => Box::new(IrcBackend::connect(config.clone())),
+        BackendSpec::Matrix(config) => Box::new(MatrixBackend::connect(config.clone()).await?),
+        BackendSpec::Signal(config) => Box::new(SignalBackend::connect(config.clone()).await?),
+        BackendSpec::Modem(config) => Box::new(ModemBackend::connect(config.clone()).await?),
+        BackendSpec::Plugin(config) => Box::new(PluginBackend::connect(config.clone()).await?),
+    })
+}
 
 pub trait MsgBackend {
     fn send_message(&mut self, message: Message);
     fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message>;
     fn get_recent_contacts(&self) -> Vec<Contact>;
+
+    /// Takes the stream of events the backend pushes as they happen. Only the
+    /// first call returns the stream, backends without events return `None`.
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        None
+    }
+
+    /// The test-injection interface, for backends that support simulating
+    /// events
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        None
+    }
+
+    /// Starts a conversation by name, for services where conversations can be
+    /// joined such as chat rooms
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        bail!("backend can not join '{}'", name)
+    }
+
+    /// The contact to message someone with no conversation yet as, joining
+    /// where that is how conversations start. Backends that can not join send
+    /// to the handle as it is.
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        match self.join_conversation(&contact.phone) {
+            Ok(joined) => Ok(joined),
+            Err(e) => {
+                tracing::event!(
+                    Level::DEBUG,
+                    "Starting {} without joining: {}",
+                    contact.phone,
+                    e
+                );
+                Ok(contact)
+            }
+        }
+    }
+
+    /// Whether `name` is written the way this backend's conversations are,
+    /// such as a phone number or a channel. Decides which account joins it
+    /// when there are several.
+    fn recognizes(&self, name: &str) -> bool {
+        let _ = name;
+        false
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        let _ = (message, reaction);
+        bail!("backend does not support reactions")
+    }
+
+    /// People to complete recipients from besides the conversations, with
+    /// their names
+    fn address_book(&self) -> Vec<Contact> {
+        Vec::new()
+    }
+
+    /// Asks the backend to finish sending and disconnect. Backends doing work in
+    /// the background return the task to wait on.
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        None
+    }
+}
+
+impl<B: MsgBackend + ?Sized> MsgBackend for Box<B> {
+    fn send_message(&mut self, message: Message) {
+        (**self).send_message(message)
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        (**self).get_messages(contact, n)
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        (**self).get_recent_contacts()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        (**self).take_events()
+    }
+
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        (**self).injector()
+    }
+
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        (**self).join_conversation(name)
+    }
+
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        (**self).start_conversation(contact)
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        (**self).recognizes(name)
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        (**self).send_reaction(message, reaction)
+    }
+
+    fn address_book(&self) -> Vec<Contact> {
+        (**self).address_book()
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        (**self).close()
+    }
 }
@@ -0,0 +1,141 @@
+use std::{collections::VecDeque, fs::File, io::Write, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use tokio::{sync::mpsc::UnboundedReceiver, time::Instant};
+use tracing::{event, Level};
+
+use super::serial::PROMPT;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sending waits on the network, which takes a while on a weak signal
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Ends the PDU after the `AT+CMGS` prompt
+const CTRL_Z: u8 = 0x1a;
+
+/// Lines the modem prints on its own, which can turn up between a command
+/// and its result
+const UNSOLICITED: [&str; 5] = ["+CMTI:", "+CDSI:", "+CMT:", "RING", "^"];
+
+/// Talks AT commands to a modem, one command at a time
+pub struct Modem {
+    writer: File,
+    lines: UnboundedReceiver<String>,
+    /// Unsolicited lines that arrived while a command was running
+    unsolicited: VecDeque<String>,
+}
+
+impl Modem {
+    pub fn new(writer: File, lines: UnboundedReceiver<String>) -> Self {
+        Self {
+            writer,
+            lines,
+            unsolicited: VecDeque::new(),
+        }
+    }
+
+    /// Runs a command and returns the lines it printed before `OK`. An
+    /// `ERROR` result is returned as the error.
+    pub async fn command(&mut self, command: &str) -> anyhow::Result<Vec<String>> {
+        self.write(format!("{}\r", command).as_bytes())?;
+        self.response(command, COMMAND_TIMEOUT).await
+    }
+
+    /// Sends a PDU with `AT+CMGS`, returning the message reference the
+    /// network gave it
+    pub async fn send_pdu(&mut self, length: usize, hex: &str) -> anyhow::Result<String> {
+        let command = format!("AT+CMGS={}", length);
+        self.write(format!("{}\r", command).as_bytes())?;
+
+        let deadline = Instant::now() + COMMAND_TIMEOUT;
+        loop {
+            let line = self.next_line(deadline, &command).await?;
+            if line == PROMPT {
+                break;
+            }
+            if is_error(&line) {
+                bail!("{}", line);
+            }
+            if is_unsolicited(&line) {
+                self.unsolicited(line, &command);
+            }
+        }
+
+        let mut pdu = hex.as_bytes().to_vec();
+        pdu.push(CTRL_Z);
+        self.write(&pdu)?;
+
+        let lines = self.response(&command, SEND_TIMEOUT).await?;
+        Ok(lines
+            .iter()
+            .find_map(|line| line.strip_prefix("+CMGS:"))
+            .map(|reference| reference.trim().to_string())
+            .unwrap_or_default())
+    }
+
+    /// The next line the modem printed on its own, or `None` once it is gone
+    pub async fn next_unsolicited(&mut self) -> Option<String> {
+        match self.unsolicited.pop_front() {
+            Some(line) => Some(line),
+            None => self.lines.recv().await,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        event!(
+            Level::TRACE,
+            ">> {}",
+            String::from_utf8_lossy(bytes).trim_end()
+        );
+        self.writer
+            .write_all(bytes)
+            .and_then(|_| self.writer.flush())
+            .context("could not write to the modem")
+    }
+
+    async fn response(&mut self, command: &str, timeout: Duration) -> anyhow::Result<Vec<String>> {
+        let deadline = Instant::now() + timeout;
+        let mut response = Vec::new();
+
+        loop {
+            let line = self.next_line(deadline, command).await?;
+            if line == "OK" {
+                return Ok(response);
+            }
+            if is_error(&line) {
+                bail!("{}", line);
+            }
+            // Echo, in case the modem ignored ATE0
+            if line == command {
+                continue;
+            }
+            if is_unsolicited(&line) {
+                self.unsolicited(line, command);
+            } else {
+                response.push(line);
+            }
+        }
+    }
+
+    async fn next_line(&mut self, deadline: Instant, command: &str) -> anyhow::Result<String> {
+        let line = tokio::time::timeout_at(deadline, self.lines.recv())
+            .await
+            .map_err(|_| anyhow!("modem did not answer {} in time", command))?
+            .ok_or_else(|| anyhow!("modem went away"))?;
+        event!(Level::TRACE, "<< {}", line);
+        Ok(line)
+    }
+
+    fn unsolicited(&mut self, line: String, command: &str) {
+        event!(Level::TRACE, "Keeping {} for after {}", line, command);
+        self.unsolicited.push_back(line);
+    }
+}
+
+fn is_error(line: &str) -> bool {
+    line == "ERROR" || line.starts_with("+CMS ERROR") || line.starts_with("+CME ERROR")
+}
+
+fn is_unsolicited(line: &str) -> bool {
+    UNSOLICITED.iter().any(|prefix| line.starts_with(prefix))
+}
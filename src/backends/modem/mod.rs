@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use chrono::NaiveDateTime;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::{BackendEvent, MsgBackend};
+use crate::state::{normalize_handle, Contact, DeliveryStatus, Message};
+
+mod at;
+mod pdu;
+mod serial;
+mod session;
+
+use at::Modem;
+use session::{Outgoing, Session};
+
+const DEFAULT_BAUD: u32 = 115200;
+
+/// The serial device of a GSM or LTE modem, parsed from
+/// `modem:<device>[@<baud>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModemConfig {
+    pub device: PathBuf,
+    pub baud: u32,
+}
+
+impl FromStr for ModemConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("modem:") else {
+            return Err(format!("'{s}' does not start with modem:"));
+        };
+
+        let (device, baud) = match rest.rsplit_once('@') {
+            Some((device, baud)) => (
+                device,
+                baud.parse()
+                    .map_err(|_| format!("invalid baud rate '{baud}'"))?,
+            ),
+            None => (rest, DEFAULT_BAUD),
+        };
+        if device.is_empty() {
+            return Err(String::from("expected modem:<device>[@<baud>]"));
+        }
+
+        Ok(Self {
+            device: PathBuf::from(device),
+            baud,
+        })
+    }
+}
+
+impl fmt::Display for ModemConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "modem:{}", self.device.display())?;
+        if self.baud != DEFAULT_BAUD {
+            write!(f, "@{}", self.baud)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `number` is a phone number, local or international
+fn is_number(number: &str) -> bool {
+    let digits = number.strip_prefix('+').unwrap_or(number);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The messages stored on the modem and those sent since starting, by
+/// normalized phone number. Shared between the backend and the session task.
+#[derive(Default)]
+struct Conversations {
+    by_number: HashMap<String, Conversation>,
+}
+
+struct Conversation {
+    contact: Contact,
+    messages: Vec<Message>,
+    last_activity: NaiveDateTime,
+}
+
+impl Conversations {
+    fn open(&mut self, contact: &Contact) -> &mut Conversation {
+        self.by_number
+            .entry(normalize_handle(&contact.phone))
+            .or_insert_with(|| Conversation {
+                contact: contact.clone(),
+                messages: Vec::new(),
+                last_activity: NaiveDateTime::default(),
+            })
+    }
+
+    fn push(&mut self, message: Message) {
+        let conversation = self.open(&message.contact);
+        conversation.last_activity = conversation.last_activity.max(message.timestamp);
+        conversation.messages.push(message);
+    }
+
+    fn find_message(&mut self, message_id: &str) -> Option<&mut Message> {
+        self.by_number
+            .values_mut()
+            .flat_map(|conversation| conversation.messages.iter_mut())
+            .find(|message| message.id == message_id)
+    }
+}
+
+/// SMS through a modem on a serial port, in PDU mode so that any text can be
+/// sent and long messages are split and joined again
+pub struct ModemBackend {
+    conversations: Arc<Mutex<Conversations>>,
+    outgoing: UnboundedSender<Outgoing>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ModemBackend {
+    /// Sets up the modem and loads the messages stored on it
+    pub async fn connect(config: ModemConfig) -> anyhow::Result<Self> {
+        let device = serial::open(&config.device, config.baud)?;
+        let reader = device
+            .try_clone()
+            .context("could not read from the modem")?;
+        let modem = Modem::new(device, serial::read_lines(reader));
+
+        let conversations = Arc::new(Mutex::new(Conversations::default()));
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let mut session = Session {
+            modem,
+            outgoing: outgoing_rx,
+            event_tx,
+            conversations: conversations.clone(),
+            reference: 0,
+            parts: HashMap::new(),
+        };
+        session
+            .init()
+            .await
+            .with_context(|| format!("could not set up {}", config))?;
+        session
+            .load()
+            .await
+            .context("could not list the stored messages")?;
+        event!(Level::INFO, "Connected to {}", config);
+
+        Ok(Self {
+            conversations,
+            outgoing: outgoing_tx,
+            event_rx: Some(event_rx),
+            task: Some(tokio::spawn(session.run())),
+        })
+    }
+}
+
+impl MsgBackend for ModemBackend {
+    fn send_message(&mut self, mut message: Message) {
+        let outgoing = Outgoing::Send {
+            number: message.contact.phone.clone(),
+            message_id: message.id.clone(),
+            body: message.content.clone(),
+        };
+        if self.outgoing.send(outgoing).is_err() {
+            message.status = DeliveryStatus::Failed(String::from("modem went away"));
+        }
+
+        self.conversations.lock().unwrap().push(message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let conversations = self.conversations.lock().unwrap();
+        let Some(conversation) = conversations
+            .by_number
+            .get(&normalize_handle(&contact.phone))
+        else {
+            return Vec::new();
+        };
+
+        let messages = &conversation.messages;
+        let skip = n.map_or(0, |n| messages.len().saturating_sub(n as usize));
+        messages[skip..].to_vec()
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut recent: Vec<&Conversation> = conversations.by_number.values().collect();
+        recent.sort_by_key(|conversation| std::cmp::Reverse(conversation.last_activity));
+        recent
+            .into_iter()
+            .map(|conversation| conversation.contact.clone())
+            .collect()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Starts a conversation with a phone number nothing was stored for
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        let number = name.trim();
+        if !is_number(number) {
+            bail!("'{}' is not a phone number", number);
+        }
+
+        let contact = Contact::new(number.to_string(), number.to_string());
+        Ok(self
+            .conversations
+            .lock()
+            .unwrap()
+            .open(&contact)
+            .contact
+            .clone())
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        is_number(name.trim())
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        let _ = self.outgoing.send(Outgoing::Quit);
+        self.task.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        fs::File,
+        io::{Read, Write},
+        os::fd::FromRawFd,
+        thread,
+        time::Duration,
+    };
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::state::MessageDirection;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// 2024-03-09 08:30:00 at UTC
+    const TIMESTAMP: [u8; 7] = [0x42, 0x30, 0x90, 0x80, 0x03, 0x00, 0x00];
+
+    /// Text the simulated modem hangs up on instead of sending
+    const HANG_UP: &str = "hang up";
+
+    /// Both ends of a pseudo terminal. The backend opens the slave by its
+    /// path like a serial port, the test plays the modem on the master.
+    fn pty() -> (File, File, PathBuf) {
+        let (mut master, mut slave) = (0, 0);
+        // openpty only writes the two descriptors, ttyname returns a static
+        // buffer that is copied before anything else could reuse it
+        unsafe {
+            assert_eq!(
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null(),
+                    std::ptr::null(),
+                ),
+                0
+            );
+            let path = CStr::from_ptr(libc::ttyname(slave)).to_str().unwrap();
+            (
+                File::from_raw_fd(master),
+                File::from_raw_fd(slave),
+                PathBuf::from(path),
+            )
+        }
+    }
+
+    /// What the simulated modem was asked to do, for the test to check
+    #[derive(Debug, PartialEq)]
+    enum Request {
+        Command(String),
+        Send { length: usize, hex: String },
+    }
+
+    /// The octets `AT+CMGS` and the listings count, which leave out the
+    /// service centre
+    fn tpdu_length(hex: &str) -> usize {
+        let smsc_length = usize::from_str_radix(&hex[..2], 16).unwrap();
+        hex.len() / 2 - 1 - smsc_length
+    }
+
+    /// A message in the modem's storage
+    type Stored = (usize, u8, String);
+
+    /// Answers AT commands on the master end like a modem holding `stored`
+    /// and whatever `arriving` is announced later. Returns once the backend
+    /// is gone or it is asked to send `HANG_UP`.
+    fn simulate_modem(
+        mut master: File,
+        stored: Vec<Stored>,
+        arriving: Vec<Stored>,
+        request_tx: UnboundedSender<Request>,
+    ) {
+        let mut buffer = [0; 256];
+        let mut input = Vec::new();
+        let mut sending = None;
+
+        loop {
+            let read = match master.read(&mut buffer) {
+                Ok(0) | Err(_) => return,
+                Ok(read) => read,
+            };
+            input.extend(&buffer[..read]);
+
+            loop {
+                let end = if sending.is_some() { 0x1a } else { b'\r' };
+                let Some(position) = input.iter().position(|&byte| byte == end) else {
+                    break;
+                };
+                let line = String::from_utf8(input.drain(..=position).collect()).unwrap();
+                let line = line[..line.len() - 1].to_string();
+
+                let reply = if let Some(length) = sending.take() {
+                    let text = pdu::decode(&line).unwrap().text;
+                    let _ = request_tx.send(Request::Send { length, hex: line });
+                    match text.as_str() {
+                        HANG_UP => return,
+                        "" => String::from("\r\n+CMS ERROR: 500\r\n"),
+                        _ => String::from("\r\n+CMGS: 7\r\n\r\nOK\r\n"),
+                    }
+                } else {
+                    let _ = request_tx.send(Request::Command(line.clone()));
+                    reply(&line, &stored, &arriving, &mut sending)
+                };
+                master.write_all(reply.as_bytes()).unwrap();
+            }
+        }
+    }
+
+    fn reply(
+        command: &str,
+        stored: &[Stored],
+        arriving: &[Stored],
+        sending: &mut Option<usize>,
+    ) -> String {
+        let listed = |(index, stat, hex): &Stored| {
+            format!(
+                "+CMGL: {},{},,{}\r\n{}\r\n",
+                index,
+                stat,
+                tpdu_length(hex),
+                hex
+            )
+        };
+
+        match command {
+            "AT" | "ATE0" | "AT+CMGF=0" | "AT+CNMI=2,1,0,0,0" => String::from("\r\nOK\r\n"),
+            "AT+CMGL=4" => {
+                let list: String = stored.iter().map(listed).collect();
+                format!("\r\n{}\r\nOK\r\n", list)
+            }
+            _ => {
+                if let Some(index) = command.strip_prefix("AT+CMGR=") {
+                    let index: usize = index.parse().unwrap();
+                    return match stored
+                        .iter()
+                        .chain(arriving)
+                        .find(|(stored, ..)| *stored == index)
+                    {
+                        Some((_, stat, hex)) => format!(
+                            "\r\n+CMGR: {},,{}\r\n{}\r\n\r\nOK\r\n",
+                            stat,
+                            tpdu_length(hex),
+                            hex
+                        ),
+                        None => String::from("\r\n+CMS ERROR: 321\r\n"),
+                    };
+                }
+                if let Some(length) = command.strip_prefix("AT+CMGS=") {
+                    *sending = Some(length.parse().unwrap());
+                    return String::from("\r\n> ");
+                }
+                String::from("\r\nERROR\r\n")
+            }
+        }
+    }
+
+    async fn next_event(event_rx: &mut UnboundedReceiver<BackendEvent>) -> BackendEvent {
+        timeout(WAIT, event_rx.recv()).await.unwrap().unwrap()
+    }
+
+    async fn next_request(request_rx: &mut UnboundedReceiver<Request>) -> Request {
+        timeout(WAIT, request_rx.recv()).await.unwrap().unwrap()
+    }
+
+    /// Waits for the next PDU the modem was asked to send
+    async fn next_sent(request_rx: &mut UnboundedReceiver<Request>) -> pdu::Pdu {
+        loop {
+            if let Request::Send { length, hex } = next_request(request_rx).await {
+                assert_eq!(length, tpdu_length(&hex));
+                return pdu::decode(&hex).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn parses_config() {
+        let config: ModemConfig = "modem:/dev/ttyUSB2@9600".parse().unwrap();
+        assert_eq!(
+            config,
+            ModemConfig {
+                device: PathBuf::from("/dev/ttyUSB2"),
+                baud: 9600,
+            }
+        );
+        assert_eq!(config.to_string(), "modem:/dev/ttyUSB2@9600");
+
+        let config: ModemConfig = "modem:/dev/ttyACM0".parse().unwrap();
+        assert_eq!(config.baud, DEFAULT_BAUD);
+        assert_eq!(config.to_string(), "modem:/dev/ttyACM0");
+
+        assert!("modem:".parse::<ModemConfig>().is_err());
+        assert!("modem:/dev/ttyACM0@fast".parse::<ModemConfig>().is_err());
+        assert!("sms:/dev/ttyACM0".parse::<ModemConfig>().is_err());
+    }
+
+    #[tokio::test]
+    async fn talks_to_a_modem() {
+        let (master, _slave, path) = pty();
+        let mut unsolicited = master.try_clone().unwrap();
+
+        let bob = "+15551234567";
+        let long_text = "long ".repeat(40);
+        let long_parts = pdu::encode_deliver(bob, &long_text, 3, TIMESTAMP);
+        let stored = vec![
+            // From the usual test vector, received in 2002
+            (
+                1,
+                1,
+                String::from(
+                    "07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07",
+                ),
+            ),
+            // Written on the modem and never sent, with a validity period
+            (
+                2,
+                session::STAT_UNSENT,
+                String::from("0011000B916407281553F80000AA0AE8329BFD4697D9EC37"),
+            ),
+            // A long message listed last part first
+            (4, 1, long_parts[1].clone()),
+            (3, 1, long_parts[0].clone()),
+        ];
+        let arriving = vec![(
+            9,
+            0,
+            pdu::encode_deliver(bob, "new 🙂", 0, TIMESTAMP).remove(0),
+        )];
+
+        let (request_tx, mut request_rx) = mpsc::unbounded_channel();
+        let modem = thread::spawn(move || simulate_modem(master, stored, arriving, request_tx));
+
+        let config: ModemConfig = format!("modem:{}", path.display()).parse().unwrap();
+        let mut backend = ModemBackend::connect(config).await.unwrap();
+        let mut event_rx = backend.take_events().unwrap();
+
+        for command in ["AT", "ATE0", "AT+CMGF=0", "AT+CNMI=2,1,0,0,0", "AT+CMGL=4"] {
+            assert_eq!(
+                next_request(&mut request_rx).await,
+                Request::Command(String::from(command))
+            );
+        }
+
+        // Everything stored was loaded, the long message joined up
+        let contacts = backend.get_recent_contacts();
+        assert_eq!(contacts[0].phone, bob);
+        assert_eq!(contacts.len(), 3);
+
+        let bob_contact = contacts[0].clone();
+        let messages = backend.get_messages(&bob_contact, None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].id, "modem-3");
+        assert_eq!(messages[0].content, long_text);
+        assert_eq!(messages[0].direction, MessageDirection::From);
+
+        let unsent = contacts
+            .iter()
+            .find(|contact| contact.phone == "+46708251358")
+            .unwrap();
+        let messages = backend.get_messages(unsent, None);
+        assert_eq!(messages[0].content, "hellohello");
+        assert_eq!(messages[0].direction, MessageDirection::To);
+        assert!(matches!(messages[0].status, DeliveryStatus::Failed(_)));
+
+        // A new message is announced and read
+        unsolicited.write_all(b"\r\n+CMTI: \"SM\",9\r\n").unwrap();
+        match next_event(&mut event_rx).await {
+            BackendEvent::MessageReceived(message) => {
+                assert_eq!(message.id, "modem-9");
+                assert_eq!(message.content, "new 🙂");
+                assert_eq!(message.contact.phone, bob);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(
+            next_request(&mut request_rx).await,
+            Request::Command(String::from("AT+CMGR=9"))
+        );
+        assert_eq!(backend.get_messages(&bob_contact, None).len(), 2);
+
+        // Long messages go out in parts that share a reference
+        let text = "reply ".repeat(30);
+        backend.send_message(Message::new(
+            bob_contact.clone(),
+            text.clone(),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        ));
+        let first = next_sent(&mut request_rx).await;
+        let second = next_sent(&mut request_rx).await;
+        assert_eq!(first.address, bob);
+        assert_eq!(first.kind, pdu::PduKind::Submit);
+        assert_eq!(first.concat.map(|concat| concat.part), Some(1));
+        assert_eq!(second.concat.map(|concat| concat.part), Some(2));
+        assert_eq!(
+            first.concat.map(|concat| concat.reference),
+            second.concat.map(|concat| concat.reference)
+        );
+        assert_eq!(format!("{}{}", first.text, second.text), text);
+
+        // The network refusing a message fails it
+        let refused = Message::new(
+            bob_contact.clone(),
+            String::new(),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        );
+        let refused_id = refused.id.clone();
+        backend.send_message(refused);
+        assert_eq!(next_sent(&mut request_rx).await.text, "");
+        match next_event(&mut event_rx).await {
+            BackendEvent::DeliveryFailed { message_id, reason } => {
+                assert_eq!(message_id, refused_id);
+                assert_eq!(reason, "+CMS ERROR: 500");
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        // Unplugging the modem fails what was being sent and disconnects
+        drop(unsolicited);
+        let hung_up = Message::new(
+            bob_contact.clone(),
+            String::from(HANG_UP),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        );
+        let hung_up_id = hung_up.id.clone();
+        backend.send_message(hung_up);
+        assert_eq!(next_sent(&mut request_rx).await.text, HANG_UP);
+        modem.join().unwrap();
+
+        let mut failed = false;
+        let mut disconnected = false;
+        while !(failed && disconnected) {
+            match next_event(&mut event_rx).await {
+                BackendEvent::DeliveryFailed { message_id, .. } => {
+                    assert_eq!(message_id, hung_up_id);
+                    failed = true;
+                }
+                BackendEvent::Disconnected { .. } => disconnected = true,
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+
+        timeout(WAIT, backend.close().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,778 @@
+//! SMS PDUs as modems read and write them in PDU mode, see 3GPP TS 23.040
+//! for the layout and TS 23.038 for the alphabets
+
+use anyhow::{anyhow, bail, ensure, Context};
+use chrono::{FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+/// The GSM 7-bit default alphabet, 0x1B escapes to the extension table
+const GSM_ALPHABET: &str =
+    "@£$¥èéùìòÇ\nØø\rÅåΔ_ΦΓΛΩΠΨΣΘΞ\u{1b}ÆæßÉ !\"#¤%&'()*+,-./0123456789:;<=>?\
+¡ABCDEFGHIJKLMNOPQRSTUVWXYZÄÖÑÜ§¿abcdefghijklmnopqrstuvwxyzäöñüà";
+
+const GSM_ESCAPE: u8 = 0x1b;
+
+/// Characters reached through the escape, as (septet, character)
+const GSM_EXTENSION: [(u8, char); 10] = [
+    (0x0a, '\u{c}'),
+    (0x14, '^'),
+    (0x28, '{'),
+    (0x29, '}'),
+    (0x2f, '\\'),
+    (0x3c, '['),
+    (0x3d, '~'),
+    (0x3e, ']'),
+    (0x40, '|'),
+    (0x65, '€'),
+];
+
+/// Septets in a single GSM-7 message, and in each part of a longer one once
+/// the concatenation header takes its share
+const GSM_SINGLE: usize = 160;
+const GSM_PART: usize = 153;
+/// UTF-16 code units, likewise
+const UCS2_SINGLE: usize = 70;
+const UCS2_PART: usize = 67;
+
+const DCS_GSM: u8 = 0x00;
+const DCS_UCS2: u8 = 0x08;
+
+/// Type of number for international numbers, written with a leading '+'
+const TOA_INTERNATIONAL: u8 = 0x91;
+const TOA_UNKNOWN: u8 = 0x81;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PduKind {
+    /// A message the modem received
+    Deliver,
+    /// A message stored on the modem to be sent or already sent
+    Submit,
+}
+
+/// One part of a message split across several SMS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Concat {
+    pub reference: u16,
+    pub total: u8,
+    pub part: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pdu {
+    pub kind: PduKind,
+    /// The sender of a received message, the recipient of a sent one
+    pub address: String,
+    /// When the service centre got the message, only known for received ones
+    pub timestamp: Option<NaiveDateTime>,
+    pub text: String,
+    pub concat: Option<Concat>,
+}
+
+/// A PDU ready for `AT+CMGS`, which wants the length without the service
+/// centre part
+#[derive(Debug, Clone)]
+pub struct SubmitPdu {
+    pub hex: String,
+    pub length: usize,
+}
+
+enum Alphabet {
+    Gsm,
+    EightBit,
+    Ucs2,
+}
+
+/// Decodes a PDU as listed by `AT+CMGL` or read by `AT+CMGR`, which starts
+/// with the service centre address
+pub fn decode(hex: &str) -> anyhow::Result<Pdu> {
+    let bytes = from_hex(hex.trim())?;
+    let mut reader = Reader {
+        bytes: &bytes,
+        position: 0,
+    };
+
+    let smsc_length = reader.byte()? as usize;
+    reader.skip(smsc_length)?;
+
+    let first = reader.byte()?;
+    let has_header = first & 0x40 != 0;
+    let kind = match first & 0x03 {
+        0x00 => PduKind::Deliver,
+        0x01 => PduKind::Submit,
+        other => bail!("unsupported message type {}", other),
+    };
+
+    if kind == PduKind::Submit {
+        // Message reference
+        reader.byte()?;
+    }
+    let address = reader.address()?;
+    let _protocol = reader.byte()?;
+    let alphabet = alphabet(reader.byte()?);
+
+    let timestamp = match kind {
+        PduKind::Deliver => reader.timestamp()?,
+        PduKind::Submit => {
+            // Validity period, its size depends on the format in the first octet
+            match (first >> 3) & 0x03 {
+                0x00 => {}
+                0x02 => reader.skip(1)?,
+                _ => reader.skip(7)?,
+            }
+            None
+        }
+    };
+
+    let data_length = reader.byte()? as usize;
+    let data = reader.rest();
+    let (text, concat) = user_data(data, data_length, &alphabet, has_header)?;
+
+    Ok(Pdu {
+        kind,
+        address,
+        timestamp,
+        text,
+        concat,
+    })
+}
+
+/// Encodes the text as one SMS-SUBMIT PDU per part. Parts of a long message
+/// share the reference, which should differ between messages.
+pub fn encode_submit(number: &str, text: &str, reference: u8) -> anyhow::Result<Vec<SubmitPdu>> {
+    let address = encode_address(number)?;
+
+    let (dcs, parts): (u8, Vec<(Vec<u8>, usize)>) = match gsm_septets(text) {
+        Some(septets) => (DCS_GSM, split_septets(&septets)),
+        None => (DCS_UCS2, split_ucs2(text)),
+    };
+    ensure!(parts.len() <= u8::MAX as usize, "message is too long");
+
+    let total = parts.len() as u8;
+    let pdus = parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, (payload, payload_length))| {
+            let header = (total > 1).then(|| [0x05, 0x00, 0x03, reference, total, index as u8 + 1]);
+
+            let mut tpdu = vec![if header.is_some() { 0x41 } else { 0x01 }, 0x00];
+            tpdu.extend(&address);
+            tpdu.extend([0x00, dcs]);
+
+            match (dcs, header) {
+                (DCS_GSM, Some(header)) => {
+                    // The text starts on a septet boundary after the header
+                    let header_septets = (header.len() * 8).div_ceil(7);
+                    let fill_bits = header_septets * 7 - header.len() * 8;
+                    tpdu.push((header_septets + payload_length) as u8);
+                    tpdu.extend(header);
+                    tpdu.extend(pack_septets(&payload, fill_bits));
+                }
+                (DCS_GSM, None) => {
+                    tpdu.push(payload_length as u8);
+                    tpdu.extend(pack_septets(&payload, 0));
+                }
+                (_, Some(header)) => {
+                    tpdu.push((header.len() + payload.len()) as u8);
+                    tpdu.extend(header);
+                    tpdu.extend(&payload);
+                }
+                (_, None) => {
+                    tpdu.push(payload.len() as u8);
+                    tpdu.extend(&payload);
+                }
+            }
+
+            // No service centre, the modem uses the one it is configured with
+            SubmitPdu {
+                hex: format!("00{}", to_hex(&tpdu)),
+                length: tpdu.len(),
+            }
+        })
+        .collect();
+
+    Ok(pdus)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> anyhow::Result<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| anyhow!("PDU ends early"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self.position + count;
+        ensure!(end <= self.bytes.len(), "PDU ends early");
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, count: usize) -> anyhow::Result<()> {
+        self.take(count).map(|_| ())
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    fn address(&mut self) -> anyhow::Result<String> {
+        let digits = self.byte()? as usize;
+        let type_of_address = self.byte()?;
+        let octets = self.take(digits.div_ceil(2))?;
+
+        // Senders such as "BANK" are written in GSM-7
+        if type_of_address & 0x70 == 0x50 {
+            return Ok(gsm_text(&unpack_septets(octets, 0, digits * 4 / 7)));
+        }
+
+        let number: String = octets
+            .iter()
+            .flat_map(|octet| [octet & 0x0f, octet >> 4])
+            .take(digits)
+            .map(|nibble| match nibble {
+                0..=9 => char::from(b'0' + nibble),
+                0x0a => '*',
+                0x0b => '#',
+                0x0c => 'a',
+                0x0d => 'b',
+                _ => 'c',
+            })
+            .collect();
+
+        if type_of_address & 0x70 == 0x10 {
+            Ok(format!("+{}", number))
+        } else {
+            Ok(number)
+        }
+    }
+
+    /// The service centre time stamp, in the sender's time zone
+    fn timestamp(&mut self) -> anyhow::Result<Option<NaiveDateTime>> {
+        let octets = self.take(7)?;
+        let field = |index: usize| swapped_bcd(octets[index]) as u32;
+
+        let zone = octets[6];
+        let quarters = swapped_bcd(zone & !0x08) as i32;
+        let offset_seconds = if zone & 0x08 != 0 {
+            -quarters
+        } else {
+            quarters
+        } * 15
+            * 60;
+
+        let local = NaiveDate::from_ymd_opt(2000 + field(0) as i32, field(1), field(2))
+            .and_then(|date| date.and_hms_opt(field(3), field(4), field(5)));
+        let offset = FixedOffset::east_opt(offset_seconds);
+
+        Ok(match (local, offset) {
+            (Some(local), Some(offset)) => offset
+                .from_local_datetime(&local)
+                .single()
+                .map(|time| time.with_timezone(&Local).naive_local()),
+            _ => None,
+        })
+    }
+}
+
+fn swapped_bcd(octet: u8) -> u8 {
+    (octet & 0x0f) * 10 + (octet >> 4)
+}
+
+fn alphabet(dcs: u8) -> Alphabet {
+    let bits = if dcs & 0xc0 == 0x00 {
+        (dcs >> 2) & 0x03
+    } else if dcs & 0xf0 == 0xf0 {
+        (dcs >> 2) & 0x01
+    } else if dcs & 0xf0 == 0xe0 {
+        0x02
+    } else {
+        0x00
+    };
+
+    match bits {
+        0x01 => Alphabet::EightBit,
+        0x02 => Alphabet::Ucs2,
+        _ => Alphabet::Gsm,
+    }
+}
+
+/// Decodes the user data, which holds the text and optionally a header
+/// before it
+fn user_data(
+    data: &[u8],
+    data_length: usize,
+    alphabet: &Alphabet,
+    has_header: bool,
+) -> anyhow::Result<(String, Option<Concat>)> {
+    let header_length = if has_header {
+        1 + *data.first().context("user data header is missing")? as usize
+    } else {
+        0
+    };
+    ensure!(header_length <= data.len(), "user data header is too long");
+    let concat = if has_header {
+        concat_header(&data[1..header_length])
+    } else {
+        None
+    };
+
+    let text = match alphabet {
+        Alphabet::Gsm => {
+            // The length counts septets, including the ones of the header
+            let header_septets = (header_length * 8).div_ceil(7);
+            let count = data_length.saturating_sub(header_septets);
+            gsm_text(&unpack_septets(data, header_septets * 7, count))
+        }
+        Alphabet::Ucs2 => {
+            let end = data_length.min(data.len());
+            let units: Vec<u16> = data[header_length.min(end)..end]
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        Alphabet::EightBit => {
+            let end = data_length.min(data.len());
+            String::from_utf8_lossy(&data[header_length.min(end)..end]).into_owned()
+        }
+    };
+
+    Ok((text, concat))
+}
+
+/// Finds the concatenation element among the information elements of a
+/// user data header
+fn concat_header(mut elements: &[u8]) -> Option<Concat> {
+    while let [id, length, rest @ ..] = elements {
+        let length = *length as usize;
+        let value = rest.get(..length)?;
+
+        match (id, value) {
+            (0x00, [reference, total, part]) => {
+                return Some(Concat {
+                    reference: *reference as u16,
+                    total: *total,
+                    part: *part,
+                })
+            }
+            (0x08, [high, low, total, part]) => {
+                return Some(Concat {
+                    reference: u16::from_be_bytes([*high, *low]),
+                    total: *total,
+                    part: *part,
+                })
+            }
+            _ => {}
+        }
+        elements = &rest[length..];
+    }
+
+    None
+}
+
+fn unpack_septets(data: &[u8], start_bit: usize, count: usize) -> Vec<u8> {
+    (0..count)
+        .map(|index| {
+            let bit = start_bit + index * 7;
+            let (byte, shift) = (bit / 8, bit % 8);
+            let low = *data.get(byte).unwrap_or(&0) as u16;
+            let high = *data.get(byte + 1).unwrap_or(&0) as u16;
+            (((low | high << 8) >> shift) & 0x7f) as u8
+        })
+        .collect()
+}
+
+fn pack_septets(septets: &[u8], fill_bits: usize) -> Vec<u8> {
+    let mut packed = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = fill_bits;
+
+    for septet in septets {
+        buffer |= ((septet & 0x7f) as u32) << bits;
+        bits += 7;
+        while bits >= 8 {
+            packed.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+    if bits > 0 {
+        packed.push(buffer as u8);
+    }
+
+    packed
+}
+
+fn gsm_text(septets: &[u8]) -> String {
+    let alphabet: Vec<char> = GSM_ALPHABET.chars().collect();
+    let mut text = String::new();
+    let mut escaped = false;
+
+    for &septet in septets {
+        if escaped {
+            escaped = false;
+            let extension = GSM_EXTENSION
+                .iter()
+                .find(|(code, _)| *code == septet)
+                .map(|(_, c)| *c);
+            // Unknown escapes fall back to the default table
+            text.push(extension.unwrap_or(alphabet[septet as usize]));
+        } else if septet == GSM_ESCAPE {
+            escaped = true;
+        } else {
+            text.push(alphabet[septet as usize]);
+        }
+    }
+
+    text
+}
+
+/// The text as GSM-7 septets, `None` when it has characters GSM-7 lacks
+fn gsm_septets(text: &str) -> Option<Vec<u8>> {
+    let mut septets = Vec::new();
+
+    for c in text.chars() {
+        if c != '\u{1b}' {
+            if let Some(position) = GSM_ALPHABET.chars().position(|g| g == c) {
+                septets.push(position as u8);
+                continue;
+            }
+        }
+        let (code, _) = GSM_EXTENSION.iter().find(|(_, e)| *e == c)?;
+        septets.extend([GSM_ESCAPE, *code]);
+    }
+
+    Some(septets)
+}
+
+/// Splits septets into parts as (septets, septet count), never between an
+/// escape and the character it escapes
+fn split_septets(septets: &[u8]) -> Vec<(Vec<u8>, usize)> {
+    if septets.len() <= GSM_SINGLE {
+        return vec![(septets.to_vec(), septets.len())];
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = septets;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(GSM_PART);
+        if end < rest.len() && rest[end - 1] == GSM_ESCAPE {
+            end -= 1;
+        }
+        parts.push((rest[..end].to_vec(), end));
+        rest = &rest[end..];
+    }
+    parts
+}
+
+/// Splits text into UCS-2 parts as (bytes, byte count), never between the
+/// halves of a surrogate pair
+fn split_ucs2(text: &str) -> Vec<(Vec<u8>, usize)> {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let part_size = if units.len() <= UCS2_SINGLE {
+        UCS2_SINGLE
+    } else {
+        UCS2_PART
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = units.as_slice();
+    while !rest.is_empty() {
+        let mut end = rest.len().min(part_size);
+        if end < rest.len() && (0xd800..0xdc00).contains(&rest[end - 1]) {
+            end -= 1;
+        }
+        let bytes: Vec<u8> = rest[..end]
+            .iter()
+            .flat_map(|unit| unit.to_be_bytes())
+            .collect();
+        let length = bytes.len();
+        parts.push((bytes, length));
+        rest = &rest[end..];
+    }
+    parts
+}
+
+fn encode_address(number: &str) -> anyhow::Result<Vec<u8>> {
+    let (type_of_address, digits) = match number.strip_prefix('+') {
+        Some(digits) => (TOA_INTERNATIONAL, digits),
+        None => (TOA_UNKNOWN, number),
+    };
+    let digits: Vec<u8> = digits
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '(' | ')'))
+        .map(|c| c.to_digit(10).map(|d| d as u8))
+        .collect::<Option<_>>()
+        .ok_or_else(|| anyhow!("'{}' is not a phone number", number))?;
+    ensure!(!digits.is_empty(), "'{}' is not a phone number", number);
+
+    let mut address = vec![digits.len() as u8, type_of_address];
+    address.extend(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] | pair.get(1).copied().unwrap_or(0x0f) << 4),
+    );
+    Ok(address)
+}
+
+fn from_hex(hex: &str) -> anyhow::Result<Vec<u8>> {
+    // Checked first so that the pairs below are always on char boundaries
+    ensure!(
+        hex.bytes().all(|byte| byte.is_ascii_hexdigit()),
+        "PDU is not hex: {}",
+        hex
+    );
+    ensure!(
+        hex.len().is_multiple_of(2),
+        "PDU has an odd number of hex digits"
+    );
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| anyhow!("PDU is not hex: {}", hex))
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Turns what `encode_submit` makes into the SMS-DELIVER PDUs a modem would
+/// list when receiving the same text, stamped with the 7 octets given
+#[cfg(test)]
+pub fn encode_deliver(number: &str, text: &str, reference: u8, timestamp: [u8; 7]) -> Vec<String> {
+    encode_submit(number, text, reference)
+        .unwrap()
+        .into_iter()
+        .map(|submit| {
+            // Without the empty service centre
+            let tpdu = from_hex(&submit.hex[2..]).unwrap();
+            let address_end = 4 + (tpdu[2] as usize).div_ceil(2);
+            // Keeps the header indicator, "no more messages" is set
+            let mut deliver = vec![(tpdu[0] & 0x40) | 0x04];
+            deliver.extend(&tpdu[2..address_end + 2]);
+            deliver.extend(timestamp);
+            deliver.extend(&tpdu[address_end + 2..]);
+            format!("00{}", to_hex(&deliver))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2002-08-26 19:37:41 at UTC+2
+    const TIMESTAMP: [u8; 7] = [0x20, 0x80, 0x62, 0x91, 0x73, 0x14, 0x80];
+
+    fn local(offset_hours: i32, y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> NaiveDateTime {
+        FixedOffset::east_opt(offset_hours * 3600)
+            .unwrap()
+            .with_ymd_and_hms(y, m, d, h, min, s)
+            .unwrap()
+            .with_timezone(&Local)
+            .naive_local()
+    }
+
+    #[test]
+    fn decodes_a_received_message() {
+        let pdu =
+            decode("07911326040000F0040B911346610089F60000208062917314080CC8F71D14969741F977FD07")
+                .unwrap();
+        assert_eq!(pdu.kind, PduKind::Deliver);
+        assert_eq!(pdu.address, "+31641600986");
+        assert_eq!(pdu.text, "How are you?");
+        // The zone octet is 0x08, a negative zero
+        assert_eq!(pdu.timestamp, Some(local(0, 2002, 8, 26, 19, 37, 41)));
+        assert_eq!(pdu.concat, None);
+    }
+
+    #[test]
+    fn decodes_a_stored_submit_with_a_validity_period() {
+        let pdu = decode("0011000B916407281553F80000AA0AE8329BFD4697D9EC37").unwrap();
+        assert_eq!(pdu.kind, PduKind::Submit);
+        assert_eq!(pdu.address, "+46708251358");
+        assert_eq!(pdu.text, "hellohello");
+        assert_eq!(pdu.timestamp, None);
+    }
+
+    #[test]
+    fn decodes_an_alphanumeric_sender() {
+        // "BANK" packed as four septets in seven digits
+        let pdu = decode("000407D0C2A073090000208062917314080148").unwrap();
+        assert_eq!(pdu.address, "BANK");
+        assert_eq!(pdu.text, "H");
+    }
+
+    #[test]
+    fn encodes_a_single_gsm_message() {
+        let pdus = encode_submit("+46708251358", "hellohello", 0).unwrap();
+        assert_eq!(pdus.len(), 1);
+        assert_eq!(
+            pdus[0].hex,
+            "0001000B916407281553F800000AE8329BFD4697D9EC37"
+        );
+        assert_eq!(pdus[0].length, 22);
+    }
+
+    #[test]
+    fn packs_septets() {
+        let septets = gsm_septets("hellohello").unwrap();
+        let packed = pack_septets(&septets, 0);
+        assert_eq!(to_hex(&packed), "E8329BFD4697D9EC37");
+        assert_eq!(unpack_septets(&packed, 0, septets.len()), septets);
+
+        // After a six octet header the text starts one fill bit in
+        let packed = pack_septets(&septets, 1);
+        assert_eq!(unpack_septets(&packed, 1, septets.len()), septets);
+    }
+
+    #[test]
+    fn uses_the_escape_table() {
+        let septets = gsm_septets("{€}\\[~]|^").unwrap();
+        assert_eq!(
+            septets,
+            [
+                0x1b, 0x28, 0x1b, 0x65, 0x1b, 0x29, 0x1b, 0x2f, 0x1b, 0x3c, 0x1b, 0x3d, 0x1b, 0x3e,
+                0x1b, 0x40, 0x1b, 0x14
+            ]
+        );
+        assert_eq!(gsm_text(&septets), "{€}\\[~]|^");
+        // An escape nobody defined reads as the default character
+        assert_eq!(gsm_text(&[0x1b, 0x41]), "A");
+        // The escape itself can not be sent as text
+        assert_eq!(gsm_septets("\u{1b}"), None);
+
+        let pdus = encode_submit("+15551234567", "costs 5€", 0).unwrap();
+        assert_eq!(decode(&pdus[0].hex).unwrap().text, "costs 5€");
+    }
+
+    #[test]
+    fn falls_back_to_ucs2() {
+        let pdus = encode_submit("+15551234567", "Привет", 0).unwrap();
+        assert_eq!(
+            pdus[0].hex,
+            "0001000B915155214365F700080C041F04400438043204350442"
+        );
+        assert_eq!(decode(&pdus[0].hex).unwrap().text, "Привет");
+
+        let pdus = encode_submit("+15551234567", "🙂 ok", 0).unwrap();
+        assert_eq!(decode(&pdus[0].hex).unwrap().text, "🙂 ok");
+    }
+
+    #[test]
+    fn splits_long_gsm_messages() {
+        let text: String = (0..200)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let pdus = encode_submit("+15551234567", &text, 42).unwrap();
+        assert_eq!(pdus.len(), 2);
+
+        let decoded: Vec<Pdu> = pdus.iter().map(|pdu| decode(&pdu.hex).unwrap()).collect();
+        for (index, pdu) in decoded.iter().enumerate() {
+            assert_eq!(
+                pdu.concat,
+                Some(Concat {
+                    reference: 42,
+                    total: 2,
+                    part: index as u8 + 1,
+                })
+            );
+        }
+        assert_eq!(decoded[0].text.chars().count(), GSM_PART);
+        assert_eq!(format!("{}{}", decoded[0].text, decoded[1].text), text);
+    }
+
+    #[test]
+    fn never_splits_an_escape() {
+        let text = format!("{}€{}", "a".repeat(GSM_PART - 1), "b".repeat(10));
+        let parts = split_septets(&gsm_septets(&text).unwrap());
+        assert_eq!(parts[0].1, GSM_PART - 1);
+        assert_eq!(parts[1].0[..2], [GSM_ESCAPE, 0x65]);
+
+        let pdus = encode_submit("+15551234567", &text, 0).unwrap();
+        let joined: String = pdus
+            .iter()
+            .map(|pdu| decode(&pdu.hex).unwrap().text)
+            .collect();
+        assert_eq!(joined, text);
+    }
+
+    #[test]
+    fn splits_long_ucs2_messages_between_surrogate_pairs() {
+        let text = format!("{}🙂{}", "я".repeat(UCS2_PART - 1), "ж".repeat(10));
+        let pdus = encode_submit("+15551234567", &text, 7).unwrap();
+        assert_eq!(pdus.len(), 2);
+
+        let decoded: Vec<Pdu> = pdus.iter().map(|pdu| decode(&pdu.hex).unwrap()).collect();
+        assert_eq!(decoded[0].text, "я".repeat(UCS2_PART - 1));
+        assert!(decoded[1].text.starts_with('🙂'));
+        assert_eq!(decoded[1].concat.map(|concat| concat.part), Some(2));
+    }
+
+    #[test]
+    fn reads_both_concatenation_headers() {
+        assert_eq!(
+            concat_header(&[0x00, 0x03, 0x2a, 0x03, 0x01]),
+            Some(Concat {
+                reference: 0x2a,
+                total: 3,
+                part: 1,
+            })
+        );
+        // Other elements first, then a 16 bit reference
+        assert_eq!(
+            concat_header(&[0x24, 0x01, 0x00, 0x08, 0x04, 0x12, 0x34, 0x02, 0x02]),
+            Some(Concat {
+                reference: 0x1234,
+                total: 2,
+                part: 2,
+            })
+        );
+        assert_eq!(concat_header(&[0x00, 0x03, 0x2a]), None);
+    }
+
+    #[test]
+    fn decodes_received_parts() {
+        let text = "x".repeat(170);
+        let parts = encode_deliver("+15551234567", &text, 9, TIMESTAMP);
+        assert_eq!(parts.len(), 2);
+
+        let first = decode(&parts[0]).unwrap();
+        assert_eq!(first.kind, PduKind::Deliver);
+        assert_eq!(first.address, "+15551234567");
+        assert_eq!(first.timestamp, Some(local(2, 2002, 8, 26, 19, 37, 41)));
+        assert_eq!(first.concat.map(|concat| concat.reference), Some(9));
+        assert_eq!(
+            first.text.len() + decode(&parts[1]).unwrap().text.len(),
+            170
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_hex() {
+        for hex in ["0", "0G", "é0", "00é", "0€"] {
+            assert!(decode(hex).is_err(), "{hex}");
+        }
+        // Cut off in the middle of the address
+        assert!(decode("07911326040000F0040B9113").is_err());
+    }
+
+    #[test]
+    fn rejects_addresses_that_are_not_numbers() {
+        assert!(encode_submit("alice", "hi", 0).is_err());
+        assert!(encode_submit("+", "hi", 0).is_err());
+        let pdus = encode_submit("+1 (555) 123-4567", "hi", 0).unwrap();
+        assert_eq!(decode(&pdus[0].hex).unwrap().address, "+15551234567");
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    path::Path,
+    thread,
+};
+
+use anyhow::{bail, Context};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tracing::{event, Level};
+
+/// What modems print when `AT+CMGS` is ready for the PDU, without a line end
+pub const PROMPT: &str = ">";
+
+/// Opens the serial device in raw mode, so that nothing between us and the
+/// modem echoes, translates line ends or waits for a whole line
+pub fn open(path: &Path, baud: u32) -> anyhow::Result<File> {
+    let speed = speed(baud)?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+        .with_context(|| format!("could not open {}", path.display()))?;
+
+    let fd = file.as_raw_fd();
+    // The descriptor stays open for as long as `file`, and tcgetattr fills in
+    // the whole struct before it is changed
+    let result = unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) == 0 {
+            libc::cfmakeraw(&mut termios);
+            termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+            termios.c_cc[libc::VMIN] = 1;
+            termios.c_cc[libc::VTIME] = 0;
+            libc::cfsetispeed(&mut termios, speed);
+            libc::cfsetospeed(&mut termios, speed);
+            libc::tcsetattr(fd, libc::TCSANOW, &termios)
+        } else {
+            -1
+        }
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("{} is not a serial port", path.display()));
+    }
+
+    Ok(file)
+}
+
+fn speed(baud: u32) -> anyhow::Result<libc::speed_t> {
+    Ok(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        460800 => libc::B460800,
+        921600 => libc::B921600,
+        _ => bail!("unsupported baud rate {}", baud),
+    })
+}
+
+/// Reads the modem's output on a thread of its own, as reading a tty blocks.
+/// Lines arrive without their line ends and with blank ones dropped; the
+/// channel closes when the device goes away.
+pub fn read_lines(mut file: File) -> UnboundedReceiver<String> {
+    let (line_tx, line_rx) = mpsc::unbounded_channel();
+
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+        let mut line = Vec::new();
+
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    event!(Level::WARN, "Could not read from the modem: {}", e);
+                    break;
+                }
+            };
+
+            for &byte in &buffer[..read] {
+                if byte == b'\r' || byte == b'\n' {
+                    if !line.is_empty() {
+                        let text = String::from_utf8_lossy(&line).trim().to_string();
+                        line.clear();
+                        if !text.is_empty() && line_tx.send(text).is_err() {
+                            return;
+                        }
+                    }
+                    continue;
+                }
+
+                line.push(byte);
+                // The prompt is never followed by a line end
+                if line == b"> " {
+                    line.clear();
+                    if line_tx.send(PROMPT.to_string()).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    line_rx
+}
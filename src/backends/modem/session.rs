@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{Local, NaiveDateTime};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{event, Level};
+
+use super::at::Modem;
+use super::pdu::{self, Pdu, PduKind};
+use super::Conversations;
+use crate::backends::BackendEvent;
+use crate::state::{normalize_handle, Contact, DeliveryStatus, Message, MessageDirection};
+
+/// `AT+CMGL` status of messages stored unsent
+pub const STAT_UNSENT: u8 = 2;
+
+/// Requests from the backend to the session task
+#[derive(Debug)]
+pub enum Outgoing {
+    Send {
+        number: String,
+        message_id: String,
+        body: String,
+    },
+    Quit,
+}
+
+/// Parts of a long message seen so far, by direction, number and reference
+type PendingParts = HashMap<(PduKind, String, u16), Vec<Option<(usize, Pdu)>>>;
+
+pub struct Session {
+    pub modem: Modem,
+    pub outgoing: UnboundedReceiver<Outgoing>,
+    pub event_tx: UnboundedSender<BackendEvent>,
+    pub conversations: Arc<Mutex<Conversations>>,
+    /// Shared by the parts of a long message, the next one gets the next
+    pub reference: u8,
+    pub parts: PendingParts,
+}
+
+impl Session {
+    /// Turns off echo, switches to PDU mode and asks for new messages to be
+    /// stored and announced with `+CMTI`
+    pub async fn init(&mut self) -> anyhow::Result<()> {
+        self.modem.command("AT").await?;
+        self.modem.command("ATE0").await?;
+        self.modem.command("AT+CMGF=0").await?;
+        self.modem.command("AT+CNMI=2,1,0,0,0").await?;
+        Ok(())
+    }
+
+    /// Loads every message stored on the modem, read or not
+    pub async fn load(&mut self) -> anyhow::Result<()> {
+        let lines = self.modem.command("AT+CMGL=4").await?;
+        let mut last_timestamp = None;
+        let mut count = 0;
+
+        let mut lines = lines.iter();
+        while let Some(header) = lines.next() {
+            let Some(fields) = header.strip_prefix("+CMGL:") else {
+                continue;
+            };
+            let Some(hex) = lines.next() else {
+                break;
+            };
+            let mut fields = fields.split(',').map(str::trim);
+            let (Some(index), Some(stat)) = (
+                fields.next().and_then(|index| index.parse().ok()),
+                fields.next().and_then(|stat| stat.parse().ok()),
+            ) else {
+                continue;
+            };
+
+            // The modem keeps no time for messages it sent, they go after
+            // whatever was listed before them
+            if let Some(message) = self.receive(index, stat, hex, last_timestamp) {
+                last_timestamp = Some(message.timestamp);
+                self.conversations.lock().unwrap().push(message);
+                count += 1;
+            }
+        }
+
+        event!(Level::INFO, "Loaded {} messages from the modem", count);
+        Ok(())
+    }
+
+    /// Handles unsolicited lines and sends messages until the backend quits
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                line = self.modem.next_unsolicited() => match line {
+                    Some(line) => self.unsolicited(&line).await,
+                    None => {
+                        let reason = String::from("modem went away");
+                        event!(Level::WARN, "{}", reason);
+                        let _ = self.event_tx.send(BackendEvent::Disconnected { reason });
+                        return self.offline().await;
+                    }
+                },
+                outgoing = self.outgoing.recv() => match outgoing {
+                    Some(Outgoing::Send { number, message_id, body }) => {
+                        self.send(&number, message_id, &body).await;
+                    }
+                    Some(Outgoing::Quit) | None => return,
+                },
+            }
+        }
+    }
+
+    /// Fails whatever is sent once the modem is gone
+    async fn offline(mut self) {
+        while let Some(Outgoing::Send { message_id, .. }) = self.outgoing.recv().await {
+            self.fail(message_id, String::from("modem went away"));
+        }
+    }
+
+    async fn unsolicited(&mut self, line: &str) {
+        let Some(storage) = line.strip_prefix("+CMTI:") else {
+            event!(Level::DEBUG, "Ignoring {}", line);
+            return;
+        };
+        let Some(index) = storage
+            .rsplit(',')
+            .next()
+            .and_then(|index| index.trim().parse().ok())
+        else {
+            event!(Level::WARN, "Malformed notification {}", line);
+            return;
+        };
+
+        let lines = match self.modem.command(&format!("AT+CMGR={}", index)).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                event!(Level::WARN, "Could not read message {}: {:#}", index, e);
+                return;
+            }
+        };
+        let Some(position) = lines.iter().position(|line| line.starts_with("+CMGR:")) else {
+            event!(Level::WARN, "Message {} is empty", index);
+            return;
+        };
+        let stat = lines[position]
+            .trim_start_matches("+CMGR:")
+            .split(',')
+            .next()
+            .and_then(|stat| stat.trim().parse().ok())
+            .unwrap_or_default();
+        let Some(hex) = lines.get(position + 1) else {
+            return;
+        };
+
+        if let Some(message) = self.receive(index, stat, hex, None) {
+            self.conversations.lock().unwrap().push(message.clone());
+            let _ = self.event_tx.send(BackendEvent::MessageReceived(message));
+        }
+    }
+
+    /// Turns a stored PDU into a message, or into nothing until every part of
+    /// a long message is there
+    fn receive(
+        &mut self,
+        index: usize,
+        stat: u8,
+        hex: &str,
+        sent_after: Option<NaiveDateTime>,
+    ) -> Option<Message> {
+        let pdu = match pdu::decode(hex) {
+            Ok(pdu) => pdu,
+            Err(e) => {
+                event!(Level::WARN, "Could not decode message {}: {:#}", index, e);
+                return None;
+            }
+        };
+
+        let (index, pdu) = match pdu.concat {
+            Some(concat) if concat.total > 1 => {
+                let key = (pdu.kind, normalize_handle(&pdu.address), concat.reference);
+                let parts = self
+                    .parts
+                    .entry(key.clone())
+                    .or_insert_with(|| vec![None; concat.total as usize]);
+                let slot = (concat.part as usize).checked_sub(1)?;
+                *parts.get_mut(slot)? = Some((index, pdu));
+
+                if parts.iter().any(Option::is_none) {
+                    return None;
+                }
+                let parts = self.parts.remove(&key)?;
+                let mut parts = parts.into_iter().flatten();
+                let (first_index, mut first) = parts.next()?;
+                for (_, part) in parts {
+                    first.text.push_str(&part.text);
+                }
+                (first_index, first)
+            }
+            _ => (index, pdu),
+        };
+
+        let contact = Contact::new(pdu.address.clone(), pdu.address.clone());
+        let (direction, timestamp) = match pdu.kind {
+            PduKind::Deliver => (MessageDirection::From, pdu.timestamp),
+            PduKind::Submit => (MessageDirection::To, sent_after),
+        };
+        let timestamp = timestamp.unwrap_or_else(|| Local::now().naive_local());
+
+        let mut message = Message::new(contact, pdu.text, timestamp, direction)
+            .with_id(format!("modem-{}", index));
+        if pdu.kind == PduKind::Submit && stat == STAT_UNSENT {
+            message.status = DeliveryStatus::Failed(String::from("stored unsent on the modem"));
+        }
+        Some(message)
+    }
+
+    async fn send(&mut self, number: &str, message_id: String, body: &str) {
+        let reference = self.reference;
+        self.reference = self.reference.wrapping_add(1);
+
+        let pdus = match pdu::encode_submit(number, body, reference) {
+            Ok(pdus) => pdus,
+            Err(e) => return self.fail(message_id, format!("{:#}", e)),
+        };
+
+        for (part, pdu) in pdus.iter().enumerate() {
+            match self.modem.send_pdu(pdu.length, &pdu.hex).await {
+                Ok(network_reference) => event!(
+                    Level::DEBUG,
+                    "Sent part {} of {} to {} as {}",
+                    part + 1,
+                    pdus.len(),
+                    number,
+                    network_reference
+                ),
+                Err(e) => return self.fail(message_id, format!("{:#}", e)),
+            }
+        }
+    }
+
+    fn fail(&self, message_id: String, reason: String) {
+        event!(Level::WARN, "Could not send {}: {}", message_id, reason);
+        if let Some(message) = self.conversations.lock().unwrap().find_message(&message_id) {
+            message.status = DeliveryStatus::Failed(reason.clone());
+        }
+        let _ = self
+            .event_tx
+            .send(BackendEvent::DeliveryFailed { message_id, reason });
+    }
+}
@@ -0,0 +1,805 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::bail;
+use chrono::NaiveDateTime;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::{create_backend, BackendEvent, BackendSpec, EventInjector, Injection, MsgBackend};
+use crate::state::{Contact, DeliveryStatus, Message};
+
+/// One of the backends, and the name its conversations are tagged with
+struct Account {
+    label: String,
+    backend: Box<dyn MsgBackend>,
+}
+
+/// When each conversation last saw a message, by account label and the
+/// contact's handle. Looked up once per conversation and then kept current
+/// from sends and received messages, so that ordering every account's
+/// conversations does not read their history each time.
+type LastActivity = HashMap<(String, String), Option<NaiveDateTime>>;
+
+/// Why each account that lost its connection did, by label. The app follows
+/// one connection, so it hears `Connected` only once none are left here.
+type Disconnected = HashMap<String, String>;
+
+/// Several backends shown as one. Conversations are tagged with the account
+/// they belong to, which is how sends find their way back to it.
+pub struct MultiBackend {
+    accounts: Vec<Account>,
+    last_activity: Arc<Mutex<LastActivity>>,
+    disconnected: Arc<Mutex<Disconnected>>,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+}
+
+impl MultiBackend {
+    /// Starts every backend, leaving out those that fail as long as one is
+    /// left
+    pub async fn connect(specs: &[BackendSpec]) -> anyhow::Result<Self> {
+        let mut accounts: Vec<Account> = Vec::new();
+        let mut failures = Vec::new();
+        let mut kinds: HashMap<&str, usize> = HashMap::new();
+
+        for spec in specs {
+            let backend = match create_backend(spec).await {
+                Ok(backend) => backend,
+                Err(e) => {
+                    event!(Level::ERROR, "Could not create {} backend: {:#}", spec, e);
+                    failures.push(format!("{}: {:#}", spec, e));
+                    continue;
+                }
+            };
+
+            // The second IRC network is irc-2 and so on
+            let kind = spec.kind();
+            let count = kinds.entry(kind).or_default();
+            *count += 1;
+            let label = match count {
+                1 => kind.to_string(),
+                _ => format!("{}-{}", kind, count),
+            };
+            event!(Level::INFO, "Started {} as {}", spec, label);
+            accounts.push(Account { label, backend });
+        }
+
+        if accounts.is_empty() {
+            bail!("no backend could be started: {}", failures.join("; "));
+        }
+
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            accounts,
+            last_activity: Arc::default(),
+            disconnected: Arc::default(),
+            event_tx,
+            event_rx: Some(event_rx),
+        })
+    }
+
+    fn account(&self, contact: &Contact) -> Option<&Account> {
+        let label = contact.account.as_ref()?;
+        self.accounts.iter().find(|account| &account.label == label)
+    }
+
+    fn account_mut(&mut self, contact: &Contact) -> Option<&mut Account> {
+        let label = contact.account.as_ref()?;
+        self.accounts
+            .iter_mut()
+            .find(|account| &account.label == label)
+    }
+
+    fn account_index(&self, label: &str) -> Option<usize> {
+        self.accounts
+            .iter()
+            .position(|account| account.label == label)
+    }
+
+    /// The account a name is for and the name without its label: the account
+    /// named before a colon, as in `irc:#rust`, else the one account the
+    /// name looks like it belongs to. `None` when no account recognizes it.
+    fn pick_account<'a>(&self, name: &'a str) -> anyhow::Result<Option<(usize, &'a str)>> {
+        if let Some((label, rest)) = name.split_once(':') {
+            if let Some(index) = self.account_index(label) {
+                return Ok(Some((index, rest)));
+            }
+        }
+
+        let recognizing: Vec<usize> = (0..self.accounts.len())
+            .filter(|&index| self.accounts[index].backend.recognizes(name))
+            .collect();
+        match recognizing.as_slice() {
+            [] => Ok(None),
+            [index] => Ok(Some((*index, name))),
+            indices => {
+                let labels: Vec<&str> = indices
+                    .iter()
+                    .map(|&index| self.accounts[index].label.as_str())
+                    .collect();
+                bail!(
+                    "'{}' could be on {}, write it as {}:{}",
+                    name,
+                    labels.join(" or "),
+                    labels[0],
+                    name
+                );
+            }
+        }
+    }
+}
+
+/// The contact as its own backend knows it
+fn untagged(contact: &Contact) -> Contact {
+    Contact {
+        account: None,
+        ..contact.clone()
+    }
+}
+
+/// Moves a conversation's last activity forward to `timestamp`
+fn saw_message(
+    last_activity: &Mutex<LastActivity>,
+    label: &str,
+    handle: &str,
+    timestamp: NaiveDateTime,
+) {
+    let mut last_activity = last_activity.lock().unwrap();
+    let seen = last_activity
+        .entry((label.to_string(), handle.to_string()))
+        .or_default();
+    *seen = (*seen).max(Some(timestamp));
+}
+
+fn tag_message(mut message: Message, label: &str) -> Message {
+    message.contact.account = Some(label.to_string());
+    message
+}
+
+/// The event to pass on once the account `label` lost or regained its
+/// connection: disconnected while any account is, with every reason
+fn connection_changed(
+    disconnected: &Mutex<Disconnected>,
+    label: &str,
+    reason: Option<String>,
+) -> BackendEvent {
+    let mut disconnected = disconnected.lock().unwrap();
+    match reason {
+        Some(reason) => disconnected.insert(label.to_string(), reason),
+        None => disconnected.remove(label),
+    };
+    if disconnected.is_empty() {
+        return BackendEvent::Connected;
+    }
+
+    let mut reasons: Vec<String> = disconnected
+        .iter()
+        .map(|(label, reason)| format!("{}: {}", label, reason))
+        .collect();
+    reasons.sort();
+    BackendEvent::Disconnected {
+        reason: reasons.join("; "),
+    }
+}
+
+/// The contact an injection is about, if any
+fn injected_contact(injection: &mut Injection) -> Option<&mut Contact> {
+    match injection {
+        Injection::Burst { contact, .. } => Some(contact),
+        Injection::Event(BackendEvent::MessageReceived(message)) => Some(&mut message.contact),
+        Injection::Event(BackendEvent::Typing { contact, .. }) => Some(contact),
+        Injection::Event(BackendEvent::Reaction { from, .. }) => Some(from),
+        Injection::Event(_) => None,
+    }
+}
+
+impl MsgBackend for MultiBackend {
+    fn send_message(&mut self, mut message: Message) {
+        let last_activity = self.last_activity.clone();
+        let Some(account) = self.account_mut(&message.contact) else {
+            let reason = String::from("conversation does not belong to any account");
+            event!(Level::WARN, "Could not send {}: {}", message.id, reason);
+            message.status = DeliveryStatus::Failed(reason.clone());
+            let _ = self.event_tx.send(BackendEvent::DeliveryFailed {
+                message_id: message.id,
+                reason,
+            });
+            return;
+        };
+
+        message.contact = untagged(&message.contact);
+        saw_message(
+            &last_activity,
+            &account.label,
+            &message.contact.phone,
+            message.timestamp,
+        );
+        account.backend.send_message(message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let Some(account) = self.account(contact) else {
+            return Vec::new();
+        };
+
+        account
+            .backend
+            .get_messages(&untagged(contact), n)
+            .into_iter()
+            .map(|message| tag_message(message, &account.label))
+            .collect()
+    }
+
+    /// Every account's conversations, the most recently active first
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let mut last_activity = self.last_activity.lock().unwrap();
+        let mut recent: Vec<(Contact, _)> = self
+            .accounts
+            .iter()
+            .flat_map(|account| {
+                account
+                    .backend
+                    .get_recent_contacts()
+                    .into_iter()
+                    .map(|contact| {
+                        let key = (account.label.clone(), contact.phone.clone());
+                        let seen = *last_activity.entry(key).or_insert_with(|| {
+                            let last_message = account.backend.get_messages(&contact, Some(1));
+                            last_message.first().map(|message| message.timestamp)
+                        });
+                        (contact.with_account(account.label.clone()), seen)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Stable, so each account's own order breaks ties
+        recent.sort_by_key(|(_, last_activity)| Reverse(*last_activity));
+        recent.into_iter().map(|(contact, _)| contact).collect()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        let event_rx = self.event_rx.take()?;
+
+        for account in &mut self.accounts {
+            let Some(mut account_rx) = account.backend.take_events() else {
+                continue;
+            };
+            let label = account.label.clone();
+            let event_tx = self.event_tx.clone();
+            let last_activity = self.last_activity.clone();
+            let disconnected = self.disconnected.clone();
+
+            tokio::spawn(async move {
+                while let Some(backend_event) = account_rx.recv().await {
+                    let backend_event = match backend_event {
+                        BackendEvent::MessageReceived(message) => {
+                            saw_message(
+                                &last_activity,
+                                &label,
+                                &message.contact.phone,
+                                message.timestamp,
+                            );
+                            BackendEvent::MessageReceived(tag_message(message, &label))
+                        }
+                        BackendEvent::Typing { contact, is_typing } => BackendEvent::Typing {
+                            contact: contact.with_account(label.clone()),
+                            is_typing,
+                        },
+                        BackendEvent::Disconnected { reason } => {
+                            connection_changed(&disconnected, &label, Some(reason))
+                        }
+                        BackendEvent::Connected => connection_changed(&disconnected, &label, None),
+                        other => other,
+                    };
+                    if event_tx.send(backend_event).is_err() {
+                        return;
+                    }
+                }
+                event!(Level::DEBUG, "Events of {} ended", label);
+            });
+        }
+
+        Some(event_rx)
+    }
+
+    /// Passes injections on to the account of the conversation they are
+    /// about, those about none to the first account that takes injections
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        let any = self
+            .accounts
+            .iter_mut()
+            .any(|account| account.backend.injector().is_some());
+        any.then_some(self as &mut dyn EventInjector)
+    }
+
+    /// Joins on the account named before a colon, as in `irc:#rust`. Else
+    /// on the one account the name looks like it belongs to, or when none
+    /// recognizes it on the first account that can join it.
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        let name = name.trim();
+        if let Some((index, name)) = self.pick_account(name)? {
+            let account = &mut self.accounts[index];
+            let contact = account.backend.join_conversation(name)?;
+            return Ok(contact.with_account(account.label.clone()));
+        }
+
+        let mut errors = Vec::new();
+        for account in &mut self.accounts {
+            match account.backend.join_conversation(name) {
+                Ok(contact) => return Ok(contact.with_account(account.label.clone())),
+                Err(e) => errors.push(format!("{}: {:#}", account.label, e)),
+            }
+        }
+        bail!("{}", errors.join("; "))
+    }
+
+    /// Starts on the account the contact is tagged with, as address book
+    /// entries are, else on the one `join_conversation` would pick. Tagged
+    /// with it even when that account sends to the handle as it is. A handle
+    /// no account recognizes or can join needs its account written out,
+    /// unless there is only one.
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        let handle = contact.phone.trim();
+        let tagged = contact
+            .account
+            .as_deref()
+            .and_then(|label| self.account_index(label));
+        let (index, handle) = match tagged {
+            Some(index) => (index, handle),
+            None => match self.pick_account(handle)? {
+                Some(picked) => picked,
+                None => match self.join_conversation(handle) {
+                    Ok(joined) => return Ok(joined),
+                    Err(_) if self.accounts.len() == 1 => (0, handle),
+                    Err(e) => bail!(
+                        "no account can start '{}', write it as {}:{} ({:#})",
+                        handle,
+                        self.accounts[0].label,
+                        handle,
+                        e
+                    ),
+                },
+            },
+        };
+
+        // Typed handles are named after themselves
+        let name = if contact.name == contact.phone {
+            handle.to_string()
+        } else {
+            contact.name.clone()
+        };
+        let untagged = Contact {
+            name,
+            phone: handle.to_string(),
+            ..untagged(&contact)
+        };
+        let account = &mut self.accounts[index];
+        let started = account.backend.start_conversation(untagged)?;
+        Ok(started.with_account(account.label.clone()))
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        self.accounts
+            .iter()
+            .any(|account| account.backend.recognizes(name))
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        let Some(account) = self.account_mut(&message.contact) else {
+            bail!("conversation does not belong to any account");
+        };
+
+        let message = Message {
+            contact: untagged(&message.contact),
+            ..message.clone()
+        };
+        account.backend.send_reaction(&message, reaction)
+    }
+
+    /// Every account's address book, tagged with the account
+    fn address_book(&self) -> Vec<Contact> {
+        self.accounts
+            .iter()
+            .flat_map(|account| {
+                account
+                    .backend
+                    .address_book()
+                    .into_iter()
+                    .map(|contact| contact.with_account(account.label.clone()))
+            })
+            .collect()
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        let tasks: Vec<JoinHandle<()>> = self
+            .accounts
+            .iter_mut()
+            .filter_map(|account| account.backend.close())
+            .collect();
+
+        Some(tokio::spawn(async move {
+            for task in tasks {
+                let _ = task.await;
+            }
+        }))
+    }
+}
+
+impl EventInjector for MultiBackend {
+    fn inject(&mut self, mut injection: Injection) -> anyhow::Result<()> {
+        let label = injected_contact(&mut injection).and_then(|contact| contact.account.take());
+        let mut accounts = self.accounts.iter_mut();
+        let injector = match label {
+            Some(label) => accounts
+                .find(|account| account.label == label)
+                .and_then(|account| account.backend.injector()),
+            None => accounts.find_map(|account| account.backend.injector()),
+        };
+        let Some(injector) = injector else {
+            bail!("no account takes injections for that conversation");
+        };
+        injector.inject(injection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use std::time::Duration;
+
+    use chrono::NaiveDate;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::{backends::MockBackend, state::MessageDirection};
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// An account joining whatever `accepts` allows and recognizing what
+    /// `recognizes` does, counting how often its history is read
+    struct FakeAccount {
+        accepts: fn(&str) -> bool,
+        recognizes: fn(&str) -> bool,
+        messages: Vec<Message>,
+        book: Vec<Contact>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl FakeAccount {
+        fn new(accepts: fn(&str) -> bool, recognizes: fn(&str) -> bool) -> Self {
+            Self {
+                accepts,
+                recognizes,
+                messages: Vec::new(),
+                book: Vec::new(),
+                reads: Arc::default(),
+            }
+        }
+    }
+
+    impl MsgBackend for FakeAccount {
+        fn send_message(&mut self, message: Message) {
+            self.messages.push(message);
+        }
+
+        fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let messages: Vec<Message> = self
+                .messages
+                .iter()
+                .filter(|message| message.contact.phone == contact.phone)
+                .cloned()
+                .collect();
+            let skip = n.map_or(0, |n| messages.len().saturating_sub(n as usize));
+            messages[skip..].to_vec()
+        }
+
+        fn get_recent_contacts(&self) -> Vec<Contact> {
+            let mut contacts: Vec<Contact> = Vec::new();
+            for message in self.messages.iter().rev() {
+                if !contacts.iter().any(|c| c.phone == message.contact.phone) {
+                    contacts.push(message.contact.clone());
+                }
+            }
+            contacts
+        }
+
+        fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+            if !(self.accepts)(name) {
+                bail!("can not join '{}'", name);
+            }
+            Ok(Contact::new(name.to_string(), name.to_string()))
+        }
+
+        fn recognizes(&self, name: &str) -> bool {
+            (self.recognizes)(name)
+        }
+
+        fn address_book(&self) -> Vec<Contact> {
+            self.book.clone()
+        }
+    }
+
+    fn anything(_: &str) -> bool {
+        true
+    }
+
+    fn nothing(_: &str) -> bool {
+        false
+    }
+
+    fn channel(name: &str) -> bool {
+        name.starts_with('#')
+    }
+
+    fn number(name: &str) -> bool {
+        name.starts_with('+')
+    }
+
+    fn multi<B: MsgBackend + 'static>(accounts: Vec<(&str, B)>) -> MultiBackend {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        MultiBackend {
+            accounts: accounts
+                .into_iter()
+                .map(|(label, backend)| Account {
+                    label: label.to_string(),
+                    backend: Box::new(backend),
+                })
+                .collect(),
+            last_activity: Arc::default(),
+            disconnected: Arc::default(),
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn message(phone: &str, hour: u32) -> Message {
+        let contact = Contact::new(phone.to_string(), phone.to_string());
+        Message::new(
+            contact,
+            String::from("hi"),
+            at(hour),
+            MessageDirection::From,
+        )
+    }
+
+    #[test]
+    fn joins_on_the_account_the_name_looks_like() {
+        // IRC takes any name as a nick, but a phone number belongs to Signal
+        let mut backend = multi(vec![
+            ("irc", FakeAccount::new(anything, channel)),
+            ("signal", FakeAccount::new(number, number)),
+        ]);
+
+        let contact = backend.join_conversation(" +15551234567 ").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("signal"));
+        let contact = backend.join_conversation("#rust").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("irc"));
+
+        // Nobody recognizes a nick, the first account that can joins it
+        let contact = backend.join_conversation("alice").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("irc"));
+
+        let contact = backend.join_conversation("irc:+15551234567").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("irc"));
+        assert_eq!(contact.phone, "+15551234567");
+    }
+
+    #[test]
+    fn needs_the_account_when_several_recognize_the_name() {
+        let mut backend = multi(vec![
+            ("signal", FakeAccount::new(number, number)),
+            ("modem", FakeAccount::new(number, number)),
+            ("plugin", FakeAccount::new(anything, nothing)),
+        ]);
+
+        let error = backend.join_conversation("+15551234567").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'+15551234567' could be on signal or modem, write it as signal:+15551234567"
+        );
+
+        let contact = backend.join_conversation("modem:+15551234567").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("modem"));
+        let contact = backend.join_conversation("alice").unwrap();
+        assert_eq!(contact.account.as_deref(), Some("plugin"));
+    }
+
+    #[test]
+    fn starts_conversations_on_an_account() {
+        // Neither can join a phone number, but Signal sends to one
+        let mut backend = multi(vec![
+            ("irc", FakeAccount::new(channel, channel)),
+            ("signal", FakeAccount::new(nothing, number)),
+        ]);
+
+        let typed = Contact::new(String::from("+15551234567"), String::from("+15551234567"));
+        let contact = backend.start_conversation(typed).unwrap();
+        assert_eq!(contact.account.as_deref(), Some("signal"));
+        backend.send_message(Message::new(
+            contact.clone(),
+            String::from("hello"),
+            at(9),
+            MessageDirection::To,
+        ));
+        assert_eq!(backend.get_messages(&contact, None).len(), 1);
+
+        // From the address book, keeping the name
+        let known = Contact::new(String::from("Ann"), String::from("signal:+15557654321"));
+        let contact = backend.start_conversation(known).unwrap();
+        assert_eq!(contact.name, "Ann");
+        assert_eq!(contact.phone, "+15557654321");
+        assert_eq!(contact.account.as_deref(), Some("signal"));
+
+        let contact = backend
+            .start_conversation(Contact::new(String::from("#rust"), String::from("#rust")))
+            .unwrap();
+        assert_eq!(contact.account.as_deref(), Some("irc"));
+
+        let unknown = Contact::new(String::from("alice"), String::from("alice"));
+        let error = backend.start_conversation(unknown.clone()).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("no account can start 'alice', write it as irc:alice"));
+
+        // With one account there is nothing to pick
+        let mut backend = multi(vec![("signal", FakeAccount::new(nothing, number))]);
+        let contact = backend.start_conversation(unknown).unwrap();
+        assert_eq!(contact.account.as_deref(), Some("signal"));
+    }
+
+    #[test]
+    fn orders_conversations_without_rereading_history() {
+        let mut irc = FakeAccount::new(anything, channel);
+        irc.messages = vec![message("#rust", 9), message("alice", 12)];
+        let irc_reads = irc.reads.clone();
+        let mut signal = FakeAccount::new(number, number);
+        signal.messages = vec![message("+15551234567", 10)];
+        let signal_reads = signal.reads.clone();
+        let mut backend = multi(vec![("irc", irc), ("signal", signal)]);
+
+        let phones = |backend: &MultiBackend| -> Vec<String> {
+            backend
+                .get_recent_contacts()
+                .into_iter()
+                .map(|contact| contact.phone)
+                .collect()
+        };
+        assert_eq!(phones(&backend), ["alice", "+15551234567", "#rust"]);
+        assert_eq!(phones(&backend), ["alice", "+15551234567", "#rust"]);
+        // Once per conversation
+        assert_eq!(irc_reads.load(Ordering::SeqCst), 2);
+        assert_eq!(signal_reads.load(Ordering::SeqCst), 1);
+
+        // Sending moves a conversation up without reading it again
+        let contact = Contact::new(String::from("#rust"), String::from("#rust"))
+            .with_account(String::from("irc"));
+        backend.send_message(Message::new(
+            contact,
+            String::from("hello"),
+            at(13),
+            MessageDirection::To,
+        ));
+        assert_eq!(phones(&backend), ["#rust", "alice", "+15551234567"]);
+        assert_eq!(irc_reads.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn lists_every_address_book() {
+        let mut irc = FakeAccount::new(anything, channel);
+        irc.book = vec![Contact::new(String::from("Ann"), String::from("ann"))];
+        let mut signal = FakeAccount::new(nothing, number);
+        signal.book = vec![Contact::new(
+            String::from("Ann"),
+            String::from("+15551234567"),
+        )];
+        let mut backend = multi(vec![("irc", irc), ("signal", signal)]);
+
+        let book = backend.address_book();
+        let handles: Vec<(&str, Option<&str>)> = book
+            .iter()
+            .map(|contact| (contact.phone.as_str(), contact.account.as_deref()))
+            .collect();
+        assert_eq!(
+            handles,
+            [("ann", Some("irc")), ("+15551234567", Some("signal"))]
+        );
+
+        // Starting from an entry stays on its account, even where another
+        // would take the handle
+        let contact = backend.start_conversation(book[1].clone()).unwrap();
+        assert_eq!(contact.account.as_deref(), Some("signal"));
+        assert_eq!(contact.name, "Ann");
+    }
+
+    #[tokio::test]
+    async fn disconnected_while_any_account_is() {
+        let mut backend = multi(vec![
+            ("mock", MockBackend::default()),
+            ("mock-2", MockBackend::default()),
+        ]);
+        let mut event_rx = backend.take_events().unwrap();
+        let down = |reason: &str| BackendEvent::Disconnected {
+            reason: reason.to_string(),
+        };
+
+        let changes = [
+            (1, down("gone"), Some("mock-2: gone")),
+            (0, down("down"), Some("mock-2: gone; mock: down")),
+            // Another account coming back does not reconnect this one
+            (0, BackendEvent::Connected, Some("mock-2: gone")),
+            (1, BackendEvent::Connected, None),
+        ];
+        for (index, change, expected) in changes {
+            let injector = backend.accounts[index].backend.injector().unwrap();
+            injector.inject(Injection::Event(change)).unwrap();
+
+            let event = timeout(WAIT, event_rx.recv()).await.unwrap().unwrap();
+            match (event, expected) {
+                (BackendEvent::Disconnected { reason }, Some(expected)) => {
+                    assert_eq!(reason, expected)
+                }
+                (BackendEvent::Connected, None) => {}
+                (event, expected) => panic!("got {:?}, expected {:?}", event, expected),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn injects_into_the_conversation_account() {
+        let mut backend = multi(vec![
+            ("mock", MockBackend::default()),
+            ("mock-2", MockBackend::default()),
+        ]);
+        let mut event_rx = backend.take_events().unwrap();
+
+        let contact = Contact::new(String::from("Joe Smith"), String::from("111-111-1111"))
+            .with_account(String::from("mock-2"));
+        let message = Message::new(
+            contact.clone(),
+            String::from("over here"),
+            at(9),
+            MessageDirection::From,
+        );
+        backend
+            .injector()
+            .unwrap()
+            .inject(Injection::Event(BackendEvent::MessageReceived(message)))
+            .unwrap();
+
+        let event = timeout(WAIT, event_rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(
+            event,
+            BackendEvent::MessageReceived(message)
+                if message.contact.account.as_deref() == Some("mock-2")
+        ));
+        assert_eq!(backend.get_messages(&contact, None).len(), 2);
+        let first = contact.with_account(String::from("mock"));
+        assert_eq!(backend.get_messages(&first, None).len(), 1);
+
+        // Accounts that are not there take nothing
+        let stranger = Contact::new(String::from("Joe Smith"), String::from("111-111-1111"))
+            .with_account(String::from("irc"));
+        let injection = Injection::Event(BackendEvent::Typing {
+            contact: stranger,
+            is_typing: true,
+        });
+        assert!(backend.injector().unwrap().inject(injection).is_err());
+    }
+}
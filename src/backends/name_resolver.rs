@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::{BackendEvent, EventInjector, MsgBackend};
+use crate::address_book::{self, AddressBook};
+use crate::state::{normalize_handle, Contact, Message};
+
+/// How often the address book files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+type Seen = HashMap<String, Contact>;
+
+/// Names the contacts of the backend it wraps from vCard files, for backends
+/// that only know handles. Handles that are not in the files are left as the
+/// backend has them.
+pub struct NameResolver {
+    inner: Box<dyn MsgBackend>,
+    /// Shared with the task that reads the files again when they change
+    book: Arc<RwLock<AddressBook>>,
+    /// The contacts as the backend has them, by normalized handle, so that
+    /// renames use the backend's handle and a contact whose card is removed
+    /// gets its own name back
+    seen: Arc<Mutex<Seen>>,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+    watcher: JoinHandle<()>,
+}
+
+impl NameResolver {
+    pub fn new(inner: Box<dyn MsgBackend>, paths: Vec<PathBuf>) -> anyhow::Result<Self> {
+        // Before reading, so that changes made meanwhile are read again
+        let modified = address_book::modified(&paths);
+        let book = Arc::new(RwLock::new(AddressBook::read(&paths)?));
+        let seen = Arc::default();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let watcher = tokio::spawn(watch(
+            paths,
+            modified,
+            book.clone(),
+            Arc::clone(&seen),
+            event_tx.clone(),
+        ));
+        Ok(Self {
+            inner,
+            book,
+            seen,
+            event_tx,
+            event_rx: Some(event_rx),
+            watcher,
+        })
+    }
+}
+
+/// Reads the files again whenever one of them changes, renaming the contacts
+/// whose name changed with it
+async fn watch(
+    paths: Vec<PathBuf>,
+    mut modified: Vec<Option<SystemTime>>,
+    book: Arc<RwLock<AddressBook>>,
+    seen: Arc<Mutex<Seen>>,
+    event_tx: UnboundedSender<BackendEvent>,
+) {
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = address_book::modified(&paths);
+        if now == modified {
+            continue;
+        }
+        modified = now;
+
+        // Files are often caught halfway through being written, the next
+        // change reads them again
+        let read = match AddressBook::read(&paths) {
+            Ok(read) => read,
+            Err(e) => {
+                event!(Level::WARN, "Keeping the old address book: {:#}", e);
+                continue;
+            }
+        };
+
+        let old = std::mem::replace(&mut *book.write().unwrap(), read);
+        let book = book.read().unwrap();
+        let seen = seen.lock().unwrap();
+        for (handle, name) in book.changes(&old) {
+            // Nothing shows a handle the backend never reported
+            let Some(contact) = seen.get(&normalize_handle(handle)) else {
+                continue;
+            };
+            let renamed = BackendEvent::ContactRenamed {
+                phone: contact.phone.clone(),
+                name: name.unwrap_or(&contact.name).to_string(),
+            };
+            if event_tx.send(renamed).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Remembers the contact as the backend has it, then names it
+fn name_contact(book: &AddressBook, seen: &mut Seen, contact: &mut Contact) {
+    seen.entry(normalize_handle(&contact.phone))
+        .or_insert_with(|| contact.clone());
+    if let Some(name) = book.name(&contact.phone) {
+        contact.name = name.to_string();
+    }
+}
+
+fn name_message(book: &AddressBook, seen: &mut Seen, message: &mut Message) {
+    name_contact(book, seen, &mut message.contact);
+    if let Some(sender) = &mut message.sender {
+        name_contact(book, seen, sender);
+    }
+    for reaction in &mut message.reactions {
+        name_contact(book, seen, &mut reaction.from);
+    }
+}
+
+fn name_event(book: &AddressBook, seen: &mut Seen, backend_event: &mut BackendEvent) {
+    match backend_event {
+        BackendEvent::MessageReceived(message) => name_message(book, seen, message),
+        BackendEvent::Typing { contact, .. } => name_contact(book, seen, contact),
+        BackendEvent::Reaction { from, .. } => name_contact(book, seen, from),
+        // The address book has the last word
+        BackendEvent::ContactRenamed { phone, name } => {
+            if let Some(contact) = seen.get_mut(&normalize_handle(phone)) {
+                contact.name = name.clone();
+            }
+            if let Some(known) = book.name(phone) {
+                *name = known.to_string();
+            }
+        }
+        _ => {}
+    }
+}
+
+impl MsgBackend for NameResolver {
+    fn send_message(&mut self, message: Message) {
+        self.inner.send_message(message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let mut messages = self.inner.get_messages(contact, n);
+        let book = self.book.read().unwrap();
+        let mut seen = self.seen.lock().unwrap();
+        for message in &mut messages {
+            name_message(&book, &mut seen, message);
+        }
+        messages
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let mut contacts = self.inner.get_recent_contacts();
+        let book = self.book.read().unwrap();
+        let mut seen = self.seen.lock().unwrap();
+        for contact in &mut contacts {
+            name_contact(&book, &mut seen, contact);
+        }
+        contacts
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        let event_rx = self.event_rx.take()?;
+        if let Some(mut inner_rx) = self.inner.take_events() {
+            let event_tx = self.event_tx.clone();
+            let book = self.book.clone();
+            let seen = self.seen.clone();
+            tokio::spawn(async move {
+                while let Some(mut backend_event) = inner_rx.recv().await {
+                    name_event(
+                        &book.read().unwrap(),
+                        &mut seen.lock().unwrap(),
+                        &mut backend_event,
+                    );
+                    if event_tx.send(backend_event).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Some(event_rx)
+    }
+
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        self.inner.injector()
+    }
+
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        let mut contact = self.inner.join_conversation(name)?;
+        name_contact(
+            &self.book.read().unwrap(),
+            &mut self.seen.lock().unwrap(),
+            &mut contact,
+        );
+        Ok(contact)
+    }
+
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        let mut contact = self.inner.start_conversation(contact)?;
+        name_contact(
+            &self.book.read().unwrap(),
+            &mut self.seen.lock().unwrap(),
+            &mut contact,
+        );
+        Ok(contact)
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        self.inner.recognizes(name)
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        self.inner.send_reaction(message, reaction)
+    }
+
+    fn address_book(&self) -> Vec<Contact> {
+        self.book.read().unwrap().entries().to_vec()
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        self.watcher.abort();
+        self.inner.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::backends::MockBackend;
+
+    /// Long enough for the watcher to notice a change
+    const WAIT: Duration = Duration::from_secs(10);
+
+    fn names(backend: &NameResolver) -> Vec<String> {
+        backend
+            .get_recent_contacts()
+            .into_iter()
+            .map(|contact| contact.name)
+            .collect()
+    }
+
+    async fn next_rename(events: &mut UnboundedReceiver<BackendEvent>) -> (String, String) {
+        loop {
+            match tokio::time::timeout(WAIT, events.recv()).await {
+                Ok(Some(BackendEvent::ContactRenamed { phone, name })) => return (phone, name),
+                Ok(Some(_)) => {}
+                _ => panic!("no contact renamed"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn renames_from_the_address_book() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("contacts.vcf");
+        fs::write(
+            &path,
+            "BEGIN:VCARD\nFN:Joseph\nTEL:+1 (111) 111-1111\nEND:VCARD\n",
+        )
+        .unwrap();
+
+        let mut backend =
+            NameResolver::new(Box::new(MockBackend::default()), vec![path.clone()]).unwrap();
+        let mut events = backend.take_events().unwrap();
+        assert_eq!(names(&backend), ["Joseph", "Ben Boy", "Becky Sue"]);
+
+        // Renamed in the file, under the handle as the backend has it
+        fs::write(&path, "BEGIN:VCARD\nFN:Joey\nTEL:111.111.1111\nEND:VCARD\n").unwrap();
+        assert_eq!(
+            next_rename(&mut events).await,
+            (String::from("111-111-1111"), String::from("Joey"))
+        );
+
+        // Removed from the file, back to the backend's own name
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            next_rename(&mut events).await,
+            (String::from("111-111-1111"), String::from("Joe Smith"))
+        );
+        assert_eq!(names(&backend), ["Joe Smith", "Ben Boy", "Becky Sue"]);
+
+        backend.close();
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, ensure, Context};
+use chrono::NaiveDateTime;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::rpc::RpcClient;
+use super::{BackendEvent, MsgBackend};
+use crate::state::{Contact, DeliveryStatus, Message, Reaction};
+
+mod protocol;
+mod session;
+
+use protocol::{
+    InitializeResult, Peer, WireMessage, CAPABILITY_JOIN, CAPABILITY_REACT, CAPABILITY_SEND,
+    HISTORY_LIMIT, PROTOCOL_VERSION,
+};
+use session::{Outgoing, Session};
+
+/// Programs that are not plugins never answer, no need to wait as long as
+/// for other calls
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A program to start as a backend, parsed from `plugin:<command line>` and
+/// split like a shell would without running one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginConfig {
+    pub command: Vec<String>,
+}
+
+impl FromStr for PluginConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("plugin:") else {
+            return Err(format!("'{s}' does not start with plugin:"));
+        };
+
+        let command = shlex::split(rest).ok_or_else(|| String::from("unbalanced quotes"))?;
+        if command.is_empty() {
+            return Err(String::from("expected plugin:<command>"));
+        }
+        Ok(Self { command })
+    }
+}
+
+impl fmt::Display for PluginConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = shlex::try_join(self.command.iter().map(String::as_str))
+            .unwrap_or_else(|_| self.command.join(" "));
+        write!(f, "plugin:{}", command)
+    }
+}
+
+/// The conversations the plugin listed and what happened in them since.
+/// Shared between the backend and the session task.
+#[derive(Default)]
+struct Conversations {
+    by_id: HashMap<String, Conversation>,
+}
+
+struct Conversation {
+    contact: Contact,
+    messages: Vec<Message>,
+    last_activity: NaiveDateTime,
+}
+
+impl Conversations {
+    fn open(&mut self, contact: &Contact) -> &mut Conversation {
+        self.by_id
+            .entry(contact.phone.clone())
+            .or_insert_with(|| Conversation {
+                contact: contact.clone(),
+                messages: Vec::new(),
+                last_activity: NaiveDateTime::default(),
+            })
+    }
+
+    fn push(&mut self, message: Message) {
+        let conversation = self.open(&message.contact);
+        conversation.last_activity = conversation.last_activity.max(message.timestamp);
+        conversation.messages.push(message);
+    }
+
+    fn find_message(&mut self, message_id: &str) -> Option<&mut Message> {
+        self.by_id
+            .values_mut()
+            .flat_map(|conversation| conversation.messages.iter_mut())
+            .find(|message| message.id == message_id)
+    }
+}
+
+/// Any service, through an external program speaking the plugin protocol
+pub struct PluginBackend {
+    name: String,
+    capabilities: Vec<String>,
+    rpc: Arc<RpcClient>,
+    conversations: Arc<Mutex<Conversations>>,
+    outgoing: UnboundedSender<Outgoing>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl PluginBackend {
+    /// Starts the plugin, agrees on the protocol version and loads the
+    /// conversations with their recent messages
+    pub async fn connect(config: PluginConfig) -> anyhow::Result<Self> {
+        let (program, arguments) = config
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("empty plugin command"))?;
+        let mut child = Command::new(program)
+            .args(arguments)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("could not start {}", program))?;
+
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            bail!("could not talk to {}", program);
+        };
+        let peer = program.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                event!(Level::DEBUG, "{}: {}", peer, line);
+            }
+        });
+        let (rpc, notifications) = RpcClient::start(program, stdout, stdin);
+
+        let handshake = call(
+            &rpc,
+            "initialize",
+            json!({
+                "protocol_version": PROTOCOL_VERSION,
+                "client": { "name": "chatty", "version": env!("CARGO_PKG_VERSION") },
+            }),
+        );
+        let initialized: InitializeResult = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| anyhow!("{} did not answer initialize in time", program))
+            .and_then(|result| result)
+            .context("handshake failed")?;
+        ensure!(
+            initialized.protocol_version == PROTOCOL_VERSION,
+            "{} speaks protocol version {}, expected {}",
+            program,
+            initialized.protocol_version,
+            PROTOCOL_VERSION
+        );
+
+        let peers: Vec<Peer> = call(&rpc, "list_conversations", json!({}))
+            .await
+            .context("could not list conversations")?;
+        let mut conversations = Conversations::default();
+        for peer in peers {
+            let contact = peer.contact();
+            let messages: Vec<WireMessage> = call(
+                &rpc,
+                "get_messages",
+                json!({ "conversation_id": peer.id, "limit": HISTORY_LIMIT }),
+            )
+            .await
+            .with_context(|| format!("could not load messages of {}", peer.name))?;
+
+            conversations.open(&contact);
+            for message in messages {
+                conversations.push(message.into_message(contact.clone()));
+            }
+        }
+        event!(
+            Level::INFO,
+            "Started {} ({}) with {} conversations and capabilities {:?}",
+            initialized.name,
+            config,
+            conversations.by_id.len(),
+            initialized.capabilities
+        );
+
+        let rpc = Arc::new(rpc);
+        let conversations = Arc::new(Mutex::new(conversations));
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let session = Session {
+            rpc: rpc.clone(),
+            notifications,
+            outgoing: outgoing_rx,
+            event_tx,
+            conversations: conversations.clone(),
+            child,
+        };
+
+        Ok(Self {
+            name: initialized.name,
+            capabilities: initialized.capabilities,
+            rpc,
+            conversations,
+            outgoing: outgoing_tx,
+            event_rx: Some(event_rx),
+            task: Some(tokio::spawn(session.run())),
+        })
+    }
+
+    fn can(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// Calls a method and reads its result as `T`
+async fn call<T: serde::de::DeserializeOwned>(
+    rpc: &RpcClient,
+    method: &str,
+    params: Value,
+) -> anyhow::Result<T> {
+    let result = rpc.call(method, params).await?;
+    serde_json::from_value(result).with_context(|| format!("malformed result of {}", method))
+}
+
+impl MsgBackend for PluginBackend {
+    fn send_message(&mut self, mut message: Message) {
+        if !self.can(CAPABILITY_SEND) {
+            message.status = DeliveryStatus::Failed(format!("{} can not send", self.name));
+        } else {
+            let outgoing = Outgoing::Send {
+                conversation_id: message.contact.phone.clone(),
+                message_id: message.id.clone(),
+                body: message.content.clone(),
+            };
+            if self.outgoing.send(outgoing).is_err() {
+                message.status = DeliveryStatus::Failed(format!("{} is not running", self.name));
+            }
+        }
+
+        self.conversations.lock().unwrap().push(message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let conversations = self.conversations.lock().unwrap();
+        let Some(conversation) = conversations.by_id.get(&contact.phone) else {
+            return Vec::new();
+        };
+
+        let messages = &conversation.messages;
+        let skip = n.map_or(0, |n| messages.len().saturating_sub(n as usize));
+        messages[skip..]
+            .iter()
+            .map(|message| Message {
+                contact: conversation.contact.clone(),
+                ..message.clone()
+            })
+            .collect()
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut recent: Vec<&Conversation> = conversations.by_id.values().collect();
+        recent.sort_by(|a, b| {
+            b.last_activity
+                .cmp(&a.last_activity)
+                .then_with(|| a.contact.name.cmp(&b.contact.name))
+        });
+        recent
+            .into_iter()
+            .map(|conversation| conversation.contact.clone())
+            .collect()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Asks the plugin to join, waiting for its answer since the plugin
+    /// decides what the conversation is called
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        if !self.can(CAPABILITY_JOIN) {
+            bail!("{} can not join '{}'", self.name, name);
+        }
+
+        let rpc = self.rpc.clone();
+        let params = json!({ "name": name.trim() });
+        let peer: Peer = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(call(&rpc, "join", params))
+        })?;
+
+        let contact = peer.contact();
+        let mut conversations = self.conversations.lock().unwrap();
+        let conversation = conversations.open(&contact);
+        conversation.contact.name = contact.name;
+        Ok(conversation.contact.clone())
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        if !self.can(CAPABILITY_REACT) {
+            bail!("{} does not support reactions", self.name);
+        }
+        self.outgoing
+            .send(Outgoing::React {
+                conversation_id: message.contact.phone.clone(),
+                message_id: message.id.clone(),
+                reaction: reaction.to_string(),
+            })
+            .map_err(|_| anyhow!("{} is not running", self.name))?;
+
+        // Shown right away, the plugin only answers whether it worked
+        let me = Contact::new(String::from("Me"), String::new());
+        let mut conversations = self.conversations.lock().unwrap();
+        if let Some(reacted) = conversations.find_message(&message.id) {
+            reacted.reactions.retain(|r| r.from != me);
+            reacted.reactions.push(Reaction {
+                from: me,
+                reaction: reaction.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        let _ = self.outgoing.send(Outgoing::Quit);
+        self.task.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::state::MessageDirection;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// `cargo test` builds the examples next to the test binary's directory
+    fn echo_plugin() -> PathBuf {
+        let test_binary = std::env::current_exe().unwrap();
+        let path = test_binary
+            .parent()
+            .and_then(|deps| deps.parent())
+            .unwrap()
+            .join("examples")
+            .join("echo_plugin");
+        assert!(
+            path.exists(),
+            "{} is missing, run cargo build --example echo_plugin",
+            path.display()
+        );
+        path
+    }
+
+    async fn next_event(event_rx: &mut UnboundedReceiver<BackendEvent>) -> BackendEvent {
+        timeout(WAIT, event_rx.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn runs_the_echo_plugin() {
+        let config = PluginConfig {
+            command: vec![echo_plugin().display().to_string()],
+        };
+        let mut backend = PluginBackend::connect(config).await.unwrap();
+        let mut event_rx = backend.take_events().unwrap();
+
+        // The handshake gave the name and capabilities, then the welcome
+        // message was loaded
+        assert_eq!(backend.name, "Echo");
+        assert!(backend.can(CAPABILITY_SEND));
+        assert!(backend.can(CAPABILITY_JOIN));
+        assert!(!backend.can(CAPABILITY_REACT));
+        let contacts = backend.get_recent_contacts();
+        assert_eq!(contacts.len(), 1);
+        let echo = contacts[0].clone();
+        assert_eq!(echo.phone, "echo");
+        let messages = backend.get_messages(&echo, None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].direction, MessageDirection::From);
+
+        // What is sent comes back, after the delivery receipt
+        backend.send_message(Message::new(
+            echo.clone(),
+            String::from("hello 👋"),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        ));
+        let sent_id = match next_event(&mut event_rx).await {
+            BackendEvent::DeliveryReceipt { message_id } => message_id,
+            event => panic!("unexpected {:?}", event),
+        };
+        match next_event(&mut event_rx).await {
+            BackendEvent::MessageReceived(message) => {
+                assert_eq!(message.content, "hello 👋");
+                assert_eq!(message.contact.phone, "echo");
+                assert_eq!(message.direction, MessageDirection::From);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        let messages = backend.get_messages(&echo, None);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1].id, sent_id);
+        assert_eq!(messages[1].status, DeliveryStatus::Delivered);
+
+        // Refused messages fail with the plugin's reason
+        let refused = Message::new(
+            echo.clone(),
+            String::from("fail"),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        );
+        let refused_id = refused.id.clone();
+        backend.send_message(refused);
+        match next_event(&mut event_rx).await {
+            BackendEvent::DeliveryFailed { message_id, reason } => {
+                assert_eq!(message_id, refused_id);
+                assert!(reason.contains("refusing to echo"), "{}", reason);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        // Joined conversations echo as well
+        let joined = backend.join_conversation(" other ").unwrap();
+        assert_eq!(joined.phone, "other");
+        assert_eq!(joined.name, "other");
+        assert!(backend.join_conversation(" ").is_err());
+        backend.send_message(Message::new(
+            joined.clone(),
+            String::from("again"),
+            NaiveDateTime::default(),
+            MessageDirection::To,
+        ));
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            BackendEvent::DeliveryReceipt { .. }
+        ));
+        match next_event(&mut event_rx).await {
+            BackendEvent::MessageReceived(message) => assert_eq!(message.contact.phone, "other"),
+            event => panic!("unexpected {:?}", event),
+        }
+        assert_eq!(backend.get_messages(&joined, None).len(), 2);
+
+        // Closing shuts the plugin down
+        timeout(WAIT, backend.close().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
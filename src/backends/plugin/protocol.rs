@@ -0,0 +1,137 @@
+//! The messages of the plugin protocol, described in docs/plugin-protocol.md
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::state::{Contact, DeliveryStatus, Message, MessageDirection};
+
+/// The version of the protocol this side speaks, a plugin must speak the same
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const CAPABILITY_SEND: &str = "send";
+pub const CAPABILITY_JOIN: &str = "join";
+pub const CAPABILITY_REACT: &str = "react";
+
+/// How many messages to load per conversation when starting
+pub const HISTORY_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct InitializeResult {
+    pub protocol_version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// A conversation or a contact, which look the same on the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub id: String,
+    pub name: String,
+}
+
+impl Peer {
+    pub fn contact(&self) -> Contact {
+        Contact::new(self.name.clone(), self.id.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WireMessage {
+    pub id: String,
+    pub body: String,
+    pub timestamp: i64,
+    pub outgoing: bool,
+    #[serde(default)]
+    pub sender: Option<Peer>,
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+}
+
+impl WireMessage {
+    pub fn into_message(self, conversation: Contact) -> Message {
+        let timestamp = DateTime::from_timestamp(self.timestamp, 0)
+            .map(|time| time.with_timezone(&Local).naive_local())
+            .unwrap_or_default();
+        let direction = if self.outgoing {
+            MessageDirection::To
+        } else {
+            MessageDirection::From
+        };
+
+        let mut message =
+            Message::new(conversation, self.body, timestamp, direction).with_id(self.id);
+        if let Some(sender) = self.sender {
+            message = message.with_sender(sender.contact());
+        }
+        message.attachments = self.attachments;
+        if let Some(status) = self
+            .status
+            .as_deref()
+            .and_then(|status| delivery_status(status, None))
+        {
+            message.status = status;
+        }
+        message
+    }
+}
+
+/// Maps a status on the wire to a delivery status
+pub fn delivery_status(status: &str, reason: Option<String>) -> Option<DeliveryStatus> {
+    match status {
+        "sent" => Some(DeliveryStatus::Sent),
+        "delivered" => Some(DeliveryStatus::Delivered),
+        "read" => Some(DeliveryStatus::Read),
+        "failed" => Some(DeliveryStatus::Failed(
+            reason.unwrap_or_else(|| String::from("failed")),
+        )),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SendResult {
+    pub message_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageReceived {
+    pub conversation: Peer,
+    pub message: WireMessage,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Delivery {
+    pub message_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Typing {
+    pub conversation_id: String,
+    pub typing: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Reacted {
+    pub message_id: String,
+    pub from: Peer,
+    pub reaction: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConversationRenamed {
+    pub conversation_id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Connection {
+    pub connected: bool,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,238 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::{
+    process::Child,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+use tracing::{event, Level};
+
+use super::protocol::{
+    delivery_status, Connection, ConversationRenamed, Delivery, MessageReceived, Reacted,
+    SendResult, Typing,
+};
+use super::{call, Conversations};
+use crate::backends::rpc::{Notification, RpcClient};
+use crate::backends::BackendEvent;
+use crate::state::{DeliveryStatus, Reaction};
+
+/// How long the plugin gets to exit after answering `shutdown`
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Requests from the backend to the session task
+#[derive(Debug)]
+pub enum Outgoing {
+    Send {
+        conversation_id: String,
+        message_id: String,
+        body: String,
+    },
+    React {
+        conversation_id: String,
+        message_id: String,
+        reaction: String,
+    },
+    Quit,
+}
+
+pub struct Session {
+    pub rpc: Arc<RpcClient>,
+    pub notifications: UnboundedReceiver<Notification>,
+    pub outgoing: UnboundedReceiver<Outgoing>,
+    pub event_tx: UnboundedSender<BackendEvent>,
+    pub conversations: Arc<Mutex<Conversations>>,
+    pub child: Child,
+}
+
+impl Session {
+    /// Applies notifications and sends messages until the backend quits or
+    /// the plugin exits
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                notification = self.notifications.recv() => match notification {
+                    Some(notification) => self.notification(notification),
+                    None => {
+                        let reason = String::from("plugin exited");
+                        event!(Level::WARN, "{}", reason);
+                        let _ = self.event_tx.send(BackendEvent::Disconnected { reason });
+                        return self.offline().await;
+                    }
+                },
+                outgoing = self.outgoing.recv() => match outgoing {
+                    Some(Outgoing::Send { conversation_id, message_id, body }) => {
+                        self.send(conversation_id, message_id, body).await;
+                    }
+                    Some(Outgoing::React { conversation_id, message_id, reaction }) => {
+                        let params = json!({
+                            "conversation_id": conversation_id,
+                            "message_id": message_id,
+                            "reaction": reaction,
+                        });
+                        if let Err(e) = self.rpc.call("react", params).await {
+                            event!(Level::WARN, "Could not react to {}: {:#}", message_id, e);
+                        }
+                    }
+                    Some(Outgoing::Quit) | None => return self.shutdown().await,
+                },
+            }
+        }
+    }
+
+    async fn shutdown(mut self) {
+        if let Err(e) = self.rpc.call("shutdown", json!({})).await {
+            event!(Level::DEBUG, "Plugin did not shut down: {:#}", e);
+        }
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.child.wait())
+            .await
+            .is_err()
+        {
+            event!(Level::DEBUG, "Killing plugin that did not exit");
+            let _ = self.child.kill().await;
+        }
+    }
+
+    /// Fails whatever is sent once the plugin is gone
+    async fn offline(mut self) {
+        while let Some(outgoing) = self.outgoing.recv().await {
+            if let Outgoing::Send { message_id, .. } = outgoing {
+                self.fail(message_id, String::from("plugin exited"));
+            }
+        }
+    }
+
+    async fn send(&mut self, conversation_id: String, message_id: String, body: String) {
+        let params = json!({
+            "conversation_id": conversation_id,
+            "message_id": message_id,
+            "body": body,
+        });
+        let result: anyhow::Result<Option<SendResult>> = call(&self.rpc, "send", params).await;
+
+        match result {
+            Ok(result) => {
+                let Some(id) = result.and_then(|result| result.message_id) else {
+                    return;
+                };
+                if let Some(message) = self.conversations.lock().unwrap().find_message(&message_id)
+                {
+                    message.id = id;
+                }
+            }
+            Err(e) => self.fail(message_id, format!("{:#}", e)),
+        }
+    }
+
+    fn fail(&self, message_id: String, reason: String) {
+        event!(Level::WARN, "Could not send {}: {}", message_id, reason);
+        if let Some(message) = self.conversations.lock().unwrap().find_message(&message_id) {
+            message.status = DeliveryStatus::Failed(reason.clone());
+        }
+        let _ = self
+            .event_tx
+            .send(BackendEvent::DeliveryFailed { message_id, reason });
+    }
+
+    fn notification(&mut self, notification: Notification) {
+        let backend_event = match notification.method.as_str() {
+            "message_received" => parse(notification.params).map(|received: MessageReceived| {
+                let contact = received.conversation.contact();
+                let message = received.message.into_message(contact.clone());
+
+                let mut conversations = self.conversations.lock().unwrap();
+                conversations.open(&contact).contact.name = contact.name.clone();
+                conversations.push(message.clone());
+                BackendEvent::MessageReceived(message)
+            }),
+            "delivery" => parse(notification.params).and_then(|delivery: Delivery| {
+                let status = delivery_status(&delivery.status, delivery.reason.clone())?;
+                if let Some(message) = self
+                    .conversations
+                    .lock()
+                    .unwrap()
+                    .find_message(&delivery.message_id)
+                {
+                    message.status = status.clone();
+                }
+
+                let message_id = delivery.message_id;
+                match status {
+                    DeliveryStatus::Sent => None,
+                    DeliveryStatus::Delivered => Some(BackendEvent::DeliveryReceipt { message_id }),
+                    DeliveryStatus::Read => Some(BackendEvent::ReadReceipt { message_id }),
+                    DeliveryStatus::Failed(reason) => {
+                        Some(BackendEvent::DeliveryFailed { message_id, reason })
+                    }
+                }
+            }),
+            "typing" => parse(notification.params).and_then(|typing: Typing| {
+                let conversations = self.conversations.lock().unwrap();
+                let conversation = conversations.by_id.get(&typing.conversation_id)?;
+                Some(BackendEvent::Typing {
+                    contact: conversation.contact.clone(),
+                    is_typing: typing.typing,
+                })
+            }),
+            "reaction" => parse(notification.params).map(|reacted: Reacted| {
+                let from = reacted.from.contact();
+                if let Some(message) = self
+                    .conversations
+                    .lock()
+                    .unwrap()
+                    .find_message(&reacted.message_id)
+                {
+                    message.reactions.retain(|r| r.from.phone != from.phone);
+                    message.reactions.push(Reaction {
+                        from: from.clone(),
+                        reaction: reacted.reaction.clone(),
+                    });
+                }
+                BackendEvent::Reaction {
+                    message_id: reacted.message_id,
+                    from,
+                    reaction: reacted.reaction,
+                }
+            }),
+            "conversation_renamed" => {
+                parse(notification.params).and_then(|renamed: ConversationRenamed| {
+                    let mut conversations = self.conversations.lock().unwrap();
+                    let conversation = conversations.by_id.get_mut(&renamed.conversation_id)?;
+                    conversation.contact.name = renamed.name.clone();
+                    Some(BackendEvent::ContactRenamed {
+                        phone: renamed.conversation_id,
+                        name: renamed.name,
+                    })
+                })
+            }
+            "connection" => parse(notification.params).map(|connection: Connection| {
+                if connection.connected {
+                    BackendEvent::Connected
+                } else {
+                    BackendEvent::Disconnected {
+                        reason: connection
+                            .reason
+                            .unwrap_or_else(|| String::from("disconnected")),
+                    }
+                }
+            }),
+            method => {
+                event!(Level::DEBUG, "Ignoring unknown notification {}", method);
+                None
+            }
+        };
+
+        if let Some(backend_event) = backend_event {
+            let _ = self.event_tx.send(backend_event);
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Option<T> {
+    serde_json::from_value(params)
+        .map_err(|e| event!(Level::WARN, "Ignoring malformed notification: {}", e))
+        .ok()
+}
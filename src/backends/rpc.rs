@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+use tracing::{event, Level};
+
+/// Peers such as signal-cli talk to their servers before answering, which can
+/// be slow
+const CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+type PendingCalls = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>;
+
+/// A request the peer sent without expecting an answer
+#[derive(Debug, Deserialize)]
+pub struct Notification {
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Value,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// A JSON-RPC 2.0 client over a stream of newline-delimited JSON, which is
+/// how signal-cli and plugins speak
+pub struct RpcClient {
+    /// Who is on the other end, for errors and logs
+    peer: String,
+    lines: UnboundedSender<String>,
+    pending: PendingCalls,
+    next_id: AtomicU64,
+}
+
+impl RpcClient {
+    /// Starts reading and writing on the stream. Notifications arrive on the
+    /// returned channel, which closes when the peer goes away.
+    pub fn start(
+        peer: &str,
+        reader: impl AsyncRead + Unpin + Send + 'static,
+        writer: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> (Self, UnboundedReceiver<Notification>) {
+        let (lines_tx, lines_rx) = mpsc::unbounded_channel();
+        let (notification_tx, notification_rx) = mpsc::unbounded_channel();
+        let pending = PendingCalls::default();
+
+        tokio::spawn(write_lines(peer.to_string(), writer, lines_rx));
+        tokio::spawn(read_lines(
+            peer.to_string(),
+            reader,
+            pending.clone(),
+            notification_tx,
+        ));
+
+        let client = Self {
+            peer: peer.to_string(),
+            lines: lines_tx,
+            pending,
+            next_id: AtomicU64::new(1),
+        };
+        (client, notification_rx)
+    }
+
+    pub async fn call(&self, method: &str, params: Value) -> anyhow::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, response_tx);
+        if self.lines.send(request.to_string()).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            bail!("connection to {} is closed", self.peer);
+        }
+
+        let response = tokio::time::timeout(CALL_TIMEOUT, response_rx).await;
+        match response {
+            Ok(Ok(Ok(result))) => Ok(result),
+            Ok(Ok(Err(error))) => Err(anyhow!("{}", error)),
+            Ok(Err(_)) => Err(anyhow!("connection to {} was closed", self.peer)),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(anyhow!("{} did not answer {} in time", self.peer, method))
+            }
+        }
+    }
+}
+
+async fn write_lines(
+    peer: String,
+    mut writer: impl AsyncWrite + Unpin,
+    mut lines: UnboundedReceiver<String>,
+) {
+    while let Some(line) = lines.recv().await {
+        event!(Level::TRACE, "{} >> {}", peer, line);
+        let result = async {
+            writer.write_all(line.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await
+        }
+        .await;
+
+        if let Err(e) = result {
+            event!(Level::WARN, "Could not write to {}: {}", peer, e);
+            return;
+        }
+    }
+}
+
+async fn read_lines(
+    peer: String,
+    reader: impl AsyncRead + Unpin,
+    pending: PendingCalls,
+    notifications: UnboundedSender<Notification>,
+) {
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                event!(Level::WARN, "Could not read from {}: {}", peer, e);
+                break;
+            }
+        };
+        event!(Level::TRACE, "{} << {}", peer, line);
+
+        let value: Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(e) => {
+                event!(
+                    Level::DEBUG,
+                    "Ignoring line that is not JSON ({}): {}",
+                    e,
+                    line
+                );
+                continue;
+            }
+        };
+
+        if value.get("method").is_some() {
+            match serde_json::from_value(value) {
+                Ok(notification) => {
+                    let _ = notifications.send(notification);
+                }
+                Err(e) => event!(Level::DEBUG, "Ignoring malformed notification: {}", e),
+            }
+            continue;
+        }
+
+        let response: Response = match serde_json::from_value(value) {
+            Ok(response) => response,
+            Err(e) => {
+                event!(Level::DEBUG, "Ignoring malformed response: {}", e);
+                continue;
+            }
+        };
+        let Some(id) = response.id.as_u64() else {
+            continue;
+        };
+        let Some(response_tx) = pending.lock().unwrap().remove(&id) else {
+            continue;
+        };
+
+        let result = match response.error {
+            Some(error) => Err(format!("{} ({})", error.message, error.code)),
+            None => Ok(response.result.unwrap_or(Value::Null)),
+        };
+        let _ = response_tx.send(result);
+    }
+
+    // Dropping the waiting senders fails every call still in flight
+    pending.lock().unwrap().clear();
+}
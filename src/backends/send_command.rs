@@ -0,0 +1,410 @@
+use std::{
+    fmt,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use tokio::{
+    process::Command,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::{BackendEvent, EventInjector, MsgBackend};
+use crate::state::{normalize_handle, Contact, DeliveryStatus, Message};
+
+/// Commands such as `osascript` can hang on a permission prompt
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Placeholders filled in each argument of the template
+const PLACEHOLDERS: [&str; 3] = ["{to}", "{name}", "{body}"];
+
+/// A command to send messages with, split like a shell would but never run
+/// through one. `{to}`, `{name}` and `{body}` in any argument are replaced
+/// with the recipient's handle and name and the message text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendCommand {
+    arguments: Vec<String>,
+}
+
+impl FromStr for SendCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let arguments = shlex::split(s).ok_or_else(|| String::from("unbalanced quotes"))?;
+        if arguments.is_empty() {
+            return Err(String::from("empty command"));
+        }
+        Ok(Self { arguments })
+    }
+}
+
+impl fmt::Display for SendCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let joined = shlex::try_join(self.arguments.iter().map(String::as_str))
+            .unwrap_or_else(|_| self.arguments.join(" "));
+        write!(f, "{}", joined)
+    }
+}
+
+impl SendCommand {
+    /// The program and its arguments with the placeholders filled in for the
+    /// message. Each argument is filled in one pass, so text in the message
+    /// that looks like a placeholder is left alone.
+    fn arguments(&self, message: &Message) -> Vec<String> {
+        let values = [
+            message.contact.phone.as_str(),
+            message.contact.name.as_str(),
+            message.content.as_str(),
+        ];
+
+        self.arguments
+            .iter()
+            .map(|argument| {
+                let mut filled = String::new();
+                let mut rest = argument.as_str();
+                'scan: while !rest.is_empty() {
+                    for (placeholder, value) in PLACEHOLDERS.iter().zip(values) {
+                        if let Some(after) = rest.strip_prefix(placeholder) {
+                            filled.push_str(value);
+                            rest = after;
+                            continue 'scan;
+                        }
+                    }
+                    let mut chars = rest.chars();
+                    filled.extend(chars.next());
+                    rest = chars.as_str();
+                }
+                filled
+            })
+            .collect()
+    }
+
+    /// Runs the command, failing with the last thing it printed to stderr
+    /// when it exits unsuccessfully
+    async fn run(&self, message: &Message) -> anyhow::Result<()> {
+        let arguments = self.arguments(message);
+        let (program, arguments) = arguments
+            .split_first()
+            .ok_or_else(|| anyhow!("empty command"))?;
+
+        let child = Command::new(program)
+            .args(arguments)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("could not run {}", program))?;
+
+        let output = tokio::time::timeout(COMMAND_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("{} did not finish in time", program))?
+            .with_context(|| format!("could not run {}", program))?;
+        if output.status.success() {
+            return Ok(());
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .map(str::trim)
+            .rfind(|line| !line.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{} {}", program, output.status));
+        Err(anyhow!(reason))
+    }
+}
+
+/// Sends messages by running a command, for tools such as `osascript` or
+/// `kdeconnect-cli` that can send but not read. Everything else, history
+/// included, comes from the backend it wraps.
+pub struct CommandSender {
+    inner: Box<dyn MsgBackend>,
+    command: Arc<SendCommand>,
+    /// Messages sent through the command, which the wrapped backend does not
+    /// know about
+    sent: Arc<Mutex<Vec<Message>>>,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl CommandSender {
+    pub fn new(inner: Box<dyn MsgBackend>, command: SendCommand) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            inner,
+            command: Arc::new(command),
+            sent: Arc::default(),
+            event_tx,
+            event_rx: Some(event_rx),
+            tasks: Vec::new(),
+        }
+    }
+}
+
+impl MsgBackend for CommandSender {
+    fn send_message(&mut self, message: Message) {
+        let command = self.command.clone();
+        let sent = self.sent.clone();
+        let event_tx = self.event_tx.clone();
+        let to_send = message.clone();
+        self.sent.lock().unwrap().push(message);
+
+        self.tasks.retain(|task| !task.is_finished());
+        self.tasks.push(tokio::spawn(async move {
+            let message_id = to_send.id.clone();
+            let (status, backend_event) = match command.run(&to_send).await {
+                // The command exiting cleanly is all there is to go on
+                Ok(()) => {
+                    event!(Level::DEBUG, "Sent {} with {}", message_id, command);
+                    (
+                        DeliveryStatus::Delivered,
+                        BackendEvent::DeliveryReceipt { message_id },
+                    )
+                }
+                Err(e) => {
+                    let reason = format!("{:#}", e);
+                    event!(Level::WARN, "Could not send {}: {}", message_id, reason);
+                    (
+                        DeliveryStatus::Failed(reason.clone()),
+                        BackendEvent::DeliveryFailed { message_id, reason },
+                    )
+                }
+            };
+
+            if let Some(message) = sent
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .find(|message| message.id == to_send.id)
+            {
+                message.status = status;
+            }
+            let _ = event_tx.send(backend_event);
+        }));
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let handle = normalize_handle(&contact.phone);
+        let mut messages = self.inner.get_messages(contact, None);
+        messages.extend(
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|message| normalize_handle(&message.contact.phone) == handle)
+                .cloned(),
+        );
+        messages.sort_by_key(|message| message.timestamp);
+
+        let skip = n.map_or(0, |n| messages.len().saturating_sub(n as usize));
+        messages.split_off(skip)
+    }
+
+    /// The wrapped backend's conversations, after any started by sending to
+    /// someone it does not know
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let known = self.inner.get_recent_contacts();
+        let mut contacts: Vec<Contact> = Vec::new();
+
+        for message in self.sent.lock().unwrap().iter().rev() {
+            let handle = normalize_handle(&message.contact.phone);
+            let is_new = |contact: &Contact| normalize_handle(&contact.phone) != handle;
+            if known.iter().all(is_new) && contacts.iter().all(is_new) {
+                contacts.push(message.contact.clone());
+            }
+        }
+
+        contacts.extend(known);
+        contacts
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        let event_rx = self.event_rx.take()?;
+        if let Some(mut inner_rx) = self.inner.take_events() {
+            let event_tx = self.event_tx.clone();
+            tokio::spawn(async move {
+                while let Some(backend_event) = inner_rx.recv().await {
+                    if event_tx.send(backend_event).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Some(event_rx)
+    }
+
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        self.inner.injector()
+    }
+
+    /// The command decides who it can send to, so anyone the wrapped backend
+    /// can not join is still a valid recipient
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        let name = name.trim();
+        Ok(self
+            .inner
+            .join_conversation(name)
+            .unwrap_or_else(|_| Contact::new(name.to_string(), name.to_string())))
+    }
+
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        Ok(self
+            .inner
+            .start_conversation(contact.clone())
+            .unwrap_or(contact))
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        self.inner.recognizes(name)
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        self.inner.send_reaction(message, reaction)
+    }
+
+    fn address_book(&self) -> Vec<Contact> {
+        self.inner.address_book()
+    }
+
+    /// Waits for the commands still running, then for the wrapped backend
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        let tasks = std::mem::take(&mut self.tasks);
+        let inner = self.inner.close();
+        Some(tokio::spawn(async move {
+            for task in tasks.into_iter().chain(inner) {
+                let _ = task.await;
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::backends::MockBackend;
+    use crate::state::MessageDirection;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn message(content: &str) -> Message {
+        Message::new(
+            Contact::new(String::from("Joe {body}"), String::from("+15551234567")),
+            content.to_string(),
+            DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
+            MessageDirection::To,
+        )
+    }
+
+    fn command(s: &str) -> SendCommand {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        assert_eq!(
+            command(r#"kdeconnect-cli --destination "{to}" --message '{body}'"#).arguments,
+            [
+                "kdeconnect-cli",
+                "--destination",
+                "{to}",
+                "--message",
+                "{body}"
+            ]
+        );
+        assert_eq!(
+            command(r#"say "two words" three\ words"#).arguments,
+            ["say", "two words", "three words"]
+        );
+        assert_eq!(
+            "send 'unbalanced".parse::<SendCommand>(),
+            Err(String::from("unbalanced quotes"))
+        );
+        assert_eq!(
+            "send \"unbalanced".parse::<SendCommand>(),
+            Err(String::from("unbalanced quotes"))
+        );
+        assert_eq!(
+            "".parse::<SendCommand>(),
+            Err(String::from("empty command"))
+        );
+        assert_eq!(
+            "   ".parse::<SendCommand>(),
+            Err(String::from("empty command"))
+        );
+    }
+
+    #[test]
+    fn fills_placeholders_once() {
+        let send = command("send --to={to} {name}: {body}");
+        assert_eq!(
+            send.arguments(&message("hi {to}")),
+            ["send", "--to=+15551234567", "Joe {body}:", "hi {to}"]
+        );
+
+        // Never seen by a shell, so nothing in the message is run
+        let send = command("sh -c 'echo sent' {body}");
+        assert_eq!(
+            send.arguments(&message("$(rm -rf ~); `id` {name}")),
+            ["sh", "-c", "echo sent", "$(rm -rf ~); `id` {name}"]
+        );
+
+        // Unknown placeholders and unicode are kept as written
+        let send = command("send {nobody} \u{e9}t\u{e9}{body}");
+        assert_eq!(
+            send.arguments(&message("!")),
+            ["send", "{nobody}", "\u{e9}t\u{e9}!"]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_how_the_command_went() {
+        let mut backend = CommandSender::new(
+            Box::new(MockBackend::default()),
+            command("sh -c 'test \"$0\" = ok || { echo \"no good\" >&2; exit 1; }' {body}"),
+        );
+        let mut events = backend.take_events().unwrap();
+
+        let sent = message("ok");
+        backend.send_message(sent.clone());
+        let Ok(Some(BackendEvent::DeliveryReceipt { message_id })) =
+            tokio::time::timeout(WAIT, events.recv()).await
+        else {
+            panic!("no delivery receipt");
+        };
+        assert_eq!(message_id, sent.id);
+
+        let failed = message("not ok");
+        backend.send_message(failed.clone());
+        let Ok(Some(BackendEvent::DeliveryFailed { message_id, reason })) =
+            tokio::time::timeout(WAIT, events.recv()).await
+        else {
+            panic!("no delivery failure");
+        };
+        assert_eq!(message_id, failed.id);
+        assert_eq!(reason, "no good");
+
+        let statuses: Vec<DeliveryStatus> = backend
+            .get_messages(&sent.contact, None)
+            .into_iter()
+            .map(|message| message.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                DeliveryStatus::Delivered,
+                DeliveryStatus::Failed(String::from("no good"))
+            ]
+        );
+    }
+}
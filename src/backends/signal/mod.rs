@@ -0,0 +1,747 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use chrono::NaiveDateTime;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::UnixStream,
+    process::Command,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+use tracing::{event, Level};
+
+use super::{BackendEvent, MsgBackend};
+use crate::state::{Contact, DeliveryStatus, Message, Reaction};
+
+mod session;
+
+use super::rpc::RpcClient;
+use session::{Outgoing, Session};
+
+/// Group conversations are told apart from contacts by this prefix on the id
+const GROUP_PREFIX: &str = "group:";
+
+/// The Signal account and how to reach signal-cli, parsed from
+/// `signal:<number>` to start `signal-cli jsonRpc` or
+/// `signal:<number>@<socket>` to use a running `signal-cli daemon --socket`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignalConfig {
+    pub account: String,
+    pub socket: Option<PathBuf>,
+}
+
+impl FromStr for SignalConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("signal:") else {
+            return Err(format!("'{s}' does not start with signal:"));
+        };
+
+        let (account, socket) = match rest.split_once('@') {
+            Some((account, socket)) if !socket.is_empty() => (account, Some(PathBuf::from(socket))),
+            Some(_) => return Err(String::from("missing socket path after @")),
+            None => (rest, None),
+        };
+        if !account.starts_with('+') || account.len() < 2 {
+            return Err(format!(
+                "'{account}' is not a phone number in international format"
+            ));
+        }
+
+        Ok(Self {
+            account: account.to_string(),
+            socket,
+        })
+    }
+}
+
+impl fmt::Display for SignalConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "signal:{}", self.account)?;
+        if let Some(socket) = &self.socket {
+            write!(f, "@{}", socket.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Signal has no message ids, a message is known by its author and the time
+/// it was sent
+fn message_id(author: &str, sent_at: i64) -> String {
+    format!("{}:{}", author, sent_at)
+}
+
+/// Whether `number` is a phone number in international format, which is
+/// how Signal knows people
+fn is_number(number: &str) -> bool {
+    number
+        .strip_prefix('+')
+        .is_some_and(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The conversations seen since starting, signal-cli keeps no history.
+/// Shared between the backend and the session task.
+#[derive(Default)]
+struct Conversations {
+    by_id: HashMap<String, Conversation>,
+}
+
+struct Conversation {
+    contact: Contact,
+    messages: Vec<Message>,
+    last_activity: NaiveDateTime,
+}
+
+impl Conversations {
+    fn open(&mut self, contact: &Contact) -> &mut Conversation {
+        self.by_id
+            .entry(contact.phone.clone())
+            .or_insert_with(|| Conversation {
+                contact: contact.clone(),
+                messages: Vec::new(),
+                last_activity: NaiveDateTime::default(),
+            })
+    }
+
+    fn push(&mut self, contact: &Contact, message: Message) {
+        let conversation = self.open(contact);
+        conversation.last_activity = conversation.last_activity.max(message.timestamp);
+        conversation.messages.push(message);
+    }
+
+    fn find_message(&mut self, message_id: &str) -> Option<&mut Message> {
+        self.by_id
+            .values_mut()
+            .flat_map(|conversation| conversation.messages.iter_mut())
+            .find(|message| message.id == message_id)
+    }
+}
+
+/// Signal through signal-cli's JSON-RPC interface
+pub struct SignalBackend {
+    account: String,
+    conversations: Arc<Mutex<Conversations>>,
+    outgoing: UnboundedSender<Outgoing>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl SignalBackend {
+    /// Connects to signal-cli and loads the contacts and groups of the account
+    pub async fn connect(config: SignalConfig) -> anyhow::Result<Self> {
+        let mut child = None;
+        let (rpc, notifications) = match &config.socket {
+            Some(socket) => {
+                let stream = UnixStream::connect(socket)
+                    .await
+                    .with_context(|| format!("could not connect to {}", socket.display()))?;
+                let (reader, writer) = stream.into_split();
+                RpcClient::start("signal-cli", reader, writer)
+            }
+            None => {
+                let mut process = Command::new("signal-cli")
+                    .args(["-a", &config.account, "jsonRpc"])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .context("could not start signal-cli")?;
+
+                let (Some(stdin), Some(stdout), Some(stderr)) = (
+                    process.stdin.take(),
+                    process.stdout.take(),
+                    process.stderr.take(),
+                ) else {
+                    bail!("could not talk to signal-cli");
+                };
+                // Its logging would draw over the TUI
+                tokio::spawn(async move {
+                    let mut lines = BufReader::new(stderr).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        event!(Level::DEBUG, "signal-cli: {}", line);
+                    }
+                });
+
+                child = Some(process);
+                RpcClient::start("signal-cli", stdout, stdin)
+            }
+        };
+
+        let send_account = config.socket.is_some();
+        let account_params = if send_account {
+            json!({ "account": config.account })
+        } else {
+            json!({})
+        };
+        let contacts = rpc
+            .call("listContacts", account_params.clone())
+            .await
+            .context("could not list contacts")?;
+        let groups = rpc
+            .call("listGroups", account_params)
+            .await
+            .context("could not list groups")?;
+
+        let mut names = HashMap::new();
+        let mut conversations = Conversations::default();
+        for contact in contacts.as_array().into_iter().flatten() {
+            let Some(address) = contact["number"].as_str().or(contact["uuid"].as_str()) else {
+                continue;
+            };
+            let name = contact_name(contact).unwrap_or_else(|| address.to_string());
+            names.insert(address.to_string(), name.clone());
+            conversations.open(&Contact::new(name, address.to_string()));
+        }
+        for group in groups.as_array().into_iter().flatten() {
+            let Some(group_id) = group["id"].as_str() else {
+                continue;
+            };
+            let id = format!("{}{}", GROUP_PREFIX, group_id);
+            let name = group["name"]
+                .as_str()
+                .filter(|name| !name.is_empty())
+                .unwrap_or("Group")
+                .to_string();
+            names.insert(id.clone(), name.clone());
+            conversations.open(&Contact::new(name, id));
+        }
+        event!(
+            Level::INFO,
+            "Connected to {} with {} conversations",
+            config,
+            conversations.by_id.len()
+        );
+
+        let conversations = Arc::new(Mutex::new(conversations));
+        let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let session = Session {
+            rpc,
+            notifications,
+            outgoing: outgoing_rx,
+            event_tx,
+            conversations: conversations.clone(),
+            account: config.account.clone(),
+            send_account,
+            names,
+            child,
+        };
+
+        Ok(Self {
+            account: config.account,
+            conversations,
+            outgoing: outgoing_tx,
+            event_rx: Some(event_rx),
+            task: Some(tokio::spawn(session.run())),
+        })
+    }
+}
+
+/// The name saved in the address book, falling back to the profile name
+fn contact_name(contact: &Value) -> Option<String> {
+    if let Some(name) = contact["name"].as_str().filter(|name| !name.is_empty()) {
+        return Some(name.to_string());
+    }
+
+    let profile = &contact["profile"];
+    let name = [&profile["givenName"], &profile["familyName"]]
+        .iter()
+        .filter_map(|part| part.as_str())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!name.is_empty()).then_some(name)
+}
+
+impl MsgBackend for SignalBackend {
+    fn send_message(&mut self, mut message: Message) {
+        let outgoing = Outgoing::Send {
+            conversation_id: message.contact.phone.clone(),
+            message_id: message.id.clone(),
+            body: message.content.clone(),
+        };
+        if self.outgoing.send(outgoing).is_err() {
+            message.status = DeliveryStatus::Failed(String::from("signal-cli is not running"));
+        }
+
+        let contact = message.contact.clone();
+        self.conversations.lock().unwrap().push(&contact, message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let conversations = self.conversations.lock().unwrap();
+        let Some(conversation) = conversations.by_id.get(&contact.phone) else {
+            return Vec::new();
+        };
+
+        let messages = &conversation.messages;
+        let skip = n.map_or(0, |n| messages.len().saturating_sub(n as usize));
+        messages[skip..].to_vec()
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let conversations = self.conversations.lock().unwrap();
+        let mut recent: Vec<&Conversation> = conversations.by_id.values().collect();
+        recent.sort_by(|a, b| {
+            b.last_activity
+                .cmp(&a.last_activity)
+                .then_with(|| a.contact.name.cmp(&b.contact.name))
+        });
+        recent
+            .into_iter()
+            .map(|conversation| conversation.contact.clone())
+            .collect()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        self.event_rx.take()
+    }
+
+    /// Starts a conversation with a phone number that is not a contact yet
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        let number = name.trim();
+        if !is_number(number) {
+            bail!("'{}' is not a phone number in international format", number);
+        }
+
+        let contact = Contact::new(number.to_string(), number.to_string());
+        Ok(self
+            .conversations
+            .lock()
+            .unwrap()
+            .open(&contact)
+            .contact
+            .clone())
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        is_number(name.trim())
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        self.outgoing
+            .send(Outgoing::React {
+                conversation_id: message.contact.phone.clone(),
+                message_id: message.id.clone(),
+                emoji: reaction.to_string(),
+            })
+            .context("signal-cli is not running")?;
+
+        // Shown right away, Signal does not confirm reactions
+        let mut conversations = self.conversations.lock().unwrap();
+        if let Some(reacted) = conversations.find_message(&message.id) {
+            reacted.reactions.retain(|r| r.from.phone != self.account);
+            reacted.reactions.push(Reaction {
+                from: Contact::new(String::from("Me"), self.account.clone()),
+                reaction: reaction.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        let _ = self.outgoing.send(Outgoing::Quit);
+        self.task.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncWriteExt, Lines},
+        net::{
+            unix::{OwnedReadHalf, OwnedWriteHalf},
+            UnixListener,
+        },
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::state::MessageDirection;
+
+    const ACCOUNT: &str = "+15550000000";
+    const ALICE: &str = "+15551111111";
+    const BOB: &str = "+15552222222";
+    const GROUP_ID: &str = "Z3JvdXA=";
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    /// signal-cli as seen through its socket, following a script
+    struct FakeSignalCli {
+        lines: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    impl FakeSignalCli {
+        /// Reads the next request, which has to be a call to `method`.
+        /// Returns its id and params.
+        async fn expect_call(&mut self, method: &str) -> (Value, Value) {
+            let line = timeout(WAIT, self.lines.next_line())
+                .await
+                .unwrap_or_else(|_| panic!("timed out waiting for {method}"))
+                .unwrap()
+                .unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+            assert_eq!(request["jsonrpc"], "2.0");
+            assert_eq!(request["method"], method, "{request}");
+            (request["id"].clone(), request["params"].clone())
+        }
+
+        async fn write(&mut self, value: Value) {
+            let line = format!("{value}\n");
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+        }
+
+        async fn respond(&mut self, id: Value, result: Value) {
+            self.write(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+                .await;
+        }
+
+        async fn receive(&mut self, envelope: Value) {
+            self.write(json!({
+                "jsonrpc": "2.0",
+                "method": "receive",
+                "params": { "envelope": envelope, "account": ACCOUNT },
+            }))
+            .await;
+        }
+    }
+
+    async fn next_event(event_rx: &mut UnboundedReceiver<BackendEvent>) -> BackendEvent {
+        timeout(WAIT, event_rx.recv()).await.unwrap().unwrap()
+    }
+
+    /// Waits for the session task to give a sent message the id Signal knows
+    /// it by
+    async fn wait_for_id(backend: &SignalBackend, contact: &Contact, id: &str) -> Message {
+        timeout(WAIT, async {
+            loop {
+                let messages = backend.get_messages(contact, None);
+                if let Some(message) = messages.iter().find(|m| m.id == id) {
+                    return message.clone();
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    fn outgoing(contact: &Contact, text: &str) -> Message {
+        Message::new(
+            contact.clone(),
+            String::from(text),
+            chrono::offset::Local::now().naive_local(),
+            MessageDirection::To,
+        )
+    }
+
+    /// Connects through a socket, answering the calls made on startup
+    async fn connect() -> (SignalBackend, FakeSignalCli, tempfile::TempDir) {
+        let directory = tempfile::tempdir().unwrap();
+        let socket = directory.path().join("signal-cli.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let config: SignalConfig = format!("signal:{ACCOUNT}@{}", socket.display())
+            .parse()
+            .unwrap();
+
+        let connecting = tokio::spawn(SignalBackend::connect(config));
+        let (stream, _) = timeout(WAIT, listener.accept()).await.unwrap().unwrap();
+        let (reader, writer) = stream.into_split();
+        let mut peer = FakeSignalCli {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+
+        let (id, params) = peer.expect_call("listContacts").await;
+        assert_eq!(params, json!({ "account": ACCOUNT }));
+        peer.respond(
+            id,
+            json!([
+                { "number": ALICE, "name": "Alice" },
+                { "number": BOB, "name": "", "profile": { "givenName": "Bob", "familyName": "Builder" } },
+            ]),
+        )
+        .await;
+        let (id, _) = peer.expect_call("listGroups").await;
+        peer.respond(id, json!([{ "id": GROUP_ID, "name": "Friends" }]))
+            .await;
+
+        let backend = connecting.await.unwrap().unwrap();
+        (backend, peer, directory)
+    }
+
+    #[test]
+    fn parses_config() {
+        let config: SignalConfig = "signal:+15550000000".parse().unwrap();
+        assert_eq!(config.socket, None);
+        let config: SignalConfig = "signal:+15550000000@/run/signal.sock".parse().unwrap();
+        assert_eq!(config.socket, Some(PathBuf::from("/run/signal.sock")));
+        assert_eq!(config.to_string(), "signal:+15550000000@/run/signal.sock");
+
+        assert!("signal:15550000000".parse::<SignalConfig>().is_err());
+        assert!("signal:+15550000000@".parse::<SignalConfig>().is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_contacts_and_groups() {
+        let (mut backend, _peer, _directory) = connect().await;
+
+        let mut names: Vec<String> = backend
+            .get_recent_contacts()
+            .into_iter()
+            .map(|contact| contact.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Alice", "Bob Builder", "Friends"]);
+        backend.close().unwrap().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn receives_messages() {
+        let (mut backend, mut peer, _directory) = connect().await;
+        let mut event_rx = backend.take_events().unwrap();
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "sourceName": "Alice",
+            "dataMessage": { "timestamp": 1000, "message": "hi" },
+        }))
+        .await;
+        let BackendEvent::MessageReceived(received) = next_event(&mut event_rx).await else {
+            panic!("expected a message");
+        };
+        assert_eq!(received.id, format!("{ALICE}:1000"));
+        assert_eq!(received.contact.name, "Alice");
+        assert_eq!(received.content, "hi");
+        assert_eq!(received.sender, None);
+
+        peer.receive(json!({
+            "sourceNumber": BOB,
+            "dataMessage": {
+                "timestamp": 1001,
+                "message": "hi all",
+                "groupInfo": { "groupId": GROUP_ID },
+                "attachments": [{ "filename": "cat.jpg" }],
+            },
+        }))
+        .await;
+        let BackendEvent::MessageReceived(received) = next_event(&mut event_rx).await else {
+            panic!("expected a message");
+        };
+        assert_eq!(received.contact.phone, format!("group:{GROUP_ID}"));
+        assert_eq!(received.contact.name, "Friends");
+        assert_eq!(
+            received.sender.map(|sender| sender.name).as_deref(),
+            Some("Bob Builder")
+        );
+        assert_eq!(received.attachments, ["cat.jpg"]);
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "typingMessage": { "action": "STARTED" },
+        }))
+        .await;
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            BackendEvent::Typing {
+                is_typing: true,
+                ..
+            }
+        ));
+
+        // The same message sent from the phone shows up as ours
+        peer.receive(json!({
+            "sourceNumber": ACCOUNT,
+            "syncMessage": { "sentMessage": {
+                "destinationNumber": BOB,
+                "timestamp": 1002,
+                "message": "from my phone",
+            }},
+        }))
+        .await;
+        let bob = Contact::new(String::from("Bob Builder"), String::from(BOB));
+        let sent = wait_for_id(&backend, &bob, &format!("{ACCOUNT}:1002")).await;
+        assert_eq!(sent.direction, MessageDirection::To);
+        assert_eq!(sent.content, "from my phone");
+
+        backend.close().unwrap().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sends_and_follows_receipts() {
+        let (mut backend, mut peer, _directory) = connect().await;
+        let mut event_rx = backend.take_events().unwrap();
+        let alice = backend.join_conversation(ALICE).unwrap();
+
+        let message = outgoing(&alice, "hello");
+        backend.send_message(message.clone());
+        let (id, params) = peer.expect_call("send").await;
+        assert_eq!(
+            params,
+            json!({ "recipient": [ALICE], "message": "hello", "account": ACCOUNT })
+        );
+        peer.respond(
+            id,
+            json!({ "timestamp": 2000, "results": [{ "type": "SUCCESS" }] }),
+        )
+        .await;
+        let signal_id = format!("{ACCOUNT}:2000");
+        wait_for_id(&backend, &alice, &signal_id).await;
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "receiptMessage": { "isDelivery": true, "timestamps": [2000] },
+        }))
+        .await;
+        match next_event(&mut event_rx).await {
+            BackendEvent::DeliveryReceipt { message_id } => assert_eq!(message_id, signal_id),
+            other => panic!("expected a delivery receipt, got {other:?}"),
+        }
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "receiptMessage": { "isRead": true, "timestamps": [2000] },
+        }))
+        .await;
+        match next_event(&mut event_rx).await {
+            BackendEvent::ReadReceipt { message_id } => assert_eq!(message_id, signal_id),
+            other => panic!("expected a read receipt, got {other:?}"),
+        }
+        let read = wait_for_id(&backend, &alice, &signal_id).await;
+        assert_eq!(read.status, DeliveryStatus::Read);
+
+        let bob = backend.join_conversation(BOB).unwrap();
+        let failing = outgoing(&bob, "are you there");
+        backend.send_message(failing.clone());
+        let (id, _) = peer.expect_call("send").await;
+        peer.respond(
+            id,
+            json!({ "timestamp": 2001, "results": [{ "type": "UNREGISTERED_FAILURE" }] }),
+        )
+        .await;
+        match next_event(&mut event_rx).await {
+            BackendEvent::DeliveryFailed { message_id, reason } => {
+                assert_eq!(message_id, failing.id);
+                assert_eq!(reason, "unregistered failure");
+            }
+            other => panic!("expected a delivery failure, got {other:?}"),
+        }
+
+        backend.close().unwrap().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reacts_both_ways() {
+        let (mut backend, mut peer, _directory) = connect().await;
+        let mut event_rx = backend.take_events().unwrap();
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "dataMessage": { "timestamp": 1000, "message": "hi" },
+        }))
+        .await;
+        let BackendEvent::MessageReceived(received) = next_event(&mut event_rx).await else {
+            panic!("expected a message");
+        };
+
+        backend.send_reaction(&received, "❤️").unwrap();
+        let (id, params) = peer.expect_call("sendReaction").await;
+        assert_eq!(
+            params,
+            json!({
+                "recipient": [ALICE],
+                "emoji": "❤️",
+                "targetAuthor": ALICE,
+                "targetTimestamp": 1000,
+                "account": ACCOUNT,
+            })
+        );
+        peer.respond(id, json!({ "timestamp": 3000 })).await;
+        let mine = &backend.get_messages(&received.contact, None)[0].reactions;
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].from.phone, ACCOUNT);
+
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "dataMessage": {
+                "timestamp": 1003,
+                "reaction": {
+                    "emoji": "👍",
+                    "targetAuthorNumber": ALICE,
+                    "targetSentTimestamp": 1000,
+                    "isRemove": false,
+                },
+            },
+        }))
+        .await;
+        match next_event(&mut event_rx).await {
+            BackendEvent::Reaction {
+                message_id,
+                from,
+                reaction,
+            } => {
+                assert_eq!(message_id, received.id);
+                assert_eq!(from.phone, ALICE);
+                assert_eq!(reaction, "👍");
+            }
+            other => panic!("expected a reaction, got {other:?}"),
+        }
+        let reactions = &backend.get_messages(&received.contact, None)[0].reactions;
+        assert_eq!(reactions.len(), 2);
+
+        // Removing it sends no event but takes it off the message
+        peer.receive(json!({
+            "sourceNumber": ALICE,
+            "dataMessage": {
+                "timestamp": 1004,
+                "reaction": {
+                    "emoji": "👍",
+                    "targetAuthorNumber": ALICE,
+                    "targetSentTimestamp": 1000,
+                    "isRemove": true,
+                },
+            },
+        }))
+        .await;
+        timeout(WAIT, async {
+            while backend.get_messages(&received.contact, None)[0]
+                .reactions
+                .len()
+                != 1
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        backend.close().unwrap().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_signal_cli_going_away() {
+        let (mut backend, peer, _directory) = connect().await;
+        let mut event_rx = backend.take_events().unwrap();
+
+        drop(peer);
+        assert!(matches!(
+            next_event(&mut event_rx).await,
+            BackendEvent::Disconnected { .. }
+        ));
+        backend.close().unwrap().await.unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use tokio::{
+    process::Child,
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+use tracing::{event, Level};
+
+use super::{message_id, Conversations, GROUP_PREFIX};
+use crate::backends::rpc::{Notification, RpcClient};
+use crate::backends::BackendEvent;
+use crate::state::{Contact, DeliveryStatus, Message, MessageDirection, Reaction};
+
+/// Requests from the backend to the session task
+#[derive(Debug)]
+pub enum Outgoing {
+    Send {
+        conversation_id: String,
+        message_id: String,
+        body: String,
+    },
+    React {
+        conversation_id: String,
+        message_id: String,
+        emoji: String,
+    },
+    Quit,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ReceiveParams {
+    envelope: Envelope,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Envelope {
+    source_number: Option<String>,
+    source_uuid: Option<String>,
+    source_name: Option<String>,
+    data_message: Option<DataMessage>,
+    receipt_message: Option<ReceiptMessage>,
+    typing_message: Option<TypingMessage>,
+    sync_message: Option<SyncMessage>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct DataMessage {
+    timestamp: i64,
+    message: Option<String>,
+    group_info: Option<GroupInfo>,
+    reaction: Option<ReactionMessage>,
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct GroupInfo {
+    group_id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ReactionMessage {
+    emoji: String,
+    target_author_number: Option<String>,
+    target_author_uuid: Option<String>,
+    target_sent_timestamp: i64,
+    is_remove: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Attachment {
+    filename: Option<String>,
+    id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct ReceiptMessage {
+    is_delivery: bool,
+    is_read: bool,
+    is_viewed: bool,
+    timestamps: Vec<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct TypingMessage {
+    action: String,
+    group_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct SyncMessage {
+    sent_message: Option<SentMessage>,
+}
+
+/// A message we sent from another device
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct SentMessage {
+    destination_number: Option<String>,
+    destination_uuid: Option<String>,
+    timestamp: i64,
+    message: Option<String>,
+    group_info: Option<GroupInfo>,
+    attachments: Vec<Attachment>,
+}
+
+pub struct Session {
+    pub rpc: RpcClient,
+    pub notifications: UnboundedReceiver<Notification>,
+    pub outgoing: UnboundedReceiver<Outgoing>,
+    pub event_tx: UnboundedSender<BackendEvent>,
+    pub conversations: Arc<Mutex<Conversations>>,
+    pub account: String,
+    /// Sent along with every call when talking to a daemon, which may serve
+    /// several accounts
+    pub send_account: bool,
+    /// Contact names by number or UUID
+    pub names: HashMap<String, String>,
+    /// The signal-cli process when it was started for us, killed on drop
+    pub child: Option<Child>,
+}
+
+impl Session {
+    /// Handles notifications and sends messages until the backend quits
+    pub async fn run(mut self) {
+        let mut connected = true;
+
+        loop {
+            tokio::select! {
+                notification = self.notifications.recv(), if connected => match notification {
+                    Some(notification) => self.handle_notification(notification),
+                    None => {
+                        connected = false;
+                        let reason = match self.child.as_mut().map(Child::try_wait) {
+                            Some(Ok(Some(status))) => format!("signal-cli exited with {}", status),
+                            _ => String::from("connection to signal-cli was closed"),
+                        };
+                        event!(Level::WARN, "{}", reason);
+                        let _ = self.event_tx.send(BackendEvent::Disconnected { reason });
+                    }
+                },
+                outgoing = self.outgoing.recv() => match outgoing {
+                    Some(Outgoing::Send { conversation_id, message_id, body }) => {
+                        self.send(&conversation_id, message_id, body).await;
+                    }
+                    Some(Outgoing::React { conversation_id, message_id, emoji }) => {
+                        if let Err(e) = self.react(&conversation_id, &message_id, &emoji).await {
+                            event!(Level::WARN, "Could not react to {}: {:#}", message_id, e);
+                        }
+                    }
+                    Some(Outgoing::Quit) | None => return,
+                },
+            }
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) {
+        if notification.method != "receive" {
+            event!(
+                Level::DEBUG,
+                "Ignoring {} notification",
+                notification.method
+            );
+            return;
+        }
+
+        match serde_json::from_value::<ReceiveParams>(notification.params) {
+            Ok(params) => self.handle_envelope(params.envelope),
+            Err(e) => event!(Level::WARN, "Could not read received envelope: {}", e),
+        }
+    }
+
+    fn handle_envelope(&mut self, envelope: Envelope) {
+        let Some(source) = envelope
+            .source_number
+            .clone()
+            .or(envelope.source_uuid.clone())
+        else {
+            return;
+        };
+        if let Some(name) = envelope.source_name.filter(|name| !name.is_empty()) {
+            self.names.entry(source.clone()).or_insert(name);
+        }
+        let sender = Contact::new(self.name(&source), source.clone());
+
+        if let Some(data) = envelope.data_message {
+            self.handle_data_message(&sender, data);
+        }
+        if let Some(receipt) = envelope.receipt_message {
+            self.handle_receipt(receipt);
+        }
+        if let Some(typing) = envelope.typing_message {
+            let conversation = match typing.group_id {
+                Some(group_id) => self.group_contact(&group_id),
+                None => sender,
+            };
+            let _ = self.event_tx.send(BackendEvent::Typing {
+                contact: conversation,
+                is_typing: typing.action == "STARTED",
+            });
+        }
+        if let Some(sent) = envelope.sync_message.and_then(|sync| sync.sent_message) {
+            self.handle_sent_elsewhere(sent);
+        }
+    }
+
+    fn handle_data_message(&mut self, sender: &Contact, data: DataMessage) {
+        let conversation = match &data.group_info {
+            Some(group) => self.group_contact(&group.group_id),
+            None => sender.clone(),
+        };
+
+        if let Some(reaction) = data.reaction {
+            self.handle_reaction(sender, reaction);
+            return;
+        }
+
+        let attachments = attachment_names(&data.attachments);
+        if data.message.is_none() && attachments.is_empty() {
+            return;
+        }
+
+        let mut message = Message::new(
+            conversation.clone(),
+            data.message.unwrap_or_default(),
+            timestamp(data.timestamp),
+            MessageDirection::From,
+        )
+        .with_id(message_id(&sender.phone, data.timestamp));
+        message.attachments = attachments;
+        if data.group_info.is_some() {
+            message = message.with_sender(sender.clone());
+        }
+
+        self.conversations
+            .lock()
+            .unwrap()
+            .push(&conversation, message.clone());
+        let _ = self.event_tx.send(BackendEvent::MessageReceived(message));
+    }
+
+    fn handle_reaction(&mut self, sender: &Contact, reaction: ReactionMessage) {
+        let Some(author) = reaction
+            .target_author_number
+            .or(reaction.target_author_uuid)
+        else {
+            return;
+        };
+        let target_id = message_id(&author, reaction.target_sent_timestamp);
+
+        let mut conversations = self.conversations.lock().unwrap();
+        let Some(message) = conversations.find_message(&target_id) else {
+            event!(Level::DEBUG, "Reaction to unknown message {}", target_id);
+            return;
+        };
+
+        // Everyone has at most one reaction on a message
+        message.reactions.retain(|r| r.from.phone != sender.phone);
+        if reaction.is_remove {
+            return;
+        }
+        message.reactions.push(Reaction {
+            from: sender.clone(),
+            reaction: reaction.emoji.clone(),
+        });
+        drop(conversations);
+
+        let _ = self.event_tx.send(BackendEvent::Reaction {
+            message_id: target_id,
+            from: sender.clone(),
+            reaction: reaction.emoji,
+        });
+    }
+
+    fn handle_receipt(&mut self, receipt: ReceiptMessage) {
+        let mut conversations = self.conversations.lock().unwrap();
+
+        for sent_at in receipt.timestamps {
+            let id = message_id(&self.account, sent_at);
+            let Some(message) = conversations.find_message(&id) else {
+                continue;
+            };
+
+            let backend_event = if receipt.is_read || receipt.is_viewed {
+                message.status = DeliveryStatus::Read;
+                BackendEvent::ReadReceipt { message_id: id }
+            } else if receipt.is_delivery && message.status == DeliveryStatus::Sent {
+                message.status = DeliveryStatus::Delivered;
+                BackendEvent::DeliveryReceipt { message_id: id }
+            } else {
+                continue;
+            };
+            let _ = self.event_tx.send(backend_event);
+        }
+    }
+
+    fn handle_sent_elsewhere(&mut self, sent: SentMessage) {
+        let conversation = match (
+            &sent.group_info,
+            sent.destination_number.or(sent.destination_uuid),
+        ) {
+            (Some(group), _) => self.group_contact(&group.group_id),
+            (None, Some(destination)) => Contact::new(self.name(&destination), destination),
+            (None, None) => return,
+        };
+
+        let id = message_id(&self.account, sent.timestamp);
+        let mut conversations = self.conversations.lock().unwrap();
+        if conversations.find_message(&id).is_some() {
+            return;
+        }
+
+        let mut message = Message::new(
+            conversation.clone(),
+            sent.message.unwrap_or_default(),
+            timestamp(sent.timestamp),
+            MessageDirection::To,
+        )
+        .with_id(id);
+        message.attachments = attachment_names(&sent.attachments);
+        conversations.push(&conversation, message);
+    }
+
+    async fn send(&self, conversation_id: &str, local_id: String, body: String) {
+        let mut params = self.target_params(conversation_id);
+        params.insert(String::from("message"), Value::String(body));
+
+        let result = self.rpc.call("send", Value::Object(params)).await;
+        let mut conversations = self.conversations.lock().unwrap();
+        let Some(message) = conversations.find_message(&local_id) else {
+            return;
+        };
+
+        match result.and_then(|result| check_send_result(&result)) {
+            Ok(sent_at) => {
+                // Receipts and reactions refer to the message by its timestamp
+                message.id = message_id(&self.account, sent_at);
+            }
+            Err(e) => {
+                let reason = format!("{:#}", e);
+                event!(
+                    Level::WARN,
+                    "Could not send to {}: {}",
+                    conversation_id,
+                    reason
+                );
+                message.status = DeliveryStatus::Failed(reason.clone());
+                let _ = self.event_tx.send(BackendEvent::DeliveryFailed {
+                    message_id: local_id,
+                    reason,
+                });
+            }
+        }
+    }
+
+    async fn react(
+        &self,
+        conversation_id: &str,
+        target_id: &str,
+        emoji: &str,
+    ) -> anyhow::Result<()> {
+        let (author, sent_at) = target_id
+            .rsplit_once(':')
+            .and_then(|(author, sent_at)| Some((author, sent_at.parse::<i64>().ok()?)))
+            .ok_or_else(|| anyhow!("message was not sent through Signal yet"))?;
+
+        let mut params = self.target_params(conversation_id);
+        params.insert(String::from("emoji"), json!(emoji));
+        params.insert(String::from("targetAuthor"), json!(author));
+        params.insert(String::from("targetTimestamp"), json!(sent_at));
+
+        self.rpc.call("sendReaction", Value::Object(params)).await?;
+        Ok(())
+    }
+
+    /// Addresses a call to a contact or group
+    fn target_params(&self, conversation_id: &str) -> Map<String, Value> {
+        let mut params = Map::new();
+        match conversation_id.strip_prefix(GROUP_PREFIX) {
+            Some(group_id) => params.insert(String::from("groupId"), json!(group_id)),
+            None => params.insert(String::from("recipient"), json!([conversation_id])),
+        };
+        if self.send_account {
+            params.insert(String::from("account"), json!(self.account));
+        }
+        params
+    }
+
+    fn name(&self, address: &str) -> String {
+        self.names
+            .get(address)
+            .cloned()
+            .unwrap_or_else(|| address.to_string())
+    }
+
+    fn group_contact(&self, group_id: &str) -> Contact {
+        let id = format!("{}{}", GROUP_PREFIX, group_id);
+        let name = self
+            .names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| String::from("Group"));
+        Contact::new(name, id)
+    }
+}
+
+/// Signal sends to every device of every recipient, the message counts as
+/// sent when it reached anyone. Returns the timestamp identifying it.
+fn check_send_result(result: &Value) -> anyhow::Result<i64> {
+    let sent_at = result["timestamp"]
+        .as_i64()
+        .context("signal-cli did not say when the message was sent")?;
+
+    let Some(results) = result["results"].as_array().filter(|r| !r.is_empty()) else {
+        return Ok(sent_at);
+    };
+    if results.iter().any(|r| r["type"] == "SUCCESS") {
+        return Ok(sent_at);
+    }
+
+    let failures: Vec<&str> = results.iter().filter_map(|r| r["type"].as_str()).collect();
+    bail!("{}", failures.join(", ").to_lowercase().replace('_', " "))
+}
+
+fn attachment_names(attachments: &[Attachment]) -> Vec<String> {
+    attachments
+        .iter()
+        .filter_map(|a| a.filename.clone().or(a.id.clone()))
+        .collect()
+}
+
+fn timestamp(millis: i64) -> NaiveDateTime {
+    DateTime::from_timestamp_millis(millis)
+        .map(|time| time.with_timezone(&Local).naive_local())
+        .unwrap_or_else(|| Local::now().naive_local())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_send_results() {
+        assert_eq!(check_send_result(&json!({ "timestamp": 5 })).unwrap(), 5);
+        let results = json!({
+            "timestamp": 5,
+            "results": [{ "type": "UNREGISTERED_FAILURE" }, { "type": "SUCCESS" }],
+        });
+        assert_eq!(check_send_result(&results).unwrap(), 5);
+        let results = json!({
+            "timestamp": 5,
+            "results": [{ "type": "UNREGISTERED_FAILURE" }],
+        });
+        assert_eq!(
+            check_send_result(&results).unwrap_err().to_string(),
+            "unregistered failure"
+        );
+        assert!(check_send_result(&json!({})).is_err());
+    }
+}
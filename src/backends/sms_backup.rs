@@ -0,0 +1,529 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Local, NaiveDateTime};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::{event, Level};
+
+use super::{BackendEvent, MsgBackend};
+use crate::state::{normalize_handle, Contact, DeliveryStatus, Message, MessageDirection};
+
+/// Name the app writes when it has no contact for an address
+const UNKNOWN_CONTACT: &str = "(Unknown)";
+
+/// MMS address types, from the MMS encapsulation spec
+const MMS_ADDR_FROM: &str = "137";
+
+/// Reads the XML files written by the Android app "SMS Backup & Restore".
+/// The archive is loaded into memory once, it can not be used to send.
+pub struct SmsBackupBackend {
+    /// Threads keyed by [`thread_key`], each sorted oldest first
+    threads: HashMap<String, Thread>,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+}
+
+struct Thread {
+    contact: Contact,
+    messages: Vec<Message>,
+}
+
+/// Attributes of an `<sms>` or `<mms>` element
+type Attributes = HashMap<String, String>;
+
+/// An `<mms>` element along with the `<part>` and `<addr>` elements in it
+struct Mms {
+    attributes: Attributes,
+    parts: Vec<Attributes>,
+    addrs: Vec<Attributes>,
+}
+
+impl SmsBackupBackend {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+        let backend = Self::from_reader(BufReader::new(file))
+            .with_context(|| format!("could not read backup {}", path.display()))?;
+
+        event!(
+            Level::INFO,
+            "Loaded {} conversations from {}",
+            backend.threads.len(),
+            path.display()
+        );
+        Ok(backend)
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> anyhow::Result<Self> {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let mut backend = Self {
+            threads: HashMap::new(),
+            event_tx,
+            event_rx: Some(event_rx),
+        };
+
+        let mut reader = Reader::from_reader(reader);
+        let mut buf = Vec::new();
+        let mut mms: Option<Mms> = None;
+        let mut sms_count = 0;
+        let mut mms_count = 0;
+
+        loop {
+            let xml_event = reader
+                .read_event_into(&mut buf)
+                .map_err(|e| anyhow!("{} at byte {}", e, reader.buffer_position()))?;
+
+            match xml_event {
+                Event::Start(element) | Event::Empty(element)
+                    if element.name().as_ref() == "sms" =>
+                {
+                    let message = sms_message(&attributes(&element)?, sms_count);
+                    sms_count += 1;
+                    if let Some((contact, message)) = message {
+                        backend.add_message(contact, message);
+                    }
+                }
+                Event::Start(element) if element.name().as_ref() == "mms" => {
+                    mms = Some(Mms {
+                        attributes: attributes(&element)?,
+                        parts: Vec::new(),
+                        addrs: Vec::new(),
+                    });
+                }
+                Event::Empty(element) if element.name().as_ref() == "mms" => {
+                    let empty = Mms {
+                        attributes: attributes(&element)?,
+                        parts: Vec::new(),
+                        addrs: Vec::new(),
+                    };
+                    if let Some((contact, message)) = mms_message(&empty, mms_count) {
+                        backend.add_message(contact, message);
+                    }
+                    mms_count += 1;
+                }
+                Event::Start(element) | Event::Empty(element) => {
+                    match (&mut mms, element.name().as_ref()) {
+                        (Some(mms), "part") => mms.parts.push(attributes(&element)?),
+                        (Some(mms), "addr") => mms.addrs.push(attributes(&element)?),
+                        _ => {}
+                    }
+                }
+                Event::End(element) if element.name().as_ref() == "mms" => {
+                    if let Some(mms) = mms.take() {
+                        if let Some((contact, message)) = mms_message(&mms, mms_count) {
+                            backend.add_message(contact, message);
+                        }
+                        mms_count += 1;
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+
+            buf.clear();
+        }
+
+        backend.name_group_senders();
+        for thread in backend.threads.values_mut() {
+            thread.messages.sort_by_key(|m| m.timestamp);
+            for message in &mut thread.messages {
+                message.contact.name = thread.contact.name.clone();
+            }
+        }
+        Ok(backend)
+    }
+
+    /// Group messages only carry the sender's address, take the name from the
+    /// one-to-one conversation with them when there is one
+    fn name_group_senders(&mut self) {
+        let names: HashMap<String, String> = self
+            .threads
+            .iter()
+            .filter(|(key, _)| !key.contains('~'))
+            .map(|(key, thread)| (key.clone(), thread.contact.name.clone()))
+            .collect();
+
+        let senders = self
+            .threads
+            .values_mut()
+            .flat_map(|thread| thread.messages.iter_mut())
+            .filter_map(|message| message.sender.as_mut());
+        for sender in senders {
+            if let Some(name) = names.get(&normalize_handle(&sender.phone)) {
+                sender.name = name.clone();
+            }
+        }
+    }
+
+    fn add_message(&mut self, contact: Contact, message: Message) {
+        let thread = self
+            .threads
+            .entry(thread_key(&contact.phone))
+            .or_insert_with(|| Thread {
+                contact: contact.clone(),
+                messages: Vec::new(),
+            });
+
+        // Older messages may have been saved before the contact had a name
+        if thread.contact.name == thread.contact.phone && contact.name != contact.phone {
+            thread.contact.name = contact.name.clone();
+        }
+        thread.contact.has_unread |= contact.has_unread;
+
+        let contact = Contact::new(thread.contact.name.clone(), thread.contact.phone.clone());
+        thread.messages.push(Message { contact, ..message });
+    }
+}
+
+/// Identifies a thread by the normalized set of people in it, so that the same
+/// number written differently or group members listed in another order all
+/// end up in one conversation
+fn thread_key(address: &str) -> String {
+    let mut handles: Vec<String> = address.split('~').map(normalize_handle).collect();
+    handles.sort();
+    handles.dedup();
+    handles.join("~")
+}
+
+fn attributes(element: &BytesStart) -> anyhow::Result<Attributes> {
+    element
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute?;
+            Ok((
+                attribute.key.as_ref().to_string(),
+                unescape(&attribute.value),
+            ))
+        })
+        .collect()
+}
+
+/// Resolves the entities in an attribute value. The app writes characters
+/// outside the BMP as pairs of UTF-16 surrogate references, which XML parsers
+/// reject, so this is done by hand.
+fn unescape(raw: &str) -> String {
+    let mut text = String::with_capacity(raw.len());
+    let mut pending_surrogate: Option<u32> = None;
+    let mut rest = raw;
+
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let code = if let Some(hex) = entity.strip_prefix("#x") {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(decimal) = entity.strip_prefix('#') {
+            decimal.parse().ok()
+        } else {
+            None
+        };
+
+        match (code, pending_surrogate.take()) {
+            (Some(low @ 0xDC00..=0xDFFF), Some(high)) => {
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                text.extend(char::from_u32(code));
+            }
+            (Some(high @ 0xD800..=0xDBFF), _) => pending_surrogate = Some(high),
+            (Some(code), _) => {
+                text.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+            }
+            (None, _) => match entity {
+                "amp" => text.push('&'),
+                "lt" => text.push('<'),
+                "gt" => text.push('>'),
+                "quot" => text.push('"'),
+                "apos" => text.push('\''),
+                _ => {
+                    text.push('&');
+                    text.push_str(entity);
+                    text.push(';');
+                }
+            },
+        }
+    }
+
+    text.push_str(rest);
+    text
+}
+
+/// Converts the milliseconds since the epoch the app stores to local time
+fn timestamp(attributes: &Attributes) -> Option<NaiveDateTime> {
+    let millis = attributes.get("date")?.parse().ok()?;
+    Some(
+        DateTime::from_timestamp_millis(millis)?
+            .with_timezone(&Local)
+            .naive_local(),
+    )
+}
+
+/// The name the app saved for an address, falling back to the address
+fn contact(attributes: &Attributes, address: &str) -> Contact {
+    let name = attributes
+        .get("contact_name")
+        .filter(|name| {
+            !name.is_empty() && name.as_str() != UNKNOWN_CONTACT && name.as_str() != "null"
+        })
+        .cloned()
+        .unwrap_or_else(|| address.replace('~', ", "));
+
+    let mut contact = Contact::new(name, address.to_string());
+    contact.has_unread = attributes.get("read").is_some_and(|read| read == "0");
+    contact
+}
+
+/// Maps the Android message box to a direction and status, drafts are skipped
+fn direction(message_box: &str) -> Option<(MessageDirection, DeliveryStatus)> {
+    match message_box {
+        "1" => Some((MessageDirection::From, DeliveryStatus::Delivered)),
+        "2" | "4" | "6" => Some((MessageDirection::To, DeliveryStatus::Sent)),
+        "5" => Some((
+            MessageDirection::To,
+            DeliveryStatus::Failed(String::from("failed to send")),
+        )),
+        _ => None,
+    }
+}
+
+fn sms_message(attributes: &Attributes, index: usize) -> Option<(Contact, Message)> {
+    let Some(address) = attributes.get("address").filter(|a| !a.is_empty()) else {
+        event!(Level::DEBUG, "Skipping SMS {} without an address", index);
+        return None;
+    };
+    let (direction, status) = direction(attributes.get("type")?)?;
+    let timestamp = timestamp(attributes)?;
+
+    let contact = contact(attributes, address);
+    let mut message = Message::new(
+        contact.clone(),
+        attributes.get("body").cloned().unwrap_or_default(),
+        timestamp,
+        direction,
+    )
+    .with_id(format!("sms-{}", index));
+    message.status = status;
+
+    Some((contact, message))
+}
+
+fn mms_message(mms: &Mms, index: usize) -> Option<(Contact, Message)> {
+    let Some(address) = mms.attributes.get("address").filter(|a| !a.is_empty()) else {
+        event!(Level::DEBUG, "Skipping MMS {} without an address", index);
+        return None;
+    };
+    let (direction, status) = direction(mms.attributes.get("msg_box")?)?;
+    let timestamp = timestamp(&mms.attributes)?;
+
+    let mut body = Vec::new();
+    let mut attachments = Vec::new();
+    for part in &mms.parts {
+        match part.get("ct").map(String::as_str) {
+            Some("application/smil") => {}
+            Some("text/plain") => body.extend(part.get("text").cloned()),
+            _ => attachments.extend(
+                ["cl", "name", "fn"]
+                    .iter()
+                    .filter_map(|key| part.get(*key))
+                    .find(|name| !name.is_empty() && name.as_str() != "null")
+                    .cloned(),
+            ),
+        }
+    }
+
+    let contact = contact(&mms.attributes, address);
+    let mut message = Message::new(contact.clone(), body.join("\n"), timestamp, direction)
+        .with_id(format!("mms-{}", index));
+    message.status = status;
+    message.attachments = attachments;
+
+    // In a group the address lists everyone, the sender is in the addr list
+    if address.contains('~') && message.direction == MessageDirection::From {
+        if let Some(from) = mms
+            .addrs
+            .iter()
+            .find(|addr| addr.get("type").is_some_and(|t| t == MMS_ADDR_FROM))
+            .and_then(|addr| addr.get("address"))
+        {
+            message = message.with_sender(Contact::new(from.clone(), from.clone()));
+        }
+    }
+
+    Some((contact, message))
+}
+
+impl MsgBackend for SmsBackupBackend {
+    fn send_message(&mut self, mut message: Message) {
+        let reason = String::from("SMS backup files are read-only");
+        message.status = DeliveryStatus::Failed(reason.clone());
+        let _ = self.event_tx.send(BackendEvent::DeliveryFailed {
+            message_id: message.id.clone(),
+            reason,
+        });
+
+        let contact = message.contact.clone();
+        self.add_message(contact, message);
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        let Some(thread) = self.threads.get(&thread_key(&contact.phone)) else {
+            return Vec::new();
+        };
+
+        let skip = n.map_or(0, |n| thread.messages.len().saturating_sub(n as usize));
+        thread.messages[skip..].to_vec()
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        let mut threads: Vec<&Thread> = self.threads.values().collect();
+        threads
+            .sort_by_key(|thread| std::cmp::Reverse(thread.messages.last().map(|m| m.timestamp)));
+        threads
+            .into_iter()
+            .map(|thread| thread.contact.clone())
+            .collect()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        self.event_rx.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../../tests/fixtures/sms_backup.xml");
+
+    fn backend() -> SmsBackupBackend {
+        SmsBackupBackend::from_reader(FIXTURE.as_bytes()).unwrap()
+    }
+
+    fn thread<'a>(backend: &'a SmsBackupBackend, address: &str) -> &'a Thread {
+        &backend.threads[&thread_key(address)]
+    }
+
+    #[test]
+    fn unescape_joins_surrogate_pairs() {
+        assert_eq!(unescape("&#55357;&#56832;"), "😀");
+        assert_eq!(unescape("&#xD83D;&#xDE00; and &#233;"), "😀 and é");
+        assert_eq!(unescape("a &lt;b&gt; &amp; &quot;c&quot;"), "a <b> & \"c\"");
+    }
+
+    #[test]
+    fn unescape_keeps_what_it_can_not_resolve() {
+        assert_eq!(unescape("&nbsp; & more"), "&nbsp; & more");
+        // A lone high surrogate is dropped rather than kept half decoded
+        assert_eq!(unescape("x&#55357;y"), "xy");
+    }
+
+    #[test]
+    fn thread_key_ignores_formatting_and_order() {
+        assert_eq!(thread_key("+1 (555) 123-4567"), thread_key("5551234567"));
+        assert_eq!(
+            thread_key("+15559876543~+1 555 123 4567"),
+            thread_key("5551234567~5559876543")
+        );
+        assert_eq!(thread_key("Alice@Example.com"), "alice@example.com");
+        assert_ne!(thread_key("5551234567"), thread_key("5559876543"));
+    }
+
+    #[test]
+    fn merges_threads_written_differently() {
+        let backend = backend();
+        assert_eq!(backend.threads.len(), 3, "the draft is skipped");
+
+        let alice = thread(&backend, "5551234567");
+        assert_eq!(alice.contact.name, "Alice");
+        assert_eq!(alice.contact.phone, "+1 (555) 123-4567");
+        assert!(alice.contact.has_unread);
+        let ids: Vec<&str> = alice.messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["sms-0", "sms-1", "sms-2"]);
+        assert!(alice.messages.iter().all(|m| m.contact.name == "Alice"));
+    }
+
+    #[test]
+    fn reads_sms() {
+        let backend = backend();
+        let alice = &thread(&backend, "5551234567").messages;
+
+        assert_eq!(alice[0].content, "Lunch at noon?");
+        assert_eq!(alice[0].direction, MessageDirection::From);
+        assert_eq!(alice[0].status, DeliveryStatus::Delivered);
+        assert_eq!(alice[1].content, "Sure & see you there");
+        assert_eq!(alice[1].direction, MessageDirection::To);
+        assert_eq!(alice[1].status, DeliveryStatus::Sent);
+        assert_eq!(alice[2].content, "Bring 😀 vibes");
+
+        let bob = &thread(&backend, "5559876543").messages;
+        assert_eq!(
+            bob[0].status,
+            DeliveryStatus::Failed(String::from("failed to send"))
+        );
+    }
+
+    #[test]
+    fn reads_mms_parts() {
+        let backend = backend();
+        let mms = &thread(&backend, "5559876543").messages[1];
+
+        assert_eq!(mms.id, "mms-0");
+        assert_eq!(mms.content, "Look at this");
+        assert_eq!(mms.attachments, ["IMG_0001.jpg"]);
+        assert_eq!(mms.direction, MessageDirection::From);
+        // Only group messages name a sender
+        assert_eq!(mms.sender, None);
+    }
+
+    #[test]
+    fn group_mms_takes_the_sender_from_the_from_addr() {
+        let backend = backend();
+        let group = thread(&backend, "5551234567~5559876543");
+        assert_eq!(group.contact.name, "Bob, Alice");
+        assert_eq!(group.messages.len(), 2);
+
+        let received = &group.messages[0];
+        let sender = received.sender.as_ref().unwrap();
+        assert_eq!(sender.phone, "+15559876543");
+        assert_eq!(sender.name, "Bob", "named from the one-to-one thread");
+
+        let sent = &group.messages[1];
+        assert_eq!(sent.direction, MessageDirection::To);
+        assert_eq!(sent.sender, None);
+    }
+
+    #[test]
+    fn recent_contacts_are_newest_first() {
+        let backend = backend();
+        let names: Vec<String> = backend
+            .get_recent_contacts()
+            .into_iter()
+            .map(|contact| contact.name)
+            .collect();
+        assert_eq!(names, ["Bob, Alice", "Bob", "Alice"]);
+    }
+
+    #[test]
+    fn get_messages_finds_the_thread_by_any_form_of_the_handle() {
+        let backend = backend();
+        let contact = Contact::new(String::from("Alice"), String::from("+15551234567"));
+        assert_eq!(backend.get_messages(&contact, None).len(), 3);
+
+        let last = backend.get_messages(&contact, Some(1));
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].id, "sms-2");
+    }
+}
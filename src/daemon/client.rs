@@ -0,0 +1,120 @@
+use anyhow::{bail, Context};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixStream,
+    },
+    sync::{
+        broadcast,
+        mpsc::{UnboundedReceiver, UnboundedSender},
+    },
+};
+use tracing::{event, Level};
+
+use super::{socket_path, write_line, Update};
+use crate::state::{action::Action, State};
+use crate::{Interrupted, Terminator};
+
+/// A client's connection to the daemon
+pub struct Connection {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Connection {
+    /// Connects to the daemon, `None` when it is not running
+    pub async fn connect() -> Option<Self> {
+        let path = socket_path();
+        let stream = match UnixStream::connect(&path).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                event!(Level::DEBUG, "No daemon on {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let (reader, writer) = stream.into_split();
+        Some(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    pub async fn send(&mut self, action: &Action) -> anyhow::Result<()> {
+        write_line(&mut self.writer, action).await
+    }
+
+    /// The next update, `None` once the daemon has gone away
+    pub async fn recv(&mut self) -> anyhow::Result<Option<Update>> {
+        let Some(line) = self.lines.next_line().await? else {
+            return Ok(None);
+        };
+        parse(&line).map(Some)
+    }
+
+    /// Waits for the state, which the daemon sends right after connecting
+    pub async fn state(&mut self) -> anyhow::Result<State> {
+        loop {
+            match self.recv().await? {
+                Some(Update::State(state)) => return Ok(state),
+                Some(Update::Event(_)) => {}
+                None => bail!("the daemon went away"),
+            }
+        }
+    }
+
+    /// Stands in for the state store of a TUI attached to the daemon, until
+    /// the user exits or the daemon goes away. Exiting leaves the daemon
+    /// running.
+    pub async fn attach(
+        mut self,
+        mut terminator: Terminator,
+        mut action_rx: UnboundedReceiver<Action>,
+        state_tx: UnboundedSender<State>,
+        mut interrupt_rx: broadcast::Receiver<Interrupted>,
+    ) -> anyhow::Result<Interrupted> {
+        let result = loop {
+            tokio::select! {
+                Some(action) = action_rx.recv() => match action {
+                    Action::Exit => {
+                        let _ = terminator.terminate(Interrupted::UserInt);
+                        break Interrupted::UserInt;
+                    }
+                    action => {
+                        if let Err(e) = write_line(&mut self.writer, &action).await {
+                            event!(Level::WARN, "Could not send {:?} to the daemon: {:#}", action, e);
+                        }
+                    }
+                },
+                line = self.lines.next_line() => {
+                    let update = match line {
+                        Ok(Some(line)) => parse(&line),
+                        Ok(None) => Err(anyhow::anyhow!("the daemon went away")),
+                        Err(e) => Err(e.into()),
+                    };
+                    match update {
+                        Ok(Update::State(state)) => {
+                            let _ = state_tx.send(state);
+                        }
+                        Ok(Update::Event(_)) => {}
+                        Err(e) => {
+                            event!(Level::ERROR, "Detaching: {:#}", e);
+                            let _ = terminator.terminate(Interrupted::UserInt);
+                            break Interrupted::UserInt;
+                        }
+                    }
+                },
+                Ok(interrupted) = interrupt_rx.recv() => {
+                    break interrupted;
+                }
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+fn parse(line: &str) -> anyhow::Result<Update> {
+    serde_json::from_str(line).context("malformed update from the daemon")
+}
@@ -0,0 +1,221 @@
+//! `chatty daemon` owns the backend and the state store so that messages keep
+//! arriving with no terminal open. TUI and CLI clients attach to it over a
+//! Unix socket in the runtime dir, where each line is a JSON message: clients
+//! send `Action`s and the daemon answers with `Update`s, starting with the
+//! current state. Every client sees the same session.
+
+use std::{
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        watch,
+    },
+};
+use tracing::{event, Level};
+
+use crate::backends::{BackendEvent, MsgBackend};
+use crate::logging::get_runtime_dir;
+use crate::state::{action::Action, State, StateStore};
+use crate::{Interrupted, Terminator};
+
+mod client;
+
+pub use client::Connection;
+
+/// Source of the numbers clients are told apart by in the log
+static NEXT_CLIENT: AtomicU64 = AtomicU64::new(1);
+
+/// What the daemon tells its clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Update {
+    State(State),
+    Event(BackendEvent),
+}
+
+pub fn socket_path() -> PathBuf {
+    get_runtime_dir().join(format!("{}.sock", env!("CARGO_PKG_NAME")))
+}
+
+/// Serves the backend to clients until SIGINT or SIGTERM, when the backend is
+/// closed like it is when exiting the TUI
+pub async fn run(backend: impl MsgBackend) -> anyhow::Result<()> {
+    let path = socket_path();
+    let listener = listen(&path).await?;
+    event!(Level::INFO, "Listening on {}", path.display());
+    eprintln!("chatty: listening on {}", path.display());
+
+    // Only the store's exit interrupts, signals go through Action::Exit so
+    // that the backend is closed first
+    let (interrupt_tx, interrupt_rx) = broadcast::channel(1);
+    let terminator = Terminator::new(interrupt_tx);
+    let (state_store, state_rx) = StateStore::new();
+    let events = state_store.events();
+    let (action_tx, action_rx) = mpsc::unbounded_channel();
+
+    let result = tokio::try_join!(
+        state_store.main_loop(terminator, backend, action_rx, interrupt_rx.resubscribe()),
+        serve(listener, action_tx, state_rx, events, interrupt_rx),
+    );
+
+    if let Err(e) = std::fs::remove_file(&path) {
+        event!(Level::WARN, "Could not remove {}: {}", path.display(), e);
+    }
+    result.map(|_| ())
+}
+
+async fn listen(path: &Path) -> anyhow::Result<UnixListener> {
+    if let Some(directory) = path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(directory)
+            .with_context(|| format!("could not create {}", directory.display()))?;
+    }
+
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            bail!("a daemon is already listening on {}", path.display());
+        }
+        // Left behind by a daemon that did not exit cleanly
+        event!(Level::INFO, "Removing stale socket {}", path.display());
+        std::fs::remove_file(path)?;
+    }
+
+    UnixListener::bind(path).with_context(|| format!("could not listen on {}", path.display()))
+}
+
+async fn serve(
+    listener: UnixListener,
+    action_tx: UnboundedSender<Action>,
+    mut state_rx: UnboundedReceiver<State>,
+    events: broadcast::Sender<BackendEvent>,
+    mut interrupt_rx: broadcast::Receiver<Interrupted>,
+) -> anyhow::Result<Interrupted> {
+    // Clients only need the latest state, not every one in between
+    let (latest_tx, _) = watch::channel(None);
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(
+                        stream,
+                        action_tx.clone(),
+                        latest_tx.subscribe(),
+                        events.subscribe(),
+                    ));
+                }
+                Err(e) => event!(Level::WARN, "Could not accept a client: {}", e),
+            },
+            Some(state) = state_rx.recv() => {
+                latest_tx.send_replace(Some(state));
+            },
+            _ = sigint.recv() => {
+                event!(Level::INFO, "Interrupted, stopping");
+                let _ = action_tx.send(Action::Exit);
+            },
+            _ = sigterm.recv() => {
+                event!(Level::INFO, "Terminated, stopping");
+                let _ = action_tx.send(Action::Exit);
+            },
+            Ok(interrupted) = interrupt_rx.recv() => {
+                break Ok(interrupted);
+            }
+        }
+    }
+}
+
+/// Passes a client's actions to the store and sends it the state and backend
+/// events until it detaches
+async fn serve_client(
+    stream: UnixStream,
+    action_tx: UnboundedSender<Action>,
+    mut latest_rx: watch::Receiver<Option<State>>,
+    mut events: broadcast::Receiver<BackendEvent>,
+) {
+    let client = NEXT_CLIENT.fetch_add(1, Ordering::Relaxed);
+    event!(Level::INFO, "Client {} attached", client);
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // Clients start with the state as it is now
+    latest_rx.mark_changed();
+
+    loop {
+        let update = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    match serde_json::from_str(&line) {
+                        // Exiting one client leaves the daemon to the others
+                        Ok(Action::Exit) => break,
+                        Ok(action) => {
+                            if action_tx.send(action).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => event!(Level::WARN, "Ignoring malformed action from client {}: {}", client, e),
+                    }
+                    continue;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    event!(Level::WARN, "Could not read from client {}: {}", client, e);
+                    break;
+                }
+            },
+            changed = latest_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let Some(state) = latest_rx.borrow_and_update().clone() else {
+                    continue;
+                };
+                Update::State(state)
+            },
+            backend_event = events.recv() => match backend_event {
+                Ok(backend_event) => Update::Event(backend_event),
+                Err(RecvError::Lagged(missed)) => {
+                    event!(Level::WARN, "Client {} missed {} events", client, missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if let Err(e) = write_line(&mut writer, &update).await {
+            event!(
+                Level::DEBUG,
+                "Could not write to client {}: {:#}",
+                client,
+                e
+            );
+            break;
+        }
+    }
+
+    event!(Level::INFO, "Client {} detached", client);
+}
+
+/// Writes `value` as a line of JSON
+async fn write_line<T: Serialize>(
+    writer: &mut (impl AsyncWrite + Unpin),
+    value: &T,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
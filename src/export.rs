@@ -0,0 +1,428 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::backends::MsgBackend;
+use crate::logging::get_data_dir;
+use crate::state::{Chat, Contact, Message, MessageDirection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// Markdown transcript
+    Md,
+    /// One JSON object per message per line
+    Json,
+    /// Self-contained HTML transcript
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Md => "md",
+            ExportFormat::Json => "jsonl",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+/// Inclusive range of days to export, either end can be left open
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DateRange {
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl DateRange {
+    fn contains(&self, message: &Message) -> bool {
+        let date = message.timestamp.date();
+        self.since.is_none_or(|since| date >= since) && self.until.is_none_or(|until| date <= until)
+    }
+}
+
+/// Loads the full history of the given conversations from the backend, keeping
+/// only the messages in `range`
+pub fn collect_chats(
+    backend: &impl MsgBackend,
+    contacts: Vec<Contact>,
+    range: DateRange,
+) -> Vec<Chat> {
+    contacts
+        .into_iter()
+        .map(|contact| {
+            let messages = backend
+                .get_messages(&contact, None)
+                .into_iter()
+                .filter(|message| range.contains(message))
+                .collect();
+            Chat::new(contact, messages)
+        })
+        .filter(|chat| !chat.messages.is_empty())
+        .collect()
+}
+
+pub fn write_export(
+    chats: &[Chat],
+    format: ExportFormat,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Md => write_markdown(chats, writer),
+        ExportFormat::Json => write_json_lines(chats, writer),
+        ExportFormat::Html => write_html(chats, writer),
+    }
+}
+
+/// Writes the export to a new file in the exports folder of the data dir and
+/// returns its path
+pub fn export_to_data_dir(chats: &[Chat], format: ExportFormat, name: &str) -> io::Result<PathBuf> {
+    let directory = get_data_dir().join("exports");
+    std::fs::create_dir_all(&directory)?;
+
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = directory.join(format!(
+        "{}-{}.{}",
+        file_name,
+        chrono::offset::Local::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    ));
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    write_export(chats, format, &mut writer)?;
+    writer.flush()?;
+
+    Ok(path)
+}
+
+fn sender_name(message: &Message) -> &str {
+    match message.direction {
+        MessageDirection::To => "Me",
+        MessageDirection::From => &message.sender.as_ref().unwrap_or(&message.contact).name,
+    }
+}
+
+/// The attachment as a link, when it is a web address, a file or a relative
+/// path. Anything else, such as `javascript:`, is only shown.
+fn link_target(attachment: &str) -> Option<&str> {
+    // Browsers skip these before reading the scheme
+    if attachment.starts_with(char::is_whitespace) || attachment.contains(char::is_control) {
+        return None;
+    }
+
+    let scheme = attachment
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        });
+    match scheme.map(str::to_ascii_lowercase).as_deref() {
+        None | Some("http" | "https" | "file") => Some(attachment),
+        Some(_) => None,
+    }
+}
+
+/// Backslash escapes what Markdown would read as formatting, links or HTML,
+/// and what would start a list or heading at the start of the lines after
+/// the first. The first line always follows something else on its line.
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            // Continues the list item
+            escaped.push_str("\n  ");
+        }
+
+        // In the indentation, and only digits after it
+        let mut leading = i > 0;
+        let mut number = i > 0;
+        for c in line.chars() {
+            let starts_block = leading && "-+=".contains(c);
+            let numbers_list = number && !leading && ".)".contains(c);
+            if "\\`*_[]<>#|~&".contains(c) || starts_block || numbers_list {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+
+            if !(leading && c.is_whitespace()) {
+                leading = false;
+                number &= c.is_ascii_digit();
+            }
+        }
+    }
+    escaped
+}
+
+fn write_markdown(chats: &[Chat], writer: &mut impl Write) -> io::Result<()> {
+    for chat in chats {
+        writeln!(
+            writer,
+            "# {} ({})",
+            escape_markdown(&chat.contact.name),
+            escape_markdown(&chat.contact.phone)
+        )?;
+        writeln!(writer)?;
+
+        for message in chat.messages.iter() {
+            writeln!(
+                writer,
+                "- **{}** {}: {}",
+                message.timestamp.format("%Y-%m-%d %H:%M:%S"),
+                escape_markdown(sender_name(message)),
+                escape_markdown(&message.content)
+            )?;
+            for attachment in &message.attachments {
+                let shown = escape_markdown(attachment);
+                match link_target(attachment).filter(|target| !target.contains(['<', '>'])) {
+                    Some(target) => writeln!(writer, "  - attachment: [{}](<{}>)", shown, target)?,
+                    None => writeln!(writer, "  - attachment: {}", shown)?,
+                }
+            }
+        }
+
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+fn write_json_lines(chats: &[Chat], writer: &mut impl Write) -> io::Result<()> {
+    for message in chats.iter().flat_map(|chat| chat.messages.iter()) {
+        serde_json::to_writer(&mut *writer, message)?;
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; max-width: 48em; margin: 2em auto; background: #fafafa; }
+h1 { font-size: 1.3em; border-bottom: 1px solid #ccc; }
+.message { margin: 0.5em 0; padding: 0.5em 0.8em; border-radius: 0.8em; max-width: 70%; }
+.from { background: #e5e5ea; }
+.to { background: #0b84ff; color: white; margin-left: auto; }
+.meta { font-size: 0.75em; opacity: 0.7; }
+.content { white-space: pre-wrap; }
+.to a { color: white; }";
+
+fn write_html(chats: &[Chat], writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html><head><meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>Chatty export</title>")?;
+    writeln!(writer, "<style>\n{}\n</style>", HTML_STYLE)?;
+    writeln!(writer, "</head><body>")?;
+
+    for chat in chats {
+        writeln!(
+            writer,
+            "<h1>{} ({})</h1>",
+            escape_html(&chat.contact.name),
+            escape_html(&chat.contact.phone)
+        )?;
+
+        for message in chat.messages.iter() {
+            let class = match message.direction {
+                MessageDirection::To => "to",
+                MessageDirection::From => "from",
+            };
+            writeln!(writer, "<div class=\"message {}\">", class)?;
+            writeln!(
+                writer,
+                "<div class=\"meta\">{} &middot; <time datetime=\"{}\">{}</time></div>",
+                escape_html(sender_name(message)),
+                message.timestamp.format("%Y-%m-%dT%H:%M:%S"),
+                message.timestamp.format("%Y-%m-%d %H:%M:%S")
+            )?;
+            writeln!(
+                writer,
+                "<div class=\"content\">{}</div>",
+                escape_html(&message.content)
+            )?;
+            for attachment in &message.attachments {
+                let shown = escape_html(attachment);
+                match link_target(attachment) {
+                    Some(target) => writeln!(
+                        writer,
+                        "<div class=\"meta\">attachment: <a href=\"{}\">{}</a></div>",
+                        escape_html(target),
+                        shown
+                    )?,
+                    None => writeln!(writer, "<div class=\"meta\">attachment: {}</div>", shown)?,
+                }
+            }
+            writeln!(writer, "</div>")?;
+        }
+    }
+
+    writeln!(writer, "</body></html>")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use super::*;
+    use crate::backends::MockBackend;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, 30, 0)
+            .unwrap()
+    }
+
+    fn contact(name: &str, phone: &str) -> Contact {
+        Contact::new(name.to_string(), phone.to_string())
+    }
+
+    /// Conversations with everything the writers have to escape
+    fn chats() -> Vec<Chat> {
+        let joe = contact("Joe <script>", "+15551234567");
+        let mut from = Message::new(
+            joe.clone(),
+            String::from("# not a heading\n- not a list\n1. nor this\n**<b>bold</b>** & [x](y)"),
+            at(1, 9),
+            MessageDirection::From,
+        )
+        .with_id(String::from("1"));
+        from.attachments = vec![
+            String::from("https://example.com/a?b=1&c=\"2\""),
+            String::from("photos/cat 1.jpg"),
+            String::from("File:///tmp/x.pdf"),
+            String::from("javascript:alert(1)"),
+            String::from(" JavaScript:alert(1)"),
+            String::from("data:text/html,<b>"),
+        ];
+        let to = Message::new(
+            joe,
+            String::from("Price is 1.5 * 2 = 3, isn't it?"),
+            at(2, 10),
+            MessageDirection::To,
+        )
+        .with_id(String::from("2"));
+
+        let group = contact("Friends", "group-1");
+        let mut in_group = Message::new(
+            group.clone(),
+            String::from("hi all"),
+            at(3, 11),
+            MessageDirection::From,
+        )
+        .with_id(String::from("3"));
+        in_group.sender = Some(contact("_Ann_", "+15559876543"));
+
+        vec![
+            Chat::new(contact("Joe <script>", "+15551234567"), vec![from, to]),
+            Chat::new(group, vec![in_group]),
+        ]
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let mut written = Vec::new();
+        write_export(&chats(), format, &mut written).unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn writes_markdown() {
+        assert_eq!(
+            export(ExportFormat::Md),
+            include_str!("../tests/fixtures/export.md")
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        assert_eq!(
+            export(ExportFormat::Json),
+            include_str!("../tests/fixtures/export.jsonl")
+        );
+    }
+
+    #[test]
+    fn writes_html() {
+        assert_eq!(
+            export(ExportFormat::Html),
+            include_str!("../tests/fixtures/export.html")
+        );
+    }
+
+    #[test]
+    fn links_only_safe_attachments() {
+        assert_eq!(link_target("http://a"), Some("http://a"));
+        assert_eq!(link_target("HTTPS://a"), Some("HTTPS://a"));
+        assert_eq!(link_target("file:///a"), Some("file:///a"));
+        // A colon after a slash is part of a path
+        assert_eq!(link_target("a/b:c.jpg"), Some("a/b:c.jpg"));
+        assert_eq!(link_target("b.jpg"), Some("b.jpg"));
+        assert_eq!(link_target("b:c.jpg"), None);
+        assert_eq!(link_target("/tmp/b.jpg"), Some("/tmp/b.jpg"));
+        assert_eq!(link_target("javascript:alert(1)"), None);
+        assert_eq!(link_target("vbscript:x"), None);
+        assert_eq!(link_target("java\tscript:x"), None);
+        assert_eq!(link_target("\u{1}javascript:x"), None);
+    }
+
+    #[test]
+    fn collects_the_date_range() {
+        let mut backend = MockBackend::default();
+        let joe = backend.get_recent_contacts()[0].clone();
+        for day in [1, 2, 3] {
+            backend.send_message(Message::new(
+                joe.clone(),
+                format!("day {}", day),
+                at(day, 12),
+                MessageDirection::To,
+            ));
+        }
+        let contents = |since: Option<u32>, until: Option<u32>| -> Vec<String> {
+            let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+            let range = DateRange {
+                since: since.map(day),
+                until: until.map(day),
+            };
+            collect_chats(&backend, vec![joe.clone()], range)
+                .iter()
+                .flat_map(|chat| chat.messages.iter())
+                .map(|message| message.content.clone())
+                .collect()
+        };
+
+        assert_eq!(contents(Some(2), Some(3)), ["day 2", "day 3"]);
+        assert_eq!(contents(None, Some(2)), ["day 1", "day 2"]);
+        // And the mock's own message from August
+        assert_eq!(contents(Some(3), None).len(), 2);
+        // Both ends are included
+        assert_eq!(contents(Some(2), Some(2)), ["day 2"]);
+        assert_eq!(contents(None, None).len(), 4);
+        // Conversations with nothing in the range are left out
+        let empty = collect_chats(
+            &backend,
+            vec![joe.clone()],
+            DateRange {
+                since: NaiveDate::from_ymd_opt(2030, 1, 1),
+                until: None,
+            },
+        );
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,17 @@
+//! Chatty, a terminal messaging client. The binary parses the command line
+//! and wires these together; benches use the state and UI directly.
+
+mod address_book;
+pub mod backends;
+pub mod cli;
+pub mod daemon;
+mod export;
+pub mod logging;
+pub mod panic_handler;
+mod replay;
+mod search;
+pub mod state;
+pub mod termination;
+pub mod ui;
+
+use termination::{Interrupted, Terminator};
@@ -1,10 +1,33 @@
-use std::path::PathBuf;
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
 
-use color_eyre::eyre::Result;
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Result};
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{self, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use tracing_subscriber::{
+    self,
+    filter::EnvFilter,
+    layer::{Context, SubscriberExt},
+    reload,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
+
+/// Number of log events kept in memory for the in-app log viewer
+const LOG_BUFFER_CAPACITY: usize = 2000;
 
 lazy_static! {
     pub static ref PROJECT_NAME: String = env!("CARGO_CRATE_NAME").to_uppercase().to_string();
@@ -14,6 +37,110 @@ lazy_static! {
             .map(PathBuf::from);
     pub static ref LOG_ENV: String = format!("{}_LOGLEVEL", PROJECT_NAME.clone());
     pub static ref LOG_FILE: String = format!("{}.log", env!("CARGO_PKG_NAME"));
+    pub static ref DAEMON_LOG_FILE: String = format!("{}-daemon.log", env!("CARGO_PKG_NAME"));
+    pub static ref LOG_BUFFER: LogBuffer = LogBuffer::new(LOG_BUFFER_CAPACITY);
+}
+
+/// Handle used to swap the `EnvFilter` while the app is running
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// The most recent log events, oldest first
+#[derive(Clone)]
+pub struct LogBuffer {
+    records: Arc<Mutex<VecDeque<LogRecord>>>,
+    capacity: usize,
+    pushed: Arc<AtomicU64>,
+}
+
+impl LogBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            pushed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn push(&self, record: LogRecord) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+        self.pushed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// How many records were ever pushed, which changes with every new one
+    pub fn generation(&self) -> u64 {
+        self.pushed.load(Ordering::Relaxed)
+    }
+
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Collects an event's message and fields into a single line
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+/// `tracing_subscriber` layer that copies every event into a `LogBuffer`
+struct RingBufferLayer {
+    buffer: LogBuffer,
+}
+
+impl<S: Subscriber> Layer<S> for RingBufferLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        self.buffer.push(LogRecord {
+            timestamp: Local::now(),
+            level: *event.metadata().level(),
+            target: String::from(event.metadata().target()),
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+/// Replaces the active log filter, `directives` uses the same syntax as
+/// `RUST_LOG`
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)?;
+    FILTER_HANDLE
+        .get()
+        .ok_or_else(|| eyre!("logging is not initialized"))?
+        .reload(filter)?;
+    Ok(())
+}
+
+/// The directives of the active log filter
+pub fn log_filter() -> Option<String> {
+    FILTER_HANDLE
+        .get()?
+        .with_current(|filter| filter.to_string())
+        .ok()
 }
 
 fn project_directory() -> Option<ProjectDirs> {
@@ -31,10 +158,20 @@ pub fn get_data_dir() -> PathBuf {
     directory
 }
 
-pub fn initialize_logging() -> Result<()> {
+/// Where per-session files such as the daemon's socket go, the data dir on
+/// systems without a runtime dir
+pub fn get_runtime_dir() -> PathBuf {
+    project_directory()
+        .and_then(|proj_dirs| proj_dirs.runtime_dir().map(PathBuf::from))
+        .unwrap_or_else(get_data_dir)
+}
+
+/// Logs to `log_file` in the data dir, the daemon and the clients attached to
+/// it each keep their own
+pub fn initialize_logging(log_file: &str) -> Result<()> {
     let directory = get_data_dir();
     std::fs::create_dir_all(directory.clone())?;
-    let log_path = directory.join(LOG_FILE.clone());
+    let log_path = directory.join(log_file);
     let log_file = std::fs::File::create(log_path)?;
     unsafe {
         std::env::set_var(
@@ -44,15 +181,21 @@ pub fn initialize_logging() -> Result<()> {
                 .unwrap_or_else(|_| format!("{}=info", env!("CARGO_CRATE_NAME"))),
         );
     }
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let _ = FILTER_HANDLE.set(filter_handle);
     let file_subscriber = tracing_subscriber::fmt::layer()
         .with_file(true)
         .with_line_number(true)
         .with_writer(log_file)
         .with_target(false)
-        .with_ansi(false)
-        .with_filter(tracing_subscriber::filter::EnvFilter::from_default_env());
+        .with_ansi(false);
+    let buffer_subscriber = RingBufferLayer {
+        buffer: LOG_BUFFER.clone(),
+    };
     tracing_subscriber::registry()
+        .with(filter)
         .with(file_subscriber)
+        .with(buffer_subscriber)
         .with(ErrorLayer::default())
         .init();
     Ok(())
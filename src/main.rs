@@ -1,35 +1,130 @@
-mod backends;
-mod logging;
-mod panic_handler;
-mod state;
-mod termination;
-mod ui;
-
 use core::panic;
+use std::process::ExitCode;
 
-use backends::MockBackend;
-use logging::initialize_logging;
-use panic_handler::initialize_panic_handler;
-use state::StateStore;
-use termination::{create_termination, Interrupted, Terminator};
-use tracing::info;
-use ui::UiManager;
+use chatty::backends::{create_backends, CommandSender, MockBackend, MsgBackend, NameResolver};
+use chatty::cli::{self, Cli, Command};
+use chatty::daemon::Connection;
+use chatty::logging::{initialize_logging, DAEMON_LOG_FILE, LOG_FILE};
+use chatty::panic_handler::initialize_panic_handler;
+use chatty::state::{recording, StateStore};
+use chatty::termination::create_termination;
+use chatty::ui::UiManager;
+use clap::Parser;
+use itertools::Itertools;
+use tokio::sync::mpsc;
+use tracing::{error, info};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+
     initialize_panic_handler()?;
 
-    let _ = initialize_logging().map_err(|_| panic!("could not init logging"));
+    let log_file = match cli.command {
+        Some(Command::Daemon) => DAEMON_LOG_FILE.as_str(),
+        _ => LOG_FILE.as_str(),
+    };
+    let _ = initialize_logging(log_file).map_err(|_| panic!("could not init logging"));
 
     info!("Beginning Chatty startup sequence");
 
-    let (terminator, mut interrupt_rx) = create_termination();
+    // Recordings play back against the mock backend whatever --backend says
+    if let Some(command @ Command::Replay { .. }) = cli.command {
+        if cli.record {
+            if let Err(code) = start_recording() {
+                return Ok(code);
+            }
+        }
+        return Ok(cli::run(command, MockBackend::default()).await);
+    }
+
+    // A running daemon owns the backend, clients attach to it instead
+    if let Some(connection) = Connection::connect().await {
+        if let Some(Command::Daemon) = cli.command {
+            eprintln!("chatty: the daemon is already running");
+            return Ok(ExitCode::from(cli::EXIT_DAEMON));
+        }
+        if cli.command.as_ref().is_none_or(Command::attaches) {
+            // The daemon's store is the one that sees the actions and events
+            if cli.record {
+                eprintln!(
+                    "chatty: --record can not record through the running daemon, \
+                     start the daemon with --record instead"
+                );
+                return Ok(ExitCode::from(cli::EXIT_DAEMON));
+            }
+            return match cli.command {
+                Some(command) => Ok(cli::run_attached(command, connection).await),
+                None => {
+                    attach_tui(connection).await?;
+                    Ok(ExitCode::SUCCESS)
+                }
+            };
+        }
+    }
+
+    if cli.record {
+        if let Err(code) = start_recording() {
+            return Ok(code);
+        }
+    }
+
+    let specs = cli.backend.iter().map(ToString::to_string).join(", ");
+    info!("Creating {} backend...", specs);
+    let backend = match create_backends(&cli.backend).await {
+        Ok(backend) => backend,
+        Err(e) => {
+            error!("Could not create {} backend: {:#}", specs, e);
+            eprintln!("chatty: {:#}", e);
+            return Ok(ExitCode::from(cli::EXIT_BACKEND));
+        }
+    };
+    let backend = match cli.send_command {
+        Some(command) => {
+            info!("Sending with {}", command);
+            Box::new(CommandSender::new(backend, command))
+        }
+        None => backend,
+    };
+    let backend: Box<dyn MsgBackend> = if cli.contacts.is_empty() {
+        backend
+    } else {
+        match NameResolver::new(backend, cli.contacts) {
+            Ok(resolver) => Box::new(resolver),
+            Err(e) => {
+                error!("Could not read the address book: {:#}", e);
+                eprintln!("chatty: {:#}", e);
+                return Ok(ExitCode::from(cli::EXIT_BACKEND));
+            }
+        }
+    };
+
+    if let Some(command) = cli.command {
+        return Ok(cli::run(command, backend).await);
+    }
+
+    run_tui(backend).await?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn start_recording() -> Result<(), ExitCode> {
+    match recording::start() {
+        Ok(path) => {
+            info!("Recording to {:?}", path);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("chatty: could not start recording: {}", e);
+            Err(ExitCode::from(cli::EXIT_IO))
+        }
+    }
+}
+
+async fn run_tui(backend: impl MsgBackend) -> anyhow::Result<()> {
+    let (terminator, interrupt_rx) = create_termination();
     let (state_store, state_rx) = StateStore::new();
     let (ui_manager, action_rx) = UiManager::new();
 
-    info!("Creating backend...");
-    let backend = MockBackend::default();
-
     info!("Starting main loops...");
     tokio::try_join!(
         state_store.main_loop(terminator, backend, action_rx, interrupt_rx.resubscribe()),
@@ -38,3 +133,17 @@ async fn main() -> anyhow::Result<()> {
     info!("Exiting...");
     Ok(())
 }
+
+async fn attach_tui(connection: Connection) -> anyhow::Result<()> {
+    let (terminator, interrupt_rx) = create_termination();
+    let (state_tx, state_rx) = mpsc::unbounded_channel();
+    let (ui_manager, action_rx) = UiManager::new();
+
+    info!("Attaching to the daemon...");
+    tokio::try_join!(
+        connection.attach(terminator, action_rx, state_tx, interrupt_rx.resubscribe()),
+        ui_manager.main_loop(state_rx, interrupt_rx.resubscribe()),
+    )?;
+    info!("Detached from the daemon");
+    Ok(())
+}
@@ -0,0 +1,383 @@
+//! Plays back a recording made with `--record`, against the mock backend
+//! since the recorded one may not be around to reproduce anything
+
+use std::time::Duration;
+
+use anyhow::bail;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{event, Level};
+
+use crate::backends::{BackendEvent, EventInjector, Injection, MsgBackend};
+use crate::search::SearchIndex;
+use crate::state::{
+    action::Action,
+    recording::{Entry, Input},
+    reducer::{reduce, reduce_event, Effect},
+    store::{carry_out, index_event, initial_state, refresh},
+    Contact, Message, State, StateStore,
+};
+use crate::termination::create_termination;
+use crate::ui::UiManager;
+
+/// Applies the whole recording in order without waiting, the same recording
+/// always ends in the same state
+pub fn headless(mut backend: impl MsgBackend, entries: Vec<Entry>) -> State {
+    let mut state = initial_state(&backend);
+    let mut index = SearchIndex::build(&backend);
+
+    for entry in entries {
+        match entry.input {
+            Input::Action(Action::Exit) => break,
+            // What the injection caused was recorded as events
+            Input::Action(Action::Inject(_)) => {}
+            Input::Action(action) => {
+                if let Some(effect) = reduce(&mut state, action) {
+                    carry_out(&mut state, &mut backend, &mut index, effect);
+                }
+            }
+            Input::Event(backend_event) => {
+                // The mock takes on whatever the recorded backend had
+                let injection = Injection::Event(backend_event.clone());
+                carry_out(
+                    &mut state,
+                    &mut backend,
+                    &mut index,
+                    Effect::Inject(injection),
+                );
+                index_event(&mut index, &backend, &backend_event);
+                reduce_event(&mut state, backend_event);
+            }
+        }
+        refresh(&mut state, &backend, &mut index);
+    }
+
+    state
+}
+
+/// Shows the recording in the TUI, `speed` times as fast as it was recorded.
+/// The user can act too, and exits when they have seen enough.
+pub async fn tui(backend: impl MsgBackend, entries: Vec<Entry>, speed: f64) -> anyhow::Result<()> {
+    let (terminator, interrupt_rx) = create_termination();
+    let (state_store, state_rx) = StateStore::new();
+    let (ui_manager, mut ui_rx) = UiManager::new();
+    let (action_tx, action_rx) = mpsc::unbounded_channel();
+
+    let user_tx = action_tx.clone();
+    tokio::spawn(async move {
+        while let Some(action) = ui_rx.recv().await {
+            if user_tx.send(action).is_err() {
+                return;
+            }
+        }
+    });
+    tokio::spawn(feed(entries, speed, action_tx));
+
+    let backend = Replaying::new(backend);
+    tokio::try_join!(
+        state_store.main_loop(terminator, backend, action_rx, interrupt_rx.resubscribe()),
+        ui_manager.main_loop(state_rx, interrupt_rx.resubscribe()),
+    )?;
+    Ok(())
+}
+
+/// Sends the recorded actions to the store when they happened, with events
+/// injected into the backend to arrive the way they did
+async fn feed(entries: Vec<Entry>, speed: f64, action_tx: mpsc::UnboundedSender<Action>) {
+    let started = Instant::now();
+
+    for entry in entries {
+        let action = match entry.input {
+            Input::Action(Action::Exit | Action::Inject(_)) => continue,
+            Input::Action(action) => action,
+            Input::Event(backend_event) => Action::Inject(Injection::Event(backend_event)),
+        };
+
+        let at = Duration::from_millis(entry.at_ms).div_f64(speed);
+        tokio::time::sleep_until(started + at).await;
+        if action_tx.send(action).is_err() {
+            return;
+        }
+    }
+
+    event!(Level::INFO, "Replayed the whole recording");
+}
+
+/// The backend a recording plays back against in the TUI. The recorded
+/// events already hold everything the backend said during the session, so
+/// what it says about the replayed actions, such as a send failing while
+/// disconnected, is dropped rather than applied a second time. Injected
+/// events are applied to the backend and then passed on as recorded.
+struct Replaying<B> {
+    inner: B,
+    event_tx: UnboundedSender<BackendEvent>,
+    event_rx: Option<UnboundedReceiver<BackendEvent>>,
+}
+
+impl<B: MsgBackend> Replaying<B> {
+    fn new(inner: B) -> Self {
+        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        Self {
+            inner,
+            event_tx,
+            event_rx: Some(event_rx),
+        }
+    }
+}
+
+impl<B: MsgBackend> MsgBackend for Replaying<B> {
+    fn send_message(&mut self, message: Message) {
+        self.inner.send_message(message)
+    }
+
+    fn get_messages(&self, contact: &Contact, n: Option<u8>) -> Vec<Message> {
+        self.inner.get_messages(contact, n)
+    }
+
+    fn get_recent_contacts(&self) -> Vec<Contact> {
+        self.inner.get_recent_contacts()
+    }
+
+    fn take_events(&mut self) -> Option<UnboundedReceiver<BackendEvent>> {
+        let event_rx = self.event_rx.take()?;
+        // Kept open, since the backend reports injections through it too
+        if let Some(mut inner_rx) = self.inner.take_events() {
+            tokio::spawn(async move { while inner_rx.recv().await.is_some() {} });
+        }
+        Some(event_rx)
+    }
+
+    fn injector(&mut self) -> Option<&mut dyn EventInjector> {
+        Some(self)
+    }
+
+    fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+        self.inner.join_conversation(name)
+    }
+
+    fn start_conversation(&mut self, contact: Contact) -> anyhow::Result<Contact> {
+        self.inner.start_conversation(contact)
+    }
+
+    fn recognizes(&self, name: &str) -> bool {
+        self.inner.recognizes(name)
+    }
+
+    fn send_reaction(&mut self, message: &Message, reaction: &str) -> anyhow::Result<()> {
+        self.inner.send_reaction(message, reaction)
+    }
+
+    fn address_book(&self) -> Vec<Contact> {
+        self.inner.address_book()
+    }
+
+    fn close(&mut self) -> Option<JoinHandle<()>> {
+        self.inner.close()
+    }
+}
+
+impl<B: MsgBackend> EventInjector for Replaying<B> {
+    /// Passes the event on even when the backend could not apply it, as
+    /// `headless` does
+    fn inject(&mut self, injection: Injection) -> anyhow::Result<()> {
+        let Injection::Event(backend_event) = injection else {
+            bail!("only events can be injected while replaying");
+        };
+
+        let applied = match self.inner.injector() {
+            Some(injector) => injector.inject(Injection::Event(backend_event.clone())),
+            None => Ok(()),
+        };
+        let _ = self.event_tx.send(backend_event);
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::backends::{BackendEvent, MockBackend};
+    use crate::search::SearchQuery;
+    use crate::state::{recording, Contact, DeliveryStatus, Message, MessageDirection};
+    use crate::termination::Terminator;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn contact(name: &str, phone: &str) -> Contact {
+        Contact::new(name.to_string(), phone.to_string())
+    }
+
+    fn message(to: &Contact, content: &str, seconds: i64, direction: MessageDirection) -> Message {
+        let timestamp = DateTime::from_timestamp(seconds, 0).unwrap().naive_utc();
+        Message::new(to.clone(), content.to_string(), timestamp, direction)
+    }
+
+    /// Runs the store like the TUI does, recording what it is fed, and
+    /// returns the last state it sent out
+    async fn record_session(actions: Vec<Action>) -> State {
+        let (interrupt_tx, interrupt_rx) = broadcast::channel(1);
+        let (state_store, mut state_rx) = StateStore::new();
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+
+        let store = tokio::spawn(state_store.main_loop(
+            Terminator::new(interrupt_tx),
+            MockBackend::default(),
+            action_rx,
+            interrupt_rx,
+        ));
+
+        let mut state = state_rx.recv().await.unwrap();
+        for action in actions {
+            action_tx.send(action).unwrap();
+            // The events an injection causes come in before the next action
+            state = tokio::time::timeout(WAIT, state_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            while let Ok(Some(next)) =
+                tokio::time::timeout(Duration::from_millis(50), state_rx.recv()).await
+            {
+                state = next;
+            }
+        }
+
+        action_tx.send(Action::Exit).unwrap();
+        tokio::time::timeout(WAIT, store)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        state
+    }
+
+    /// Plays the recording through the store the way the TUI does, as fast
+    /// as it goes, and returns the last state it sent out and how many
+    /// backend events it saw
+    async fn play_session(entries: Vec<Entry>) -> (State, usize) {
+        let (interrupt_tx, interrupt_rx) = broadcast::channel(1);
+        let (state_store, mut state_rx) = StateStore::new();
+        let mut event_rx = state_store.events().subscribe();
+        let (action_tx, action_rx) = mpsc::unbounded_channel();
+
+        let store = tokio::spawn(state_store.main_loop(
+            Terminator::new(interrupt_tx),
+            Replaying::new(MockBackend::default()),
+            action_rx,
+            interrupt_rx,
+        ));
+
+        feed(entries, 1000.0, action_tx.clone()).await;
+        let mut state = state_rx.recv().await.unwrap();
+        while let Ok(Some(next)) =
+            tokio::time::timeout(Duration::from_millis(100), state_rx.recv()).await
+        {
+            state = next;
+        }
+
+        action_tx.send(Action::Exit).unwrap();
+        tokio::time::timeout(WAIT, store)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let mut events = 0;
+        while event_rx.try_recv().is_ok() {
+            events += 1;
+        }
+        (state, events)
+    }
+
+    #[tokio::test]
+    async fn replays_to_the_recorded_state() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = recording::start_in(directory.path()).unwrap();
+
+        let ben = contact("Ben Boy", "222-222-2222");
+        let stranger = contact("+15550001111", "+15550001111");
+        let sent = message(&ben, "on my way", 1724895200, MessageDirection::To);
+        let failed = message(&ben, "hello?", 1724895300, MessageDirection::To);
+        let event = |backend_event| Action::Inject(Injection::Event(backend_event));
+
+        let recorded = record_session(vec![
+            Action::FocusConversation(ben.clone()),
+            Action::SendMessage(sent.clone()),
+            event(BackendEvent::DeliveryReceipt {
+                message_id: sent.id.clone(),
+            }),
+            event(BackendEvent::Typing {
+                contact: stranger.clone(),
+                is_typing: true,
+            }),
+            event(BackendEvent::MessageReceived(message(
+                &stranger,
+                "who is this",
+                1724895250,
+                MessageDirection::From,
+            ))),
+            event(BackendEvent::Reaction {
+                message_id: String::from("mock-1"),
+                from: contact("Joe Smith", "111-111-1111"),
+                reaction: String::from("👍"),
+            }),
+            Action::MuteConversation(
+                stranger.clone(),
+                Some(DateTime::from_timestamp(1724899999, 0).unwrap().naive_utc()),
+            ),
+            Action::Search(SearchQuery::parse(&[String::from("who")]).unwrap()),
+            event(BackendEvent::Disconnected {
+                reason: String::from("lost the signal"),
+            }),
+            Action::SendMessage(failed.clone()),
+            Action::StartConversation(contact("Zed", "999-999-9999")),
+            Action::FocusConversation(ben.clone()),
+        ])
+        .await;
+
+        let entries = recording::read(&path).unwrap();
+        // The session's own actions and the events they led to
+        assert!(entries.len() > 11, "{} entries", entries.len());
+        let replayed = headless(MockBackend::default(), entries.clone());
+
+        assert_eq!(
+            serde_json::to_value(&replayed).unwrap(),
+            serde_json::to_value(&recorded).unwrap()
+        );
+        // Nothing of the session went missing on the way
+        let status = |id: &str| {
+            let messages = replayed.chat.messages.iter();
+            messages
+                .filter(|m| m.id == id)
+                .map(|m| m.status.clone())
+                .next()
+        };
+        assert_eq!(status(&sent.id), Some(DeliveryStatus::Delivered));
+        assert!(matches!(
+            status(&failed.id),
+            Some(DeliveryStatus::Failed(_))
+        ));
+        assert!(replayed.muted.contains_key(&stranger.phone));
+        assert_eq!(replayed.search.as_ref().unwrap().total, 1);
+        assert!(matches!(
+            replayed.connection,
+            crate::state::ConnectionStatus::Disconnected(_)
+        ));
+
+        // The TUI gets there too, hearing of the failed send only once
+        let recorded_events = entries
+            .iter()
+            .filter(|entry| matches!(entry.input, Input::Event(_)))
+            .count();
+        let (played, events) = play_session(entries).await;
+        assert_eq!(
+            serde_json::to_value(&played).unwrap(),
+            serde_json::to_value(&replayed).unwrap()
+        );
+        assert_eq!(events, recorded_events);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::state::{normalize_handle, Contact};
+
+/// Score of every character of the pattern that is found
+const MATCH: i64 = 16;
+/// Extra score for a character right after the previous match
+const CONSECUTIVE: i64 = 24;
+/// Extra score for a character that starts a word
+const WORD_START: i64 = 20;
+/// Score lost for every character skipped between two matches, up to
+/// `MAX_GAP_PENALTY` for each gap
+const GAP: i64 = 2;
+const MAX_GAP_PENALTY: i64 = 20;
+
+/// A fuzzy match of a pattern in a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// Higher is better
+    pub score: i64,
+    /// Indices of the matched characters in the text, counted in chars
+    pub positions: Vec<usize>,
+}
+
+/// Finds the characters of the pattern in the text, in order and ignoring
+/// case, preferring them together and at the starts of words. `None` when
+/// they are not all in it.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<FuzzyMatch> {
+    let pattern: Vec<char> = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let text: Vec<char> = text.chars().collect();
+    let lowered: Vec<char> = text
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    if pattern.is_empty() {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: Vec::new(),
+        });
+    }
+
+    // Matching greedily from each place the pattern could start and keeping
+    // the best is enough for names and numbers, which are short
+    lowered
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c == pattern[0])
+        .filter_map(|(start, _)| match_from(&pattern, &text, &lowered, start))
+        .max_by_key(|found| found.score)
+}
+
+fn match_from(
+    pattern: &[char],
+    text: &[char],
+    lowered: &[char],
+    start: usize,
+) -> Option<FuzzyMatch> {
+    let mut positions = Vec::with_capacity(pattern.len());
+    let mut next = start;
+    for c in pattern {
+        let found = next + lowered[next..].iter().position(|l| l == c)?;
+        positions.push(found);
+        next = found + 1;
+    }
+
+    let mut score = 0;
+    for (i, &position) in positions.iter().enumerate() {
+        score += MATCH;
+        if position == 0 || !text[position - 1].is_alphanumeric() {
+            score += WORD_START;
+        }
+        if i > 0 {
+            let gap = (position - positions[i - 1] - 1) as i64;
+            if gap == 0 {
+                score += CONSECUTIVE;
+            } else {
+                score -= (gap * GAP).min(MAX_GAP_PENALTY);
+            }
+        }
+    }
+    Some(FuzzyMatch { score, positions })
+}
+
+/// How well a conversation matches, by its name or group name, its handle in
+/// either form or its account
+pub fn match_contact(pattern: &str, contact: &Contact) -> Option<FuzzyMatch> {
+    let name = fuzzy_match(pattern, &contact.name);
+    let others = [
+        Some(contact.phone.clone()),
+        Some(normalize_handle(&contact.phone)),
+        contact.account.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|text| fuzzy_match(pattern, &text))
+    .max_by_key(|found| found.score)
+    // Only the name is highlighted
+    .map(|found| FuzzyMatch {
+        positions: Vec::new(),
+        ..found
+    });
+
+    match (name, others) {
+        (Some(name), Some(other)) if other.score > name.score => Some(other),
+        (Some(name), _) => Some(name),
+        (None, other) => other,
+    }
+}
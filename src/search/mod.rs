@@ -0,0 +1,310 @@
+//! Full-text search over the messages the backend has, with an inverted index
+//! of the words in them. The state store keeps the index up to date and
+//! answers `Action::Search` with `State::search`. Fuzzy matching of names for
+//! pickers lives here too.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+};
+
+use crate::backends::MsgBackend;
+use crate::state::{normalize_handle, Contact, Message, SearchHit, SearchResults};
+
+mod fuzzy;
+mod query;
+
+pub use fuzzy::{fuzzy_match, match_contact};
+pub use query::SearchQuery;
+
+/// Most hits kept for a search, the newest ones
+const RESULT_LIMIT: usize = 200;
+
+/// The lowercased words of a text with where they are in it, words being runs
+/// of letters and digits
+pub fn terms(text: &str) -> impl Iterator<Item = (Range<usize>, String)> + '_ {
+    text.char_indices()
+        .filter(|(_, c)| c.is_alphanumeric())
+        .filter(move |(start, _)| {
+            !text[..*start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric)
+        })
+        .map(move |(start, _)| {
+            let end = text[start..]
+                .find(|c: char| !c.is_alphanumeric())
+                .map_or(text.len(), |length| start + length);
+            (start..end, text[start..end].to_lowercase())
+        })
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    messages: Vec<Message>,
+    /// Where each indexed message is in `messages`
+    ids: HashMap<String, usize>,
+    /// The conversations whose whole history is indexed, by account and
+    /// handle
+    conversations: HashSet<(Option<String>, String)>,
+    /// The messages each word appears in, as (message, position of the word
+    /// in it), ordered by message
+    postings: HashMap<String, Vec<(usize, usize)>>,
+}
+
+impl SearchIndex {
+    pub fn build(backend: &impl MsgBackend) -> Self {
+        let mut index = Self::default();
+        index.update(backend);
+        index
+    }
+
+    /// Adds the backend's messages that are not indexed yet, for when it has
+    /// loaded more of the history
+    pub fn update(&mut self, backend: &impl MsgBackend) {
+        for contact in backend.get_recent_contacts() {
+            self.add_history(backend, &contact);
+        }
+    }
+
+    /// Adds the history of the conversations that are not indexed yet, for
+    /// backends that load it without a `Connected` event
+    pub fn add_conversations(&mut self, backend: &impl MsgBackend, contacts: &[Contact]) {
+        for contact in contacts {
+            if !self.conversations.contains(&conversation(contact)) {
+                self.add_history(backend, contact);
+            }
+        }
+    }
+
+    fn add_history(&mut self, backend: &impl MsgBackend, contact: &Contact) {
+        self.conversations.insert(conversation(contact));
+        for message in backend.get_messages(contact, None) {
+            self.add(message);
+        }
+    }
+
+    /// Indexes a message, or replaces the indexed one with the same id
+    pub fn add(&mut self, message: Message) {
+        let Some(&document) = self.ids.get(&message.id) else {
+            let document = self.messages.len();
+            self.ids.insert(message.id.clone(), document);
+            self.post(document, &message.content);
+            self.messages.push(message);
+            return;
+        };
+
+        let indexed = &self.messages[document];
+        if indexed.content != message.content {
+            let content = indexed.content.clone();
+            self.unpost(document, &content);
+            self.post(document, &message.content);
+        }
+        self.messages[document] = message;
+    }
+
+    /// Changes an indexed message, for events that only name it
+    pub fn update_message(&mut self, id: &str, change: impl FnOnce(&mut Message)) {
+        if let Some(&document) = self.ids.get(id) {
+            let mut message = self.messages[document].clone();
+            change(&mut message);
+            self.add(message);
+        }
+    }
+
+    /// Adds the words of a message, keeping the postings ordered by message
+    fn post(&mut self, document: usize, content: &str) {
+        for (position, (_, term)) in terms(content).enumerate() {
+            let postings = self.postings.entry(term).or_default();
+            let at = postings.partition_point(|&posting| posting < (document, position));
+            postings.insert(at, (document, position));
+        }
+    }
+
+    fn unpost(&mut self, document: usize, content: &str) {
+        for (_, term) in terms(content) {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.retain(|&(posted, _)| posted != document);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    pub fn search(&self, query: &SearchQuery) -> SearchResults {
+        let mut documents: Vec<usize> = match self.candidates(query) {
+            Some(documents) => documents,
+            None => (0..self.messages.len()).collect(),
+        };
+        documents.retain(|&document| {
+            self.has_phrases(document, query)
+                && self.passes_filters(&self.messages[document], query)
+        });
+
+        documents.sort_by_key(|&document| std::cmp::Reverse(self.messages[document].timestamp));
+        let total = documents.len();
+        let searched: HashSet<&str> = query.terms().map(String::as_str).collect();
+        let hits = documents
+            .into_iter()
+            .take(RESULT_LIMIT)
+            .map(|document| {
+                let message = self.messages[document].clone();
+                let highlights = terms(&message.content)
+                    .filter(|(_, term)| searched.contains(term.as_str()))
+                    .map(|(range, _)| range)
+                    .collect();
+                SearchHit {
+                    message,
+                    highlights,
+                }
+            })
+            .collect();
+
+        SearchResults {
+            query: query.text.clone(),
+            hits,
+            total,
+        }
+    }
+
+    /// The messages with every word of the query, `None` when it has none
+    /// and every message is a candidate
+    fn candidates(&self, query: &SearchQuery) -> Option<Vec<usize>> {
+        let mut postings: Vec<&[(usize, usize)]> = query
+            .terms()
+            .map(|term| self.postings.get(term).map_or(&[][..], Vec::as_slice))
+            .collect();
+        // Starting from the rarest word keeps the intersection small
+        postings.sort_by_key(|postings| postings.len());
+
+        let (rarest, rest) = postings.split_first()?;
+        let mut documents: Vec<usize> = rarest.iter().map(|(document, _)| *document).collect();
+        documents.dedup();
+        for postings in rest {
+            documents.retain(|&document| !positions(postings, document).is_empty());
+        }
+        Some(documents)
+    }
+
+    fn has_phrases(&self, document: usize, query: &SearchQuery) -> bool {
+        query.phrases.iter().all(|phrase| {
+            let positions: Vec<&[(usize, usize)]> = phrase
+                .iter()
+                .map(|term| {
+                    let postings = self.postings.get(term).map_or(&[][..], Vec::as_slice);
+                    positions(postings, document)
+                })
+                .collect();
+
+            positions[0].iter().any(|&(_, start)| {
+                positions[1..]
+                    .iter()
+                    .enumerate()
+                    .all(|(offset, following)| {
+                        following
+                            .binary_search_by_key(&(start + offset + 1), |&(_, position)| position)
+                            .is_ok()
+                    })
+            })
+        })
+    }
+
+    fn passes_filters(&self, message: &Message, query: &SearchQuery) -> bool {
+        let date = message.timestamp.date();
+        if query.since.is_some_and(|since| date < since)
+            || query.until.is_some_and(|until| date > until)
+        {
+            return false;
+        }
+
+        let Some(from) = &query.from else {
+            return true;
+        };
+        if message.sent_by_me() {
+            return from == "me";
+        }
+        let sender = message.sender.as_ref().unwrap_or(&message.contact);
+        sender.name.to_lowercase().contains(from.as_str())
+            || normalize_handle(&sender.phone) == normalize_handle(from)
+    }
+}
+
+/// The postings of one message, found by bisecting since they are ordered by
+/// message
+fn positions(postings: &[(usize, usize)], document: usize) -> &[(usize, usize)] {
+    let start = postings.partition_point(|&(d, _)| d < document);
+    let end = postings.partition_point(|&(d, _)| d <= document);
+    &postings[start..end]
+}
+
+fn conversation(contact: &Contact) -> (Option<String>, String) {
+    (contact.account.clone(), contact.phone.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::backends::MockBackend;
+    use crate::state::{DeliveryStatus, MessageDirection};
+
+    fn message(id: &str, content: &str) -> Message {
+        Message::new(
+            Contact::new(String::from("Joe"), String::from("+15551234567")),
+            content.to_string(),
+            DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
+            MessageDirection::From,
+        )
+        .with_id(id.to_string())
+    }
+
+    fn search(index: &SearchIndex, query: &str) -> Vec<String> {
+        let arguments: Vec<String> = query.split(' ').map(String::from).collect();
+        let query = SearchQuery::parse(&arguments).unwrap();
+        index
+            .search(&query)
+            .hits
+            .into_iter()
+            .map(|hit| hit.message.id)
+            .collect()
+    }
+
+    #[test]
+    fn replaces_changed_messages() {
+        let mut index = SearchIndex::default();
+        index.add(message("1", "lunch at noon"));
+        index.add(message("2", "dinner at eight"));
+
+        index.update_message("1", |message| message.status = DeliveryStatus::Read);
+        let hits = index.search(&SearchQuery::parse(&[String::from("lunch")]).unwrap());
+        assert_eq!(hits.total, 1);
+        assert_eq!(hits.hits[0].message.status, DeliveryStatus::Read);
+
+        index.add(message("1", "dinner instead"));
+        assert!(search(&index, "lunch").is_empty());
+        assert_eq!(search(&index, "dinner").len(), 2);
+        assert_eq!(search(&index, "\"dinner instead\""), ["1"]);
+        assert_eq!(search(&index, "at"), ["2"]);
+
+        // Unknown ids are left alone
+        index.update_message("3", |message| message.content = String::from("lunch"));
+        assert!(search(&index, "lunch").is_empty());
+    }
+
+    #[test]
+    fn indexes_new_conversations_once() {
+        let backend = MockBackend::default();
+        let mut index = SearchIndex::default();
+
+        let contacts = backend.get_recent_contacts();
+        index.add_conversations(&backend, &contacts[..1]);
+        assert_eq!(search(&index, "hey"), ["mock-1"]);
+        assert!(search(&index, "benny").is_empty());
+
+        index.add_conversations(&backend, &contacts);
+        assert_eq!(search(&index, "benny"), ["mock-2"]);
+        assert_eq!(index.messages.len(), 3);
+    }
+}
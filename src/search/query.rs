@@ -0,0 +1,71 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use super::terms;
+
+/// What to search the message history for. Every word and phrase has to
+/// appear in a message for it to match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchQuery {
+    /// The query as typed
+    pub text: String,
+    pub words: Vec<String>,
+    /// Words that have to appear one after the other
+    pub phrases: Vec<Vec<String>>,
+    /// Name or handle of the sender, `me` for messages sent from here
+    pub from: Option<String>,
+    /// First day to search
+    pub since: Option<NaiveDate>,
+    /// Last day to search
+    pub until: Option<NaiveDate>,
+}
+
+impl SearchQuery {
+    /// Reads a query from arguments already split like a shell would, so
+    /// that quoted phrases arrive as one argument. `from:`, `since:` and
+    /// `until:` filter, dates are YYYY-MM-DD.
+    pub fn parse(arguments: &[String]) -> Result<Self, String> {
+        let mut query = SearchQuery {
+            text: shlex::try_join(arguments.iter().map(String::as_str))
+                .unwrap_or_else(|_| arguments.join(" ")),
+            ..Default::default()
+        };
+
+        for argument in arguments {
+            if let Some(from) = argument.strip_prefix("from:") {
+                query.from = Some(from.trim().to_lowercase());
+            } else if let Some(since) = argument.strip_prefix("since:") {
+                query.since = Some(parse_date(since)?);
+            } else if let Some(until) = argument.strip_prefix("until:") {
+                query.until = Some(parse_date(until)?);
+            } else {
+                let mut words: Vec<String> = terms(argument).map(|(_, term)| term).collect();
+                match words.len() {
+                    0 => {}
+                    1 => query.words.append(&mut words),
+                    _ => query.phrases.push(words),
+                }
+            }
+        }
+
+        if query.words.is_empty()
+            && query.phrases.is_empty()
+            && query.from.is_none()
+            && query.since.is_none()
+            && query.until.is_none()
+        {
+            return Err(String::from("nothing to search for"));
+        }
+        Ok(query)
+    }
+
+    /// Every word to look up in the index, those of the phrases included
+    pub fn terms(&self) -> impl Iterator<Item = &String> {
+        self.words.iter().chain(self.phrases.iter().flatten())
+    }
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map_err(|_| format!("'{text}' is not a date like 2024-08-28"))
+}
@@ -1,8 +1,34 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
 use super::{Contact, Message};
+use crate::backends::Injection;
+use crate::export::ExportFormat;
+use crate::search::SearchQuery;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     Exit,
     SendMessage(Message),
+    /// Joins the conversation by name and sends the message to it, for
+    /// `chatty send` through the daemon to someone it does not list yet
+    JoinAndSend(String, Message),
+    /// Reacts to a message, with an emoji on most services
+    React(Message, String),
     FocusConversation(Contact),
+    /// Focuses the message's conversation and scrolls to the message
+    FocusMessage(Message),
+    /// Joins a channel or starts a conversation by name and focuses it
+    JoinConversation(String),
+    /// Focuses a conversation with someone the backend may not know yet, it
+    /// is listed from then on
+    StartConversation(Contact),
+    /// Mutes the conversation until the given time, `None` unmutes it
+    MuteConversation(Contact, Option<NaiveDateTime>),
+    /// Exports the focused conversation to the data dir
+    ExportConversation(ExportFormat),
+    /// Searches the message history, the results end up in `State::search`
+    Search(SearchQuery),
+    /// Simulates a backend event through the backend's test-injection interface
+    Inject(Injection),
 }
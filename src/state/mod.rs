@@ -2,5 +2,8 @@ pub use self::state::*;
 pub use self::store::StateStore;
 
 pub mod action;
+pub mod recording;
+pub mod reducer;
+#[allow(clippy::module_inception)]
 mod state;
-mod store;
+pub mod store;
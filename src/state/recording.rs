@@ -0,0 +1,111 @@
+//! Records everything the state store is fed, to reproduce bugs with
+//! `chatty replay`. Each line of a recording is an `Entry` as JSON.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{event, Level};
+
+use super::action::Action;
+use crate::backends::BackendEvent;
+use crate::logging::get_data_dir;
+
+static RECORDER: OnceLock<Mutex<Recorder>> = OnceLock::new();
+
+struct Recorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Input {
+    Action(Action),
+    Event(BackendEvent),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since recording started
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub input: Input,
+}
+
+/// Starts recording to a new file in the data dir, returning its path
+pub fn start() -> io::Result<PathBuf> {
+    start_in(&get_data_dir())
+}
+
+/// Starts recording to a new file in `directory`, returning its path
+pub fn start_in(directory: &Path) -> io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let name = chrono::offset::Local::now().format("recording-%Y%m%d-%H%M%S.jsonl");
+    let path = directory.join(name.to_string());
+
+    let recorder = Recorder {
+        writer: BufWriter::new(File::create(&path)?),
+        started: Instant::now(),
+    };
+    if RECORDER.set(Mutex::new(recorder)).is_err() {
+        return Err(io::Error::other("already recording"));
+    }
+    Ok(path)
+}
+
+pub fn record_action(action: &Action) {
+    record(|| Input::Action(action.clone()));
+}
+
+pub fn record_event(backend_event: &BackendEvent) {
+    record(|| Input::Event(backend_event.clone()));
+}
+
+/// Appends to the recording, if one was started. Every entry is flushed so
+/// that a crash does not lose what led up to it.
+fn record(input: impl FnOnce() -> Input) {
+    let Some(recorder) = RECORDER.get() else {
+        return;
+    };
+    let mut recorder = recorder.lock().unwrap();
+
+    let entry = Entry {
+        at_ms: recorder.started.elapsed().as_millis() as u64,
+        input: input(),
+    };
+    let result = serde_json::to_writer(&mut recorder.writer, &entry)
+        .map_err(io::Error::from)
+        .and_then(|()| writeln!(recorder.writer))
+        .and_then(|()| recorder.writer.flush());
+    if let Err(e) = result {
+        event!(Level::WARN, "Could not record {:?}: {}", entry.input, e);
+    }
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Vec<Entry>> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).with_context(|| {
+            format!(
+                "{} line {} is not a recorded entry",
+                path.display(),
+                number + 1
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
@@ -0,0 +1,268 @@
+use tracing::{event, Level};
+
+use super::{action::Action, normalize_handle, ConnectionStatus, Contact, Message, State};
+use crate::backends::{BackendEvent, Injection};
+use crate::export::ExportFormat;
+use crate::search::SearchQuery;
+
+/// The part of an action that needs the backend, carried out by the store
+#[derive(Debug)]
+pub enum Effect {
+    Exit,
+    Send(Message),
+    JoinAndSend(String, Message),
+    React(Message, String),
+    Join(String),
+    Start(Contact),
+    Export(Contact, ExportFormat),
+    Inject(Injection),
+    Search(SearchQuery),
+}
+
+/// Applies an action to the state. Anything that needs the backend is left
+/// to the caller, so that actions can be replayed without one.
+pub fn reduce(state: &mut State, action: Action) -> Option<Effect> {
+    match action {
+        Action::Exit => Some(Effect::Exit),
+        Action::SendMessage(message) => Some(Effect::Send(message)),
+        Action::JoinAndSend(name, message) => Some(Effect::JoinAndSend(name, message)),
+        Action::React(message, reaction) => Some(Effect::React(message, reaction)),
+        Action::FocusConversation(contact) => {
+            state.chat.contact = contact;
+            state.chat.focused_message = None;
+            None
+        }
+        Action::FocusMessage(message) => {
+            state.chat.contact = message.contact;
+            state.chat.focused_message = Some(message.id);
+            None
+        }
+        Action::JoinConversation(name) => Some(Effect::Join(name)),
+        Action::StartConversation(contact) => Some(Effect::Start(contact)),
+        Action::MuteConversation(contact, Some(until)) => {
+            state.muted.insert(contact.phone, until);
+            None
+        }
+        Action::MuteConversation(contact, None) => {
+            state.muted.remove(&contact.phone);
+            None
+        }
+        Action::ExportConversation(format) => {
+            Some(Effect::Export(state.chat.contact.clone(), format))
+        }
+        Action::Inject(injection) => Some(Effect::Inject(injection)),
+        Action::Search(query) => Some(Effect::Search(query)),
+    }
+}
+
+/// Updates the parts of the state the backend does not keep itself, everything
+/// else is picked up when the state is refreshed from the backend
+pub fn reduce_event(state: &mut State, backend_event: BackendEvent) {
+    event!(Level::DEBUG, "Received backend event: {:?}", backend_event);
+
+    match backend_event {
+        BackendEvent::Typing { contact, is_typing } => {
+            if is_typing {
+                state.typing.insert(contact.phone);
+            } else {
+                state.typing.remove(&contact.phone);
+            }
+        }
+        BackendEvent::MessageReceived(message) => {
+            // A contact stops typing once their message arrives
+            state.typing.remove(&message.contact.phone);
+        }
+        BackendEvent::ContactRenamed { phone, name } => {
+            if normalize_handle(&state.chat.contact.phone) == normalize_handle(&phone) {
+                state.chat.contact.name = name;
+            }
+        }
+        BackendEvent::Disconnected { reason } => {
+            event!(Level::WARN, "Backend disconnected: {}", reason);
+            state.connection = ConnectionStatus::Disconnected(reason);
+        }
+        BackendEvent::Connected => {
+            event!(Level::INFO, "Backend connected");
+            state.connection = ConnectionStatus::Connected;
+        }
+        BackendEvent::DeliveryFailed { message_id, reason } => {
+            event!(
+                Level::WARN,
+                "Message {} failed to send: {}",
+                message_id,
+                reason
+            );
+        }
+        BackendEvent::DeliveryReceipt { .. }
+        | BackendEvent::ReadReceipt { .. }
+        | BackendEvent::Reaction { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDateTime};
+
+    use super::*;
+    use crate::state::{Chat, ConversationList, MessageDirection};
+
+    fn contact(name: &str, phone: &str) -> Contact {
+        Contact::new(name.to_string(), phone.to_string())
+    }
+
+    fn at(seconds: i64) -> NaiveDateTime {
+        DateTime::from_timestamp(seconds, 0).unwrap().naive_utc()
+    }
+
+    fn state() -> State {
+        State::new(
+            Chat::new(contact("Joe", "+15551234567"), Vec::new()),
+            ConversationList::new(Vec::new()),
+        )
+    }
+
+    fn message(from: &Contact) -> Message {
+        Message::new(
+            from.clone(),
+            String::from("hi"),
+            at(0),
+            MessageDirection::From,
+        )
+    }
+
+    #[test]
+    fn focuses_conversations_and_messages() {
+        let mut state = state();
+        let ben = contact("Ben", "+15559876543");
+
+        let found = message(&ben);
+        assert!(reduce(&mut state, Action::FocusMessage(found.clone())).is_none());
+        assert_eq!(state.chat.contact, ben);
+        assert_eq!(state.chat.focused_message, Some(found.id));
+
+        let joe = contact("Joe", "+15551234567");
+        assert!(reduce(&mut state, Action::FocusConversation(joe.clone())).is_none());
+        assert_eq!(state.chat.contact, joe);
+        assert_eq!(state.chat.focused_message, None);
+    }
+
+    #[test]
+    fn mutes_and_unmutes() {
+        let mut state = state();
+        let joe = state.chat.contact.clone();
+
+        reduce(
+            &mut state,
+            Action::MuteConversation(joe.clone(), Some(at(60))),
+        );
+        assert_eq!(state.muted.get(&joe.phone), Some(&at(60)));
+        reduce(
+            &mut state,
+            Action::MuteConversation(joe.clone(), Some(at(120))),
+        );
+        assert_eq!(state.muted.get(&joe.phone), Some(&at(120)));
+
+        reduce(&mut state, Action::MuteConversation(joe.clone(), None));
+        assert!(state.muted.is_empty());
+    }
+
+    #[test]
+    fn leaves_what_needs_the_backend_to_the_caller() {
+        let mut state = state();
+        let joe = state.chat.contact.clone();
+        let sent = message(&joe);
+
+        assert!(matches!(
+            reduce(&mut state, Action::Exit),
+            Some(Effect::Exit)
+        ));
+        assert!(matches!(
+            reduce(&mut state, Action::SendMessage(sent.clone())),
+            Some(Effect::Send(message)) if message.id == sent.id
+        ));
+        assert!(matches!(
+            reduce(&mut state, Action::React(sent.clone(), String::from("👍"))),
+            Some(Effect::React(message, reaction)) if message.id == sent.id && reaction == "👍"
+        ));
+        assert!(matches!(
+            reduce(&mut state, Action::JoinConversation(String::from("#rust"))),
+            Some(Effect::Join(name)) if name == "#rust"
+        ));
+        assert!(matches!(
+            reduce(&mut state, Action::StartConversation(joe.clone())),
+            Some(Effect::Start(contact)) if contact == joe
+        ));
+        // Exports the conversation in focus
+        assert!(matches!(
+            reduce(&mut state, Action::ExportConversation(ExportFormat::Md)),
+            Some(Effect::Export(contact, ExportFormat::Md)) if contact == joe
+        ));
+
+        // None of that changed the state
+        assert_eq!(state.chat.contact, joe);
+        assert!(state.muted.is_empty());
+        assert!(state.search.is_none());
+    }
+
+    #[test]
+    fn tracks_who_is_typing() {
+        let mut state = state();
+        let joe = state.chat.contact.clone();
+
+        let typing = |is_typing| BackendEvent::Typing {
+            contact: joe.clone(),
+            is_typing,
+        };
+        reduce_event(&mut state, typing(true));
+        assert!(state.typing.contains(&joe.phone));
+        reduce_event(&mut state, typing(false));
+        assert!(state.typing.is_empty());
+
+        // Their message arriving ends it too
+        reduce_event(&mut state, typing(true));
+        reduce_event(&mut state, BackendEvent::MessageReceived(message(&joe)));
+        assert!(state.typing.is_empty());
+    }
+
+    #[test]
+    fn renames_the_focused_contact() {
+        let mut state = state();
+
+        reduce_event(
+            &mut state,
+            BackendEvent::ContactRenamed {
+                phone: String::from("+15559876543"),
+                name: String::from("Ben"),
+            },
+        );
+        assert_eq!(state.chat.contact.name, "Joe");
+
+        // However the number is written
+        reduce_event(
+            &mut state,
+            BackendEvent::ContactRenamed {
+                phone: String::from("+1 (555) 123-4567"),
+                name: String::from("Joseph"),
+            },
+        );
+        assert_eq!(state.chat.contact.name, "Joseph");
+    }
+
+    #[test]
+    fn follows_the_connection() {
+        let mut state = state();
+
+        reduce_event(
+            &mut state,
+            BackendEvent::Disconnected {
+                reason: String::from("gone"),
+            },
+        );
+        assert_eq!(
+            state.connection,
+            ConnectionStatus::Disconnected(String::from("gone"))
+        );
+        reduce_event(&mut state, BackendEvent::Connected);
+        assert_eq!(state.connection, ConnectionStatus::Connected);
+    }
+}
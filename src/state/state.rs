@@ -1,10 +1,28 @@
-use chrono::{NaiveDateTime};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Source of ids for messages created locally, which are prefixed with the
+/// process id since clients of the daemon create messages too
+static NEXT_LOCAL_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default, Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Contact {
     pub name: String,
     pub phone: String,
     pub has_unread: bool,
+    /// The account the conversation belongs to, when several backends run at
+    /// once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
 }
 
 impl Contact {
@@ -13,38 +31,121 @@ impl Contact {
             name,
             phone,
             has_unread: false,
+            account: None,
+        }
+    }
+
+    pub fn with_account(self, account: String) -> Self {
+        Self {
+            account: Some(account),
+            ..self
         }
     }
+}
+
+/// Reduces a phone number or email address to a form that can be compared
+/// between sources: emails are lowercased and phone numbers keep only their
+/// last ten digits so that country codes and formatting do not matter
+pub fn normalize_handle(handle: &str) -> String {
+    let handle = handle.trim();
+    if handle.contains('@') {
+        return handle.to_lowercase();
+    }
 
-    fn eq(&self, other: &Contact) -> bool {
-        self.name == other.name && self.phone == other.name
+    let digits: String = handle.chars().filter(char::is_ascii_digit).collect();
+    if digits.is_empty() {
+        // Alphanumeric senders such as short codes
+        return handle.to_lowercase();
     }
+
+    digits[digits.len().saturating_sub(10)..].to_string()
 }
 
 // TODO: Consider deleting this, what is it getting me?
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationList {
-    pub contacts: Vec<Contact>,
+    /// Shared with every copy of the state, see `Chat::messages`
+    pub contacts: Arc<[Contact]>,
 }
 
 impl ConversationList {
-    pub fn new(contacts : Vec<Contact>) -> Self {
-        Self {contacts}
+    pub fn new(contacts: Vec<Contact>) -> Self {
+        Self {
+            contacts: contacts.into(),
+        }
+    }
+
+    /// Replaces the contacts unless they are the same
+    pub fn update(&mut self, contacts: Vec<Contact>) {
+        if *self.contacts != *contacts {
+            self.contacts = contacts.into();
+        }
+    }
+
+    /// Finds a single contact by name, preferring an exact match over a
+    /// partial one. The error is meant to be shown to the user.
+    pub fn find_by_name(&self, name: &str) -> Result<&Contact, String> {
+        let name = name.to_lowercase();
+
+        if let Some(contact) = self.contacts.iter().find(|c| c.name.to_lowercase() == name) {
+            return Ok(contact);
+        }
+
+        let matches: Vec<&Contact> = self
+            .contacts
+            .iter()
+            .filter(|c| c.name.to_lowercase().contains(&name))
+            .collect();
+
+        match matches.as_slice() {
+            [] => Err(format!("no conversation matches '{name}'")),
+            [contact] => Ok(contact),
+            _ => Err(format!(
+                "'{name}' matches several conversations: {}",
+                matches
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum MessageDirection {
     To,
     From,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    Sent,
+    Delivered,
+    Read,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    pub from: Contact,
+    pub reaction: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
+    pub id: String,
     pub contact: Contact,
     pub content: String,
     pub timestamp: NaiveDateTime,
     pub direction: MessageDirection,
+    pub status: DeliveryStatus,
+    pub reactions: Vec<Reaction>,
+    /// Paths or URLs of files attached to the message
+    pub attachments: Vec<String>,
+    /// Who sent the message in a group conversation, where `contact` is the
+    /// group itself
+    pub sender: Option<Contact>,
 }
 
 impl Message {
@@ -55,40 +156,123 @@ impl Message {
         direction: MessageDirection,
     ) -> Self {
         Self {
+            id: format!(
+                "local-{}-{}",
+                std::process::id(),
+                NEXT_LOCAL_ID.fetch_add(1, Ordering::Relaxed)
+            ),
             contact,
             content,
             timestamp,
             direction,
+            status: DeliveryStatus::Sent,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            sender: None,
+        }
+    }
+
+    /// Replaces the local id with the one the backend knows the message by
+    pub fn with_id(self, id: String) -> Self {
+        Self { id, ..self }
+    }
+
+    pub fn with_sender(self, sender: Contact) -> Self {
+        Self {
+            sender: Some(sender),
+            ..self
         }
     }
 
-    pub fn sent_by_me(self) -> bool {
+    pub fn sent_by_me(&self) -> bool {
         self.direction == MessageDirection::To
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Chat {
     pub contact: Contact,
-    pub messages: Vec<Message>,
+    /// Shared with every copy of the state, which the store sends out after
+    /// each action. Only replaced when the messages change, so it is the same
+    /// list for as long as it is the same.
+    pub messages: Arc<[Message]>,
+    /// The message a search led to, the transcript scrolls to it and the
+    /// whole history stays loaded so that it is there
+    pub focused_message: Option<String>,
 }
 
 impl Chat {
     pub fn new(contact: Contact, messages: Vec<Message>) -> Self {
         Self {
-            contact, messages
+            contact,
+            messages: messages.into(),
+            focused_message: None,
         }
     }
+
+    /// Replaces the messages unless they are the same
+    pub fn update_messages(&mut self, messages: Vec<Message>) {
+        if *self.messages != *messages {
+            self.messages = messages.into();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub message: Message,
+    /// Where the searched words are in the message's content, in bytes
+    pub highlights: Vec<Range<usize>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchResults {
+    /// The query as typed
+    pub query: String,
+    /// The newest of the matching messages first, up to a limit
+    pub hits: Vec<SearchHit>,
+    /// How many messages matched, beyond the limit too
+    pub total: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum ConnectionStatus {
+    Connected,
+    Disconnected(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     pub chat: Chat,
     pub conversations: ConversationList,
+    /// Muted conversations keyed by phone number, with the time the mute ends
+    pub muted: HashMap<String, NaiveDateTime>,
+    /// Phone numbers of the contacts that are currently typing
+    pub typing: HashSet<String>,
+    pub connection: ConnectionStatus,
+    /// Results of the last search
+    pub search: Option<Arc<SearchResults>>,
+    /// People from the address book, to start conversations with
+    #[serde(default)]
+    pub address_book: Arc<[Contact]>,
 }
 
 impl State {
     pub fn new(chat: Chat, conversations: ConversationList) -> Self {
-        Self {chat, conversations}
+        Self {
+            chat,
+            conversations,
+            muted: HashMap::new(),
+            typing: HashSet::new(),
+            connection: ConnectionStatus::Connected,
+            search: None,
+            address_book: Vec::new().into(),
+        }
+    }
+
+    pub fn is_muted(&self, contact: &Contact) -> bool {
+        self.muted
+            .get(&contact.phone)
+            .is_some_and(|until| *until > chrono::offset::Local::now().naive_local())
     }
 }
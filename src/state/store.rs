@@ -1,22 +1,47 @@
+use std::{sync::Arc, time::Duration};
+
 use tokio::sync::{
     broadcast,
     mpsc::{self, UnboundedReceiver, UnboundedSender},
 };
+use tracing::{event, Level};
 
-use crate::backends::MsgBackend;
+use crate::backends::{BackendEvent, MsgBackend};
+use crate::export::{self, DateRange};
+use crate::search::SearchIndex;
 use crate::{Interrupted, Terminator};
 
-use super::{action::Action, Chat, ConversationList, State};
+use super::reducer::{reduce, reduce_event, Effect};
+use super::{
+    action::Action, recording, Chat, Contact, ConversationList, DeliveryStatus, Message, Reaction,
+    State,
+};
+
+/// How long to let the backend say goodbye when exiting
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Backend events kept for subscribers that fall behind
+const EVENT_CAPACITY: usize = 256;
+/// Messages loaded of the focused conversation, unless the whole history is
+/// needed for a message picked from search results
+const RECENT_MESSAGES: u8 = 100;
 
 pub struct StateStore {
     state_tx: UnboundedSender<State>,
+    event_tx: broadcast::Sender<BackendEvent>,
 }
 
 impl StateStore {
     pub fn new() -> (Self, UnboundedReceiver<State>) {
         let (state_tx, state_rx) = mpsc::unbounded_channel::<State>();
+        let (event_tx, _) = broadcast::channel(EVENT_CAPACITY);
 
-        (StateStore { state_tx }, state_rx)
+        (StateStore { state_tx, event_tx }, state_rx)
+    }
+
+    /// The backend events as the store receives them, for clients that need
+    /// more than the state
+    pub fn events(&self) -> broadcast::Sender<BackendEvent> {
+        self.event_tx.clone()
     }
 
     pub async fn main_loop(
@@ -26,35 +51,48 @@ impl StateStore {
         mut action_rx: UnboundedReceiver<Action>,
         mut interrupt_rx: broadcast::Receiver<Interrupted>,
     ) -> anyhow::Result<Interrupted> {
-        let conversations = backend.get_recent_contacts();
-        let msgs = backend.get_messages(&conversations[0], Some(100));
+        let mut state = initial_state(&backend);
+        let mut index = SearchIndex::build(&backend);
 
-        let mut state = State::new(
-            Chat::new(conversations[0].clone(), msgs),
-            ConversationList::new(conversations),
-        );
+        // Backends without events get a closed channel, disabling that branch
+        let mut backend_rx = backend
+            .take_events()
+            .unwrap_or_else(|| mpsc::unbounded_channel().1);
 
         self.state_tx.send(state.clone())?;
 
         let result = loop {
             tokio::select! {
                 // Handle any actions that are received
-                Some(action) = action_rx.recv() => match action {
-                    Action::Exit => {
-                        let _ = terminator.terminate(Interrupted::UserInt);
-                        break Interrupted::UserInt;
+                Some(action) = action_rx.recv() => {
+                    recording::record_action(&action);
+                    match reduce(&mut state, action) {
+                        Some(Effect::Exit) => {
+                            if let Some(task) = backend.close() {
+                                let _ = tokio::time::timeout(CLOSE_TIMEOUT, task).await;
+                            }
+                            let _ = terminator.terminate(Interrupted::UserInt);
+                            break Interrupted::UserInt;
+                        }
+                        Some(effect) => {
+                            // Failures the store noticed itself reach the
+                            // clients like the backend's own
+                            if let Some(failed) = carry_out(&mut state, &mut backend, &mut index, effect) {
+                                let _ = self.event_tx.send(failed);
+                            }
+                        }
+                        None => {}
                     }
-                    Action::SendMessage(msg) => {
-                        backend.send_message(
-                            msg
-                        );
-                    }
-                    Action::FocusConversation(contact) => {
-                        state.chat.contact = contact;
-                    }
-                    //_ => (),
                 },
 
+                // Handle events pushed by the backend
+                Some(backend_event) = backend_rx.recv() => {
+                    recording::record_event(&backend_event);
+                    // Nobody listening is fine
+                    let _ = self.event_tx.send(backend_event.clone());
+                    index_event(&mut index, &backend, &backend_event);
+                    reduce_event(&mut state, backend_event);
+                },
 
                 // Handle Interruptions
                 Ok(interrupted) = interrupt_rx.recv() => {
@@ -62,9 +100,7 @@ impl StateStore {
                 }
             }
 
-            // Update state from backend
-            state.chat.messages = backend.get_messages(&state.chat.contact, Some(100));
-            state.conversations = ConversationList::new(backend.get_recent_contacts());
+            refresh(&mut state, &backend, &mut index);
 
             // Send state out
             self.state_tx.send(state.clone())?;
@@ -73,3 +109,394 @@ impl StateStore {
         Ok(result)
     }
 }
+
+/// The state before any action, focused on the most recent conversation
+pub fn initial_state(backend: &impl MsgBackend) -> State {
+    let conversations = backend.get_recent_contacts();
+    // Backends that connect in the background may not have any yet
+    let contact = conversations.first().cloned().unwrap_or_default();
+    let msgs = backend.get_messages(&contact, Some(RECENT_MESSAGES));
+
+    let mut state = State::new(
+        Chat::new(contact, msgs),
+        ConversationList::new(conversations),
+    );
+    state.address_book = backend.address_book().into();
+    state
+}
+
+/// Carries out the part of an action that needs the backend, except exiting
+/// which is up to the caller. Returns the failure of a send that never reached
+/// the backend.
+pub fn carry_out(
+    state: &mut State,
+    backend: &mut impl MsgBackend,
+    index: &mut SearchIndex,
+    effect: Effect,
+) -> Option<BackendEvent> {
+    match effect {
+        Effect::Exit => {}
+        Effect::Send(message) => {
+            index.add(message.clone());
+            backend.send_message(message);
+        }
+        Effect::JoinAndSend(name, mut message) => match backend.join_conversation(&name) {
+            Ok(contact) => {
+                message.contact = contact;
+                index.add(message.clone());
+                backend.send_message(message);
+            }
+            Err(e) => {
+                event!(Level::WARN, "Could not join {}: {}", name, e);
+                return Some(BackendEvent::DeliveryFailed {
+                    message_id: message.id,
+                    reason: format!("could not find or join '{}': {:#}", name, e),
+                });
+            }
+        },
+        Effect::React(message, reaction) => {
+            if let Err(e) = backend.send_reaction(&message, &reaction) {
+                event!(Level::WARN, "Could not react to {}: {}", message.id, e);
+            }
+        }
+        Effect::Join(name) => match backend.join_conversation(&name) {
+            Ok(contact) => {
+                reduce(state, Action::FocusConversation(contact));
+            }
+            Err(e) => event!(Level::WARN, "Could not join {}: {}", name, e),
+        },
+        Effect::Start(contact) => match backend.start_conversation(contact) {
+            Ok(contact) => {
+                reduce(state, Action::FocusConversation(contact));
+            }
+            Err(e) => event!(Level::WARN, "Could not start conversation: {}", e),
+        },
+        Effect::Export(contact, format) => {
+            let chats = export::collect_chats(backend, vec![contact.clone()], DateRange::default());
+            match export::export_to_data_dir(&chats, format, &contact.name) {
+                Ok(path) => event!(Level::INFO, "Exported conversation to {:?}", path),
+                Err(e) => event!(Level::ERROR, "Could not export conversation: {}", e),
+            }
+        }
+        Effect::Inject(injection) => {
+            let result = match backend.injector() {
+                Some(injector) => injector.inject(injection),
+                None => Err(anyhow::anyhow!("backend does not support injecting events")),
+            };
+            if let Err(e) = result {
+                event!(Level::WARN, "Could not inject event: {}", e);
+            }
+        }
+        Effect::Search(query) => {
+            let results = index.search(&query);
+            event!(
+                Level::INFO,
+                "Search for {} found {} messages",
+                results.query,
+                results.total
+            );
+            state.search = Some(Arc::new(results));
+        }
+    }
+    None
+}
+
+/// Keeps the index up to date with what the backend reports
+pub fn index_event(
+    index: &mut SearchIndex,
+    backend: &impl MsgBackend,
+    backend_event: &BackendEvent,
+) {
+    match backend_event {
+        BackendEvent::MessageReceived(message) => index.add(message.clone()),
+        // Backends load their history once connected
+        BackendEvent::Connected => index.update(backend),
+        BackendEvent::DeliveryReceipt { message_id } => {
+            index.update_message(message_id, |message| {
+                message.status = DeliveryStatus::Delivered;
+            });
+        }
+        BackendEvent::ReadReceipt { message_id } => {
+            index.update_message(message_id, |message| {
+                message.status = DeliveryStatus::Read;
+            });
+        }
+        BackendEvent::DeliveryFailed { message_id, reason } => {
+            index.update_message(message_id, |message| {
+                message.status = DeliveryStatus::Failed(reason.clone());
+            });
+        }
+        BackendEvent::Reaction {
+            message_id,
+            from,
+            reaction,
+        } => {
+            index.update_message(message_id, |message| {
+                message.reactions.retain(|r| r.from.phone != from.phone);
+                message.reactions.push(Reaction {
+                    from: from.clone(),
+                    reaction: reaction.clone(),
+                });
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Updates the state from the backend, and the index with whatever history
+/// the backend loaded without saying so
+pub fn refresh(state: &mut State, backend: &impl MsgBackend, index: &mut SearchIndex) {
+    let loaded = state.chat.messages.clone();
+    let messages = focused_messages(state, backend);
+    state.chat.update_messages(messages);
+    if !Arc::ptr_eq(&loaded, &state.chat.messages) {
+        for message in state.chat.messages.iter() {
+            index.add(message.clone());
+        }
+    }
+    let mut contacts = backend.get_recent_contacts();
+    index.add_conversations(backend, &contacts);
+    // A conversation started here is listed before the backend has any of it
+    let focused = &state.chat.contact;
+    if *focused != Contact::default()
+        && !contacts
+            .iter()
+            .any(|c| c.phone == focused.phone && c.account == focused.account)
+    {
+        contacts.insert(0, focused.clone());
+    }
+    state.conversations.update(contacts);
+    let address_book = backend.address_book();
+    if *state.address_book != *address_book {
+        state.address_book = address_book.into();
+    }
+    if state.chat.contact == Contact::default() {
+        if let Some(contact) = state.conversations.contacts.first().cloned() {
+            state
+                .chat
+                .update_messages(backend.get_messages(&contact, Some(RECENT_MESSAGES)));
+            state.chat.contact = contact;
+        }
+    }
+}
+
+/// The messages of the focused conversation. A message picked from search
+/// results needs the whole history, which is loaded once; after that only the
+/// recent messages are, in place of the same ones at the end of the history.
+fn focused_messages(state: &mut State, backend: &impl MsgBackend) -> Vec<Message> {
+    let chat = &state.chat;
+    let Some(focused) = &chat.focused_message else {
+        return backend.get_messages(&chat.contact, Some(RECENT_MESSAGES));
+    };
+
+    if chat.messages.iter().any(|message| &message.id == focused) {
+        let recent = backend.get_messages(&chat.contact, Some(RECENT_MESSAGES));
+        if let Some(messages) = with_earlier(&chat.messages, recent) {
+            return messages;
+        }
+    }
+
+    let messages = backend.get_messages(&chat.contact, None);
+    // Nothing to scroll to, and no reason to load everything again
+    if !messages.iter().any(|message| &message.id == focused) {
+        state.chat.focused_message = None;
+    }
+    messages
+}
+
+/// The messages of `loaded` from before the first of `recent`, then
+/// `recent`. `None` when more messages came in than `recent` holds, so that
+/// the two do not overlap.
+fn with_earlier(loaded: &[Message], recent: Vec<Message>) -> Option<Vec<Message>> {
+    // Fewer than asked for is the whole history
+    if recent.len() < RECENT_MESSAGES as usize {
+        return Some(recent);
+    }
+
+    let first = &recent.first()?.id;
+    let earlier = loaded.iter().position(|message| &message.id == first)?;
+    Some(loaded[..earlier].iter().cloned().chain(recent).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::search::SearchQuery;
+    use crate::state::MessageDirection;
+
+    /// One conversation, counting how often its whole history is read
+    #[derive(Default)]
+    struct FakeBackend {
+        messages: Vec<Message>,
+        full_reads: Cell<usize>,
+    }
+
+    impl FakeBackend {
+        fn receive(&mut self, count: usize) {
+            for _ in 0..count {
+                let n = self.messages.len();
+                self.messages.push(
+                    Message::new(
+                        joe(),
+                        format!("message {}", n),
+                        DateTime::from_timestamp(n as i64, 0).unwrap().naive_utc(),
+                        MessageDirection::From,
+                    )
+                    .with_id(format!("{}", n)),
+                );
+            }
+        }
+    }
+
+    impl MsgBackend for FakeBackend {
+        fn send_message(&mut self, message: Message) {
+            self.messages.push(message);
+        }
+
+        fn get_messages(&self, _contact: &Contact, n: Option<u8>) -> Vec<Message> {
+            let skip = match n {
+                Some(n) => self.messages.len().saturating_sub(n as usize),
+                None => {
+                    self.full_reads.set(self.full_reads.get() + 1);
+                    0
+                }
+            };
+            self.messages[skip..].to_vec()
+        }
+
+        fn get_recent_contacts(&self) -> Vec<Contact> {
+            vec![joe()]
+        }
+
+        fn join_conversation(&mut self, name: &str) -> anyhow::Result<Contact> {
+            anyhow::ensure!(name.starts_with('#'), "not a channel");
+            Ok(Contact::new(name.to_string(), name.to_string()))
+        }
+    }
+
+    fn joe() -> Contact {
+        Contact::new(String::from("Joe"), String::from("+15551234567"))
+    }
+
+    fn ids(state: &State) -> Vec<String> {
+        state.chat.messages.iter().map(|m| m.id.clone()).collect()
+    }
+
+    #[test]
+    fn loads_the_whole_history_once_for_a_focused_message() {
+        let mut backend = FakeBackend::default();
+        backend.receive(250);
+        let mut state = initial_state(&backend);
+        let mut index = SearchIndex::default();
+        assert_eq!(state.chat.messages.len(), RECENT_MESSAGES as usize);
+
+        state.chat.focused_message = Some(String::from("3"));
+        refresh(&mut state, &backend, &mut index);
+        assert_eq!(state.chat.messages.len(), 250);
+        // The index reads the history once too
+        let reads = backend.full_reads.get();
+
+        backend.receive(10);
+        refresh(&mut state, &backend, &mut index);
+        refresh(&mut state, &backend, &mut index);
+        assert_eq!(backend.full_reads.get(), reads);
+        assert_eq!(
+            ids(&state),
+            (0..260).map(|n| n.to_string()).collect::<Vec<_>>()
+        );
+
+        // More new messages than the recent ones hold
+        backend.receive(150);
+        refresh(&mut state, &backend, &mut index);
+        assert_eq!(backend.full_reads.get(), reads + 1);
+        assert_eq!(state.chat.messages.len(), 410);
+    }
+
+    #[test]
+    fn forgets_a_focused_message_that_is_gone() {
+        let mut backend = FakeBackend::default();
+        backend.receive(5);
+        let mut state = initial_state(&backend);
+        let mut index = SearchIndex::default();
+
+        state.chat.focused_message = Some(String::from("deleted"));
+        refresh(&mut state, &backend, &mut index);
+        assert_eq!(state.chat.focused_message, None);
+        assert_eq!(ids(&state), ["0", "1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn indexes_history_and_changes() {
+        let mut backend = FakeBackend::default();
+        backend.receive(2);
+        let mut state = initial_state(&backend);
+        let mut index = SearchIndex::default();
+
+        // History loaded without a Connected event
+        refresh(&mut state, &backend, &mut index);
+        let query = SearchQuery::parse(&[String::from("message")]).unwrap();
+        assert_eq!(index.search(&query).total, 2);
+
+        index_event(
+            &mut index,
+            &backend,
+            &BackendEvent::ReadReceipt {
+                message_id: String::from("1"),
+            },
+        );
+        let thumbs = |emoji: &str| BackendEvent::Reaction {
+            message_id: String::from("1"),
+            from: joe(),
+            reaction: emoji.to_string(),
+        };
+        index_event(&mut index, &backend, &thumbs("👍"));
+        index_event(&mut index, &backend, &thumbs("👎"));
+
+        let results = index.search(&query);
+        let read = results
+            .hits
+            .iter()
+            .find(|hit| hit.message.id == "1")
+            .unwrap();
+        assert_eq!(read.message.status, DeliveryStatus::Read);
+        assert_eq!(read.message.reactions.len(), 1);
+        assert_eq!(read.message.reactions[0].reaction, "👎");
+    }
+
+    #[test]
+    fn joins_before_sending() {
+        let mut backend = FakeBackend::default();
+        let mut state = initial_state(&backend);
+        let mut index = SearchIndex::default();
+        let message = |to: &str| {
+            Message::new(
+                Contact::new(to.to_string(), to.to_string()),
+                String::from("hi"),
+                DateTime::from_timestamp(0, 0).unwrap().naive_utc(),
+                MessageDirection::To,
+            )
+        };
+
+        let joined = message("#rust");
+        let effect = Effect::JoinAndSend(String::from("#rust"), joined.clone());
+        assert!(carry_out(&mut state, &mut backend, &mut index, effect).is_none());
+        assert_eq!(backend.messages[0].id, joined.id);
+        // Sending leaves the focus alone
+        assert_eq!(state.chat.contact, joe());
+
+        let refused = message("nobody");
+        let effect = Effect::JoinAndSend(String::from("nobody"), refused.clone());
+        let failed = carry_out(&mut state, &mut backend, &mut index, effect);
+        assert!(matches!(
+            failed,
+            Some(BackendEvent::DeliveryFailed { message_id, reason })
+                if message_id == refused.id
+                    && reason == "could not find or join 'nobody': not a channel"
+        ));
+        assert_eq!(backend.messages.len(), 1);
+    }
+}
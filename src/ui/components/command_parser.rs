@@ -0,0 +1,16 @@
+use clap::Parser;
+
+/// Splits a command line the way a shell would and parses it with a `clap`
+/// multicall parser.
+///
+/// On failure the error is returned already formatted for display, including
+/// clap's usage and help output.
+pub fn parse_command<C: Parser>(line: &str) -> Result<C, String> {
+    let args = shlex::split(line).ok_or_else(|| String::from("unbalanced quotes"))?;
+
+    if args.is_empty() {
+        return Err(String::from("no command given"));
+    }
+
+    C::try_parse_from(args).map_err(|e| e.render().to_string())
+}
@@ -1,7 +1,10 @@
+use std::cell::Cell;
+
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
 use ratatui::{
     prelude::Rect,
     style::{Color, Style, Stylize},
+    text::Span,
     widgets::{Block, Borders, Paragraph},
     Frame,
 };
@@ -15,8 +18,11 @@ pub struct InputBox {
     _action_tx: UnboundedSender<Action>,
     /// Current value of the input box
     text: String,
-    /// Position of cursor in the editor area.
+    /// Position of cursor in the editor area, counted in chars
     cursor_position: usize,
+    /// Chars scrolled out of view on the left as of the last render, so the
+    /// cursor stays in the box
+    scroll: Cell<usize>,
 }
 
 impl InputBox {
@@ -24,9 +30,11 @@ impl InputBox {
         &self.text
     }
 
+    /// Only the dev console, which is left out of release builds, sets text
+    #[cfg(any(debug_assertions, test))]
     pub fn set_text(&mut self, new_text: &str) {
         self.text = String::from(new_text);
-        self.cursor_position = self.text.len();
+        self.cursor_position = self.text.chars().count();
     }
 
     pub fn reset(&mut self) {
@@ -38,6 +46,21 @@ impl InputBox {
         self.text.is_empty()
     }
 
+    /// Moves the cursor to the char shown at `column` of the editor area,
+    /// or to the end of the text when the column is past it
+    pub fn click(&mut self, column: u16) {
+        let mut width = 0;
+        let mut position = self.scroll.get();
+        for c in self.text.chars().skip(position) {
+            width += char_width(c);
+            if width > column as usize {
+                break;
+            }
+            position += 1;
+        }
+        self.cursor_position = self.clamp_cursor(position);
+    }
+
     fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.cursor_position.saturating_sub(1);
         self.cursor_position = self.clamp_cursor(cursor_moved_left);
@@ -49,7 +72,12 @@ impl InputBox {
     }
 
     fn enter_char(&mut self, new_char: char) {
-        self.text.insert(self.cursor_position, new_char);
+        let index = self
+            .text
+            .char_indices()
+            .nth(self.cursor_position)
+            .map_or(self.text.len(), |(index, _)| index);
+        self.text.insert(index, new_char);
 
         self.move_cursor_right();
     }
@@ -77,10 +105,33 @@ impl InputBox {
     }
 
     fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
-        new_cursor_pos.clamp(0, self.text.len())
+        new_cursor_pos.clamp(0, self.text.chars().count())
+    }
+
+    /// Scrolls just far enough for the cursor to fit in `width` columns
+    fn scroll_to_cursor(&self, width: usize) -> usize {
+        let mut scroll = self.scroll.get().min(self.cursor_position);
+        let before_cursor = |scroll: usize| -> usize {
+            self.text
+                .chars()
+                .skip(scroll)
+                .take(self.cursor_position - scroll)
+                .map(char_width)
+                .sum()
+        };
+        // The cursor takes a column of its own
+        while scroll < self.cursor_position && before_cursor(scroll) >= width {
+            scroll += 1;
+        }
+        self.scroll.set(scroll);
+        scroll
     }
 }
 
+fn char_width(c: char) -> usize {
+    Span::raw(c.to_string()).width()
+}
+
 impl Component for InputBox {
     fn new(_state: &State, _action_tx: UnboundedSender<Action>) -> Self {
         Self {
@@ -88,6 +139,7 @@ impl Component for InputBox {
             //
             text: String::new(),
             cursor_position: 0,
+            scroll: Cell::new(0),
         }
     }
 
@@ -129,13 +181,24 @@ pub struct RenderProps {
     pub title: String,
     pub area: Rect,
     pub border_color: Color,
+    pub text_color: Color,
     pub show_cursor: bool,
 }
 
 impl ComponentRender<RenderProps> for InputBox {
     fn render(&self, frame: &mut Frame, props: RenderProps) {
-        let input = Paragraph::new(self.text.as_str())
-            .style(Style::default().fg(Color::Yellow))
+        // Inside the borders
+        let width = props.area.width.saturating_sub(2) as usize;
+        let scroll = self.scroll_to_cursor(width);
+        let visible: String = self.text.chars().skip(scroll).collect();
+        let cursor_column: usize = visible
+            .chars()
+            .take(self.cursor_position - scroll)
+            .map(char_width)
+            .sum();
+
+        let input = Paragraph::new(visible)
+            .style(Style::default().fg(props.text_color))
             .block(
                 Block::default()
                     .borders(Borders::ALL)
@@ -152,10 +215,64 @@ impl ComponentRender<RenderProps> for InputBox {
             frame.set_cursor(
                 // Draw the cursor at the current position in the input field.
                 // This position is can be controlled via the left and right arrow key
-                props.area.x + self.cursor_position as u16 + 1,
+                props.area.x + cursor_column as u16 + 1,
                 // Move one line down, from the border to the input line
                 props.area.y + 1,
             )
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::state::{Chat, Contact, ConversationList};
+
+    fn input_box(text: &str) -> InputBox {
+        let (action_tx, _) = mpsc::unbounded_channel();
+        let state = State::new(
+            Chat::new(Contact::default(), Vec::new()),
+            ConversationList::new(Vec::new()),
+        );
+        let mut input_box = InputBox::new(&state, action_tx);
+        input_box.set_text(text);
+        input_box
+    }
+
+    #[test]
+    fn click_inside_non_ascii_text_inserts_at_the_char() {
+        let mut input_box = input_box("héllo wörld");
+        input_box.click(2);
+        input_box.enter_char('x');
+        assert_eq!(input_box.text(), "héxllo wörld");
+    }
+
+    #[test]
+    fn click_past_the_end_moves_to_the_end() {
+        let mut input_box = input_box("ünï");
+        input_box.click(40);
+        input_box.enter_char('!');
+        assert_eq!(input_box.text(), "ünï!");
+    }
+
+    #[test]
+    fn click_counts_from_the_scrolled_text() {
+        let mut input_box = input_box("0123456789");
+        // Eight columns wide with the cursor at the end, so "3456789" and the
+        // cursor are shown
+        assert_eq!(input_box.scroll_to_cursor(8), 3);
+        input_box.click(0);
+        input_box.enter_char('x');
+        assert_eq!(input_box.text(), "012x3456789");
+    }
+
+    #[test]
+    fn click_counts_wide_chars_as_two_columns() {
+        let mut input_box = input_box("日本語");
+        input_box.click(3);
+        input_box.enter_char('x');
+        assert_eq!(input_box.text(), "日x本語");
+    }
+}
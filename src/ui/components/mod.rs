@@ -1,4 +1,5 @@
 pub mod component;
 
+pub mod command_parser;
 pub mod input_box;
 pub use component::{Component, ComponentRender};
@@ -5,14 +5,17 @@ use std::{
 
 use anyhow::Context;
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream, MouseEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::prelude::*;
-use tokio::sync::{
-    broadcast,
-    mpsc::{self, UnboundedReceiver},
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, UnboundedReceiver},
+    },
+    time::MissedTickBehavior,
 };
 use tokio_stream::StreamExt;
 
@@ -22,11 +25,14 @@ use super::router::AppRouter;
 use crate::ui::components::Component;
 use crate::ui::components::ComponentRender;
 use crate::{
+    logging::LOG_BUFFER,
     state::{action::Action, State},
     Interrupted,
 };
 
-const RENDERING_TICK_RATE: Duration = Duration::from_millis(250);
+/// How often to look for new log records while the log viewer is open,
+/// nothing else changes on screen by itself
+const LIVE_REFRESH_RATE: Duration = Duration::from_millis(250);
 
 pub struct UiManager {
     action_tx: mpsc::UnboundedSender<Action>,
@@ -44,38 +50,60 @@ impl UiManager {
         mut interrupt_rx: broadcast::Receiver<Interrupted>,
     ) -> anyhow::Result<Interrupted> {
         let mut router = {
-            let state = state_rx.recv().await.unwrap();
+            let Some(state) = state_rx.recv().await else {
+                anyhow::bail!("no state to show");
+            };
             AppRouter::new(&state, self.action_tx.clone())
         };
 
         let mut terminal = setup_terminal()?;
-        let mut ticker = tokio::time::interval(RENDERING_TICK_RATE);
+        let mut ticker = tokio::time::interval(LIVE_REFRESH_RATE);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
         let mut crossterm_events = EventStream::new();
 
+        // The frame is only drawn again once something on it may have changed
+        let mut dirty = true;
+        let mut logs_seen = LOG_BUFFER.generation();
+
         let result: anyhow::Result<Interrupted> = loop {
+            if dirty {
+                dirty = false;
+                if let Err(err) = terminal
+                    .draw(|frame| router.render(frame, ()))
+                    .context("could not render to the terminal")
+                {
+                    break Err(err);
+                }
+            }
+
             tokio::select! {
-                _ = ticker.tick() => (),
+                _ = ticker.tick(), if router.shows_logs() => {
+                    let generation = LOG_BUFFER.generation();
+                    dirty = generation != logs_seen;
+                    logs_seen = generation;
+                },
                 maybe_event = crossterm_events.next() => match maybe_event {
                     Some(Ok(Event::Key(key))) => {
                         router.handle_key_event(key);
+                        dirty = true;
+                    },
+                    Some(Ok(Event::Mouse(mouse))) => {
+                        router.handle_mouse_event(mouse);
+                        // Panes do not react to the pointer moving over them
+                        dirty = !matches!(mouse.kind, MouseEventKind::Moved);
                     },
+                    Some(Ok(Event::Resize(_, _))) => dirty = true,
                     None => break Ok(Interrupted::UserInt),
                     _ => (),
                 },
                 Some(state) = state_rx.recv() => {
                     router = router.move_with_state(&state);
+                    dirty = true;
                 },
                 Ok(interrupted) = interrupt_rx.recv() => {
                     break Ok(interrupted);
                 }
             }
-
-            if let Err(err) = terminal
-                .draw(|frame| router.render(frame, ()))
-                .context("could not render to the terminal")
-            {
-                break Err(err);
-            }
         };
 
         // Restore the terminal to its original state
@@ -1,10 +1,13 @@
+pub use components::Component;
 pub use manager::UiManager;
 use ratatui::layout::{Constraint, Flex, Layout, Rect};
+pub use router::AppRouter;
 
 mod components;
 pub mod manager;
 mod panes;
 mod router;
+pub mod theme;
 
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 pub fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
@@ -0,0 +1,318 @@
+use chrono::TimeDelta;
+use clap::{Parser, Subcommand};
+use crossterm::event::{KeyEvent, KeyEventKind};
+use ratatui::{prelude::*, widgets::*, Frame};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, Level};
+
+use crate::export::ExportFormat;
+use crate::logging::set_log_filter;
+use crate::search::SearchQuery;
+use crate::state::{action::Action, State};
+use crate::ui::components::{
+    command_parser::parse_command,
+    input_box::{self, InputBox},
+    Component, ComponentRender,
+};
+use crate::ui::theme::ThemeName;
+
+use super::Pane;
+
+#[derive(Debug, Parser)]
+#[command(multicall = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Focus the conversation with the given contact
+    Open {
+        #[arg(required = true)]
+        name: Vec<String>,
+    },
+    /// Join a channel or start a conversation by name, on backends that can
+    Join { name: String },
+    /// React to the last message received in the focused conversation
+    React { reaction: String },
+    /// Mute the focused conversation for a duration like 30m, 1h or 2d
+    Mute {
+        #[arg(value_parser = parse_duration)]
+        duration: TimeDelta,
+    },
+    /// Unmute the focused conversation
+    Unmute,
+    /// Export the focused conversation to the data dir
+    Export { format: ExportFormat },
+    /// Search the message history, with from:NAME, since:DATE and until:DATE
+    /// filters and quoted phrases
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Switch the color theme
+    Theme { name: ThemeName },
+    /// Show the log viewer
+    Logs,
+    /// Change the log filter, using the same syntax as RUST_LOG
+    LogLevel { directives: String },
+}
+
+/// Result of submitting a command, tells the router what to do with the palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// The command ran, the palette can be closed
+    Done,
+    /// The command failed, the error is shown in the palette
+    Failed,
+    /// The command changes the look of the UI, which the router owns
+    SetTheme(ThemeName),
+    OpenLogs,
+    /// The search was sent, the results show up in a popup
+    OpenSearch(String),
+}
+
+fn parse_duration(text: &str) -> Result<TimeDelta, String> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in '{text}', expected one of s, m, h, d, w"))?;
+    let (amount, unit) = text.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("invalid amount in '{text}'"))?;
+
+    let duration = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => {
+            return Err(format!(
+                "unknown unit '{unit}', expected one of s, m, h, d, w"
+            ))
+        }
+    };
+    duration.ok_or_else(|| String::from("duration too long"))
+}
+
+pub struct CommandPalette {
+    state: State,
+    action_tx: UnboundedSender<Action>,
+
+    input_box: InputBox,
+    error: Option<String>,
+}
+
+impl CommandPalette {
+    /// Runs the command in the input box, leaving the error in the palette if
+    /// it could not be run
+    pub fn submit(&mut self) -> CommandOutcome {
+        match self.run_command() {
+            Ok(outcome) => {
+                self.input_box.reset();
+                self.error = None;
+                outcome
+            }
+            Err(e) => {
+                event!(Level::DEBUG, "Command failed: {}", e);
+                self.error = Some(e);
+                CommandOutcome::Failed
+            }
+        }
+    }
+
+    fn run_command(&mut self) -> Result<CommandOutcome, String> {
+        let cli = parse_command::<Cli>(self.input_box.text())?;
+
+        event!(Level::DEBUG, "Parsed command: {:?}", cli.command);
+
+        match cli.command {
+            Commands::Open { name } => {
+                let contact = self
+                    .state
+                    .conversations
+                    .find_by_name(&name.join(" "))?
+                    .clone();
+                self.send(Action::FocusConversation(contact))
+            }
+            Commands::Join { name } => self.send(Action::JoinConversation(name)),
+            Commands::React { reaction } => {
+                let message = self
+                    .state
+                    .chat
+                    .messages
+                    .iter()
+                    .rev()
+                    .find(|message| !message.sent_by_me())
+                    .ok_or_else(|| String::from("no message to react to"))?
+                    .clone();
+                self.send(Action::React(message, reaction))
+            }
+            Commands::Mute { duration } => {
+                let until = chrono::offset::Local::now()
+                    .naive_local()
+                    .checked_add_signed(duration)
+                    .ok_or_else(|| String::from("duration too long"))?;
+                self.send(Action::MuteConversation(
+                    self.state.chat.contact.clone(),
+                    Some(until),
+                ))
+            }
+            Commands::Unmute => self.send(Action::MuteConversation(
+                self.state.chat.contact.clone(),
+                None,
+            )),
+            Commands::Export { format } => self.send(Action::ExportConversation(format)),
+            Commands::Search { query } => {
+                let query = SearchQuery::parse(&query)?;
+                let text = query.text.clone();
+                self.send(Action::Search(query))?;
+                Ok(CommandOutcome::OpenSearch(text))
+            }
+            Commands::Theme { name } => Ok(CommandOutcome::SetTheme(name)),
+            Commands::Logs => Ok(CommandOutcome::OpenLogs),
+            Commands::LogLevel { directives } => {
+                set_log_filter(&directives).map_err(|e| e.to_string())?;
+                event!(Level::INFO, "Log filter changed to {}", directives);
+                Ok(CommandOutcome::Done)
+            }
+        }
+    }
+
+    fn send(&self, action: Action) -> Result<CommandOutcome, String> {
+        self.action_tx
+            .send(action)
+            .map(|_| CommandOutcome::Done)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn reset(&mut self) {
+        self.input_box.reset();
+        self.error = None;
+    }
+}
+
+impl Pane for CommandPalette {}
+
+impl Component for CommandPalette {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        Self {
+            state: state.clone(),
+            action_tx: action_tx.clone(),
+            input_box: InputBox::new(state, action_tx),
+            error: None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Command"
+    }
+
+    fn move_with_state(self, state: &State) -> Self
+    where
+        Self: Sized,
+    {
+        Self {
+            state: state.clone(),
+            ..self
+        }
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        self.input_box.handle_key_event(key);
+    }
+}
+
+pub struct RenderProps {
+    pub area: Rect,
+    pub border_color: Color,
+    pub text_color: Color,
+    pub error_color: Color,
+}
+
+impl ComponentRender<RenderProps> for CommandPalette {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
+        let vertical = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]);
+        let [input_area, error_area] = vertical.areas(props.area);
+
+        frame.render_widget(Clear, input_area);
+        self.input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: ":".into(),
+                area: input_area,
+                border_color: props.border_color,
+                text_color: props.text_color,
+                show_cursor: true,
+            },
+        );
+
+        if let Some(error) = &self.error {
+            let error = Paragraph::new(error.as_str())
+                .style(Style::default().fg(props.error_color))
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .title("Error")
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(props.error_color)),
+                );
+
+            frame.render_widget(Clear, error_area);
+            frame.render_widget(error, error_area);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::state::{Chat, Contact, ConversationList};
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("30s"), Ok(TimeDelta::seconds(30)));
+        assert_eq!(parse_duration("30m"), Ok(TimeDelta::minutes(30)));
+        assert_eq!(parse_duration("1h"), Ok(TimeDelta::hours(1)));
+        assert_eq!(parse_duration("2d"), Ok(TimeDelta::days(2)));
+        assert_eq!(parse_duration("3w"), Ok(TimeDelta::weeks(3)));
+    }
+
+    #[test]
+    fn rejects_durations_too_long_to_represent() {
+        for text in ["9999999999999d", "9223372036854775807s"] {
+            assert_eq!(parse_duration(text), Err(String::from("duration too long")));
+        }
+    }
+
+    #[test]
+    fn mute_past_the_last_date_is_an_error() {
+        let (action_tx, mut action_rx) = mpsc::unbounded_channel();
+        let state = State::new(
+            Chat::new(Contact::default(), Vec::new()),
+            ConversationList::new(Vec::new()),
+        );
+        let mut palette = CommandPalette::new(&state, action_tx);
+        palette.input_box.set_text("mute 99999999w");
+
+        assert!(matches!(palette.submit(), CommandOutcome::Failed));
+        assert_eq!(palette.error.as_deref(), Some("duration too long"));
+        assert!(action_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("30y").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+}
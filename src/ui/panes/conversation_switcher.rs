@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{prelude::*, widgets::*, Frame};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, Level};
+
+use crate::search::match_contact;
+use crate::state::{action::Action, Contact, State};
+use crate::ui::components::{
+    input_box::{self, InputBox},
+    Component, ComponentRender,
+};
+
+use super::Pane;
+
+/// Extra score of the most recent conversation, one less for each one after
+/// it, so that close matches favour conversations that are active
+const RECENCY_BONUS: i64 = 10;
+
+/// A conversation that matches what was typed
+struct Candidate {
+    /// Index in the conversation list, which is newest first
+    index: usize,
+    score: i64,
+    /// Matched characters of the name, to highlight
+    positions: Vec<usize>,
+}
+
+/// Ctrl-P popup that fuzzy finds a conversation to focus
+pub struct ConversationSwitcher {
+    action_tx: UnboundedSender<Action>,
+    conversations: Arc<[Contact]>,
+    input_box: InputBox,
+    candidates: Vec<Candidate>,
+    list_state: ListState,
+}
+
+impl ConversationSwitcher {
+    pub fn reset(&mut self) {
+        self.input_box.reset();
+        self.update_candidates();
+    }
+
+    fn update_candidates(&mut self) {
+        let pattern = self.input_box.text();
+        self.candidates = self
+            .conversations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, contact)| {
+                let found = match_contact(pattern, contact)?;
+                let recency = RECENCY_BONUS.saturating_sub(index as i64).max(0);
+                Some(Candidate {
+                    index,
+                    score: found.score + recency,
+                    positions: found.positions,
+                })
+            })
+            .collect();
+        // Stable, so equal scores stay newest first
+        self.candidates
+            .sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+
+        let selected = (!self.candidates.is_empty()).then_some(0);
+        self.list_state.select(selected);
+    }
+
+    /// Focuses the selected conversation, returns whether there was one
+    pub fn open_selected(&self) -> bool {
+        let Some(contact) = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.candidates.get(selected))
+            .and_then(|candidate| self.conversations.get(candidate.index))
+        else {
+            return false;
+        };
+
+        event!(Level::INFO, "Switching to conversation {:?}", contact.name);
+        let _ = self
+            .action_tx
+            .send(Action::FocusConversation(contact.clone()));
+        true
+    }
+
+    fn select_next(&mut self) {
+        let next = self.list_state.selected().map_or(0, |i| i + 1);
+        if next < self.candidates.len() {
+            self.list_state.select(Some(next));
+        }
+    }
+}
+
+fn candidate_line(contact: &Contact, positions: &[usize], highlight: Style) -> Line<'static> {
+    let mut line = Line::default();
+    for (i, c) in contact.name.chars().enumerate() {
+        if positions.contains(&i) {
+            line.push_span(Span::styled(c.to_string(), highlight));
+        } else {
+            line.push_span(c.to_string());
+        }
+    }
+    if contact.phone != contact.name {
+        line.push_span(format!("  {}", contact.phone).dim());
+    }
+    if let Some(account) = &contact.account {
+        line.push_span(format!(" [{}]", account).dim());
+    }
+    line
+}
+
+impl Pane for ConversationSwitcher {
+    fn is_typing(&self) -> bool {
+        true
+    }
+}
+
+impl Component for ConversationSwitcher {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        let mut switcher = Self {
+            action_tx: action_tx.clone(),
+            conversations: state.conversations.contacts.clone(),
+            input_box: InputBox::new(state, action_tx),
+            candidates: Vec::new(),
+            list_state: ListState::default(),
+        };
+        switcher.update_candidates();
+        switcher
+    }
+
+    fn name(&self) -> &str {
+        "Switch Conversation"
+    }
+
+    fn move_with_state(self, state: &State) -> Self
+    where
+        Self: Sized,
+    {
+        if Arc::ptr_eq(&self.conversations, &state.conversations.contacts) {
+            return self;
+        }
+
+        let selected = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.candidates.get(selected))
+            .and_then(|candidate| self.conversations.get(candidate.index))
+            .cloned();
+        let mut switcher = Self {
+            conversations: state.conversations.contacts.clone(),
+            ..self
+        };
+        switcher.update_candidates();
+
+        // Conversations move as messages come in, the selection follows
+        let position = switcher.candidates.iter().position(|candidate| {
+            switcher
+                .conversations
+                .get(candidate.index)
+                .map(|c| &c.phone)
+                == selected.as_ref().map(|c| &c.phone)
+        });
+        if position.is_some() {
+            switcher.list_state.select(position);
+        }
+        switcher
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Down => self.select_next(),
+            KeyCode::Char('n') if control => self.select_next(),
+            KeyCode::Up => self.list_state.select_previous(),
+            KeyCode::Char('p') if control => self.list_state.select_previous(),
+            _ => {
+                self.input_box.handle_key_event(key);
+                self.update_candidates();
+            }
+        }
+    }
+}
+
+pub struct RenderProps {
+    pub area: Rect,
+    pub border_color: Color,
+    pub text_color: Color,
+    pub highlight_color: Color,
+}
+
+impl ComponentRender<RenderProps> for ConversationSwitcher {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
+        let vertical = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]);
+        let [input_area, list_area] = vertical.areas(props.area);
+
+        frame.render_widget(Clear, props.area);
+        self.input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: self.name().into(),
+                area: input_area,
+                border_color: props.border_color,
+                text_color: props.text_color,
+                show_cursor: true,
+            },
+        );
+
+        let highlight = Style::default().fg(props.highlight_color).bold();
+        let list = List::new(self.candidates.iter().filter_map(|candidate| {
+            let contact = self.conversations.get(candidate.index)?;
+            Some(candidate_line(contact, &candidate.positions, highlight))
+        }))
+        .block(
+            Block::bordered()
+                .title(format!(
+                    "{}/{}",
+                    self.candidates.len(),
+                    self.conversations.len()
+                ))
+                .title_bottom("↑/↓ move  Enter open  Esc close")
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(props.border_color)),
+        )
+        .highlight_symbol(">")
+        .highlight_spacing(HighlightSpacing::Always);
+
+        let mut list_state = self.list_state.clone();
+        frame.render_stateful_widget(list, list_area, &mut list_state);
+    }
+}
@@ -1,4 +1,6 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::{cell::Cell, collections::HashSet, sync::Arc};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind};
 use ratatui::{prelude::*, widgets::*, Frame};
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::{event, Level};
@@ -9,13 +11,25 @@ use crate::ui::components::{Component, ComponentRender};
 use crate::ui::panes::Pane;
 
 struct Props {
-    conversations: Vec<Contact>,
+    conversations: Arc<[Contact]>,
+    /// Phone numbers of the conversations that are currently muted
+    muted: HashSet<String>,
+    /// Phone numbers of the contacts that are currently typing
+    typing: HashSet<String>,
 }
 
 impl From<&State> for Props {
     fn from(state: &State) -> Self {
         Props {
             conversations: state.conversations.contacts.clone(),
+            muted: state
+                .conversations
+                .contacts
+                .iter()
+                .filter(|contact| state.is_muted(contact))
+                .map(|contact| contact.phone.clone())
+                .collect(),
+            typing: state.typing.clone(),
         }
     }
 }
@@ -25,6 +39,29 @@ pub struct ConversationsPane {
     props: Props,
     list_state: ListState,
     is_focused: bool,
+    area: Cell<Rect>,
+    /// Scroll offset of the list as of the last render
+    rendered_offset: Cell<usize>,
+}
+
+impl ConversationsPane {
+    fn focus_selected_conversation(&self) {
+        let Some(selected) = self.list_state.selected() else {
+            return;
+        };
+        let Some(selected_conversation) = self.props.conversations.get(selected).cloned() else {
+            return;
+        };
+
+        event!(
+            Level::INFO,
+            "Focusing conversation: {:?}",
+            selected_conversation.name
+        );
+        let _ = self
+            .action_tx
+            .send(Action::FocusConversation(selected_conversation));
+    }
 }
 
 impl Pane for ConversationsPane {
@@ -36,6 +73,28 @@ impl Pane for ConversationsPane {
     fn unfocus(&mut self) {
         self.is_focused = false;
     }
+
+    fn area(&self) -> Rect {
+        self.area.get()
+    }
+
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        if mouse.kind != MouseEventKind::Down(MouseButton::Left) {
+            return;
+        }
+
+        // The first row of the pane is the top border
+        let area = self.area.get();
+        let Some(row) = mouse.row.checked_sub(area.y + 1) else {
+            return;
+        };
+
+        let index = self.rendered_offset.get() + row as usize;
+        if index < self.props.conversations.len() {
+            self.list_state.select(Some(index));
+            self.focus_selected_conversation();
+        }
+    }
 }
 
 impl Component for ConversationsPane {
@@ -45,6 +104,8 @@ impl Component for ConversationsPane {
             props: Props::from(state),
             list_state: ListState::default(),
             is_focused: false,
+            area: Cell::new(Rect::default()),
+            rendered_offset: Cell::new(0),
         }
     }
 
@@ -85,18 +146,7 @@ impl Component for ConversationsPane {
             KeyCode::Char('k') => {
                 self.list_state.select_previous();
             }
-            KeyCode::Enter if self.list_state.selected().is_some() => {
-                let selected_conversation =
-                    self.props.conversations[self.list_state.selected().unwrap()].clone();
-                event!(
-                    Level::INFO,
-                    "Focusing conversation: {:?}",
-                    selected_conversation.name
-                );
-                let _ = self
-                    .action_tx
-                    .send(Action::FocusConversation(selected_conversation));
-            }
+            KeyCode::Enter => self.focus_selected_conversation(),
             _ => {}
         }
     }
@@ -109,20 +159,37 @@ pub struct RenderProps {
 
 impl ComponentRender<RenderProps> for ConversationsPane {
     fn render(&self, frame: &mut Frame, props: RenderProps) {
-        let contacts = List::new(self.props.conversations.iter().map(|x| x.name.clone()))
-            .block(
-                Block::bordered()
-                    .title(self.name())
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(props.border_color)),
-            )
-            .highlight_symbol(">")
-            .highlight_spacing(HighlightSpacing::Always);
+        self.area.set(props.area);
+
+        let contacts = List::new(self.props.conversations.iter().map(|x| {
+            let mut line = Line::from(x.name.clone());
+            if let Some(account) = &x.account {
+                line.push_span(format!(" [{}]", account).dim());
+            }
+            if self.props.typing.contains(&x.phone) {
+                line.push_span(" ...".italic());
+            }
+            if self.props.muted.contains(&x.phone) {
+                line.push_span(" (muted)".dim());
+            }
+            line
+        }))
+        .block(
+            Block::bordered()
+                .title(self.name())
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(props.border_color)),
+        )
+        .highlight_symbol(">")
+        .highlight_spacing(HighlightSpacing::Always);
 
         if self.is_focused {
-            frame.render_stateful_widget(contacts, props.area, &mut self.list_state.clone());
+            let mut list_state = self.list_state.clone();
+            frame.render_stateful_widget(contacts, props.area, &mut list_state);
+            self.rendered_offset.set(list_state.offset());
         } else {
             frame.render_widget(contacts, props.area);
+            self.rendered_offset.set(0);
         }
     }
 }
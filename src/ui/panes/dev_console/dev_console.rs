@@ -1,15 +1,20 @@
 use core::panic;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
-use ratatui::{layout::Rect, prelude::Color, widgets::Clear};
+use itertools::Itertools;
+use ratatui::{prelude::*, widgets::*};
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::{event, Level};
 
 use crate::{
+    backends::{BackendEvent, Injection},
+    logging::get_data_dir,
     state::{action::Action, Contact, Message, MessageDirection, State},
     ui::{
         components::{
+            command_parser::parse_command,
             input_box::{self, InputBox},
             Component, ComponentRender,
         },
@@ -17,6 +22,18 @@ use crate::{
     },
 };
 
+use super::history::CommandHistory;
+
+const HISTORY_FILE: &str = "dev_console_history";
+
+/// Oldest output lines are dropped once the scrollback grows past this
+const MAX_OUTPUT_LINES: usize = 1000;
+
+/// Bounds of a burst's rate in messages per second, one a day up to one a
+/// millisecond
+const MIN_BURST_RATE: f64 = 1.0 / (24.0 * 60.0 * 60.0);
+const MAX_BURST_RATE: f64 = 1000.0;
+
 #[derive(Debug, Parser)]
 #[command(multicall = true)]
 struct Cli {
@@ -26,80 +43,352 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum Commands {
+    /// Send a message to a contact as if typed in the input pane
     SendTo {
         contact_name: String,
         phone_number: String,
         message: String,
     },
+    /// Send a message as if it was received from a contact
     SendFrom {
         contact_name: String,
         phone_number: String,
         message: String,
     },
+    /// Show a contact as typing, or as no longer typing with --stop
+    Typing {
+        contact_name: String,
+        #[arg(long)]
+        stop: bool,
+    },
+    /// Fail delivery of the last message sent in the focused conversation
+    Fail {
+        #[arg(default_value = "simulated failure")]
+        reason: String,
+    },
+    /// Mark the last message sent in the focused conversation as delivered
+    Deliver,
+    /// Mark the last message sent in the focused conversation as read
+    Read,
+    /// React to the last message in the focused conversation
+    React { reaction: String },
+    /// Rename a contact
+    Rename {
+        contact_name: String,
+        new_name: String,
+    },
+    /// Disconnect the backend
+    Disconnect {
+        #[arg(default_value = "simulated disconnect")]
+        reason: String,
+    },
+    /// Reconnect the backend
+    Reconnect,
+    /// Receive COUNT messages from a contact at RATE messages per second
+    Burst {
+        contact_name: String,
+        count: usize,
+        #[arg(long, default_value_t = 10.0)]
+        rate: f64,
+    },
+    /// Panic to test the panic handler
     Panic,
 }
 
 pub struct DevConsole {
-    _state: State,
+    state: State,
     action_tx: UnboundedSender<Action>,
 
     input_box: InputBox,
+    history: CommandHistory,
+    output: Vec<Line<'static>>,
+    /// Number of lines the output is scrolled up from the bottom
+    output_scroll: usize,
 }
 
 impl DevConsole {
     fn handle_command(&mut self) {
-        let args = match shlex::split(self.input_box.text()) {
-            Some(args) => args,
-            None => {
-                self.input_box.reset();
-                event!(Level::INFO, "shlex split failed");
-                return;
-            }
-        };
+        let command = String::from(self.input_box.text());
+        self.input_box.reset();
+        self.output_scroll = 0;
+
+        if command.trim().is_empty() {
+            return;
+        }
+
+        self.history.push(&command);
+        self.print(Line::from(format!("> {}", command)).bold());
 
-        let cli = match Cli::try_parse_from(args) {
+        let cli = match parse_command::<Cli>(&command) {
             Ok(cli) => cli,
             Err(e) => {
-                self.input_box.reset();
-                event!(Level::INFO, "CLI parsing failed: {:?}", e);
+                event!(Level::DEBUG, "CLI parsing failed: {}", e);
+                self.print_text(&e);
                 return;
             }
         };
 
         event!(Level::DEBUG, "Parsed dev command: {:?}", cli.command);
 
-        match cli.command {
+        match self.run_command(cli.command) {
+            Ok(output) => self.print_text(&output),
+            Err(e) => self.print(Line::from(e).red()),
+        }
+    }
+
+    /// Runs a parsed command, returning the output to show in the console
+    fn run_command(&self, command: Commands) -> Result<String, String> {
+        match command {
             Commands::SendFrom {
                 contact_name,
                 phone_number,
                 message,
             } => {
-                let _ = self.action_tx.send(Action::SendMessage(Message::new(
+                let output = format!("received '{}' from {}", message, contact_name);
+                self.inject(BackendEvent::MessageReceived(Message::new(
                     Contact::new(contact_name, phone_number),
                     message,
                     chrono::offset::Local::now().naive_local(),
                     MessageDirection::From,
-                )));
+                )))?;
+                Ok(output)
             }
             Commands::SendTo {
                 contact_name,
                 phone_number,
                 message,
             } => {
-                let _ = self.action_tx.send(Action::SendMessage(Message::new(
+                let output = format!("sent '{}' to {}", message, contact_name);
+                self.send(Action::SendMessage(Message::new(
                     Contact::new(contact_name, phone_number),
                     message,
                     chrono::offset::Local::now().naive_local(),
                     MessageDirection::To,
-                )));
+                )))?;
+                Ok(output)
+            }
+            Commands::Typing { contact_name, stop } => {
+                let contact = self.find_contact(&contact_name)?;
+                let output = format!(
+                    "{} {} typing",
+                    contact.name,
+                    if stop { "stopped" } else { "started" }
+                );
+                self.inject(BackendEvent::Typing {
+                    contact,
+                    is_typing: !stop,
+                })?;
+                Ok(output)
+            }
+            Commands::Fail { reason } => {
+                let message = self.last_message(true)?;
+                self.inject(BackendEvent::DeliveryFailed {
+                    message_id: message.id.clone(),
+                    reason,
+                })?;
+                Ok(format!("failed '{}'", message.content))
+            }
+            Commands::Deliver => {
+                let message = self.last_message(true)?;
+                self.inject(BackendEvent::DeliveryReceipt {
+                    message_id: message.id.clone(),
+                })?;
+                Ok(format!("delivered '{}'", message.content))
+            }
+            Commands::Read => {
+                let message = self.last_message(true)?;
+                self.inject(BackendEvent::ReadReceipt {
+                    message_id: message.id.clone(),
+                })?;
+                Ok(format!("read '{}'", message.content))
+            }
+            Commands::React { reaction } => {
+                let message = self.last_message(false)?;
+                let output = format!("reacted {} to '{}'", reaction, message.content);
+                self.inject(BackendEvent::Reaction {
+                    message_id: message.id.clone(),
+                    from: self.state.chat.contact.clone(),
+                    reaction,
+                })?;
+                Ok(output)
+            }
+            Commands::Rename {
+                contact_name,
+                new_name,
+            } => {
+                let contact = self.find_contact(&contact_name)?;
+                let output = format!("renamed {} to {}", contact.name, new_name);
+                self.inject(BackendEvent::ContactRenamed {
+                    phone: contact.phone,
+                    name: new_name,
+                })?;
+                Ok(output)
+            }
+            Commands::Disconnect { reason } => {
+                self.inject(BackendEvent::Disconnected { reason })?;
+                Ok(String::from("disconnected"))
+            }
+            Commands::Reconnect => {
+                self.inject(BackendEvent::Connected)?;
+                Ok(String::from("reconnected"))
+            }
+            Commands::Burst {
+                contact_name,
+                count,
+                rate,
+            } => {
+                let interval = burst_interval(rate)?;
+                let contact = self.find_contact(&contact_name)?;
+                let output = format!("receiving {} messages from {}", count, contact.name);
+                self.send(Action::Inject(Injection::Burst {
+                    contact,
+                    count,
+                    interval,
+                }))?;
+                Ok(output)
             }
             Commands::Panic => {
                 panic!("Dev Console Panic")
             }
         }
+    }
 
-        self.input_box.reset();
+    fn send(&self, action: Action) -> Result<(), String> {
+        self.action_tx.send(action).map_err(|e| e.to_string())
+    }
+
+    fn inject(&self, event: BackendEvent) -> Result<(), String> {
+        self.send(Action::Inject(Injection::Event(event)))
+    }
+
+    fn find_contact(&self, name: &str) -> Result<Contact, String> {
+        self.state.conversations.find_by_name(name).cloned()
     }
+
+    /// The newest message in the focused conversation, only considering sent
+    /// messages when `sent` is set
+    fn last_message(&self, sent: bool) -> Result<&Message, String> {
+        self.state
+            .chat
+            .messages
+            .iter()
+            .rev()
+            .find(|message| !sent || message.sent_by_me())
+            .ok_or_else(|| String::from("no matching message in the focused conversation"))
+    }
+
+    fn print(&mut self, line: Line<'static>) {
+        self.output.push(line);
+
+        let overflow = self.output.len().saturating_sub(MAX_OUTPUT_LINES);
+        self.output.drain(..overflow);
+    }
+
+    fn print_text(&mut self, text: &str) {
+        for line in text.trim_end().lines() {
+            self.print(Line::from(String::from(line)));
+        }
+    }
+
+    fn recall_previous(&mut self) {
+        if let Some(command) = self.history.previous() {
+            let command = String::from(command);
+            self.input_box.set_text(&command);
+        }
+    }
+
+    fn recall_next(&mut self) {
+        match self.history.next() {
+            Some(command) => {
+                let command = String::from(command);
+                self.input_box.set_text(&command);
+            }
+            None => self.input_box.reset(),
+        }
+    }
+
+    /// Completes the word before the cursor with a subcommand when it is the
+    /// first word, otherwise with a contact name
+    fn complete(&mut self) {
+        let text = String::from(self.input_box.text());
+
+        // An unbalanced quote means the word being completed started there
+        let word_start = if text.matches('"').count() % 2 == 1 {
+            text.rfind('"').unwrap_or(0)
+        } else {
+            text.rfind(' ').map_or(0, |i| i + 1)
+        };
+        let (before, word) = text.split_at(word_start);
+        let partial = word.trim_start_matches('"').to_lowercase();
+
+        let candidates: Vec<String> = if before.trim().is_empty() {
+            Cli::command()
+                .get_subcommands()
+                .map(|command| String::from(command.get_name()))
+                .collect()
+        } else {
+            self.state
+                .conversations
+                .contacts
+                .iter()
+                .map(|contact| contact.name.clone())
+                .unique()
+                .collect()
+        };
+
+        let matches: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.to_lowercase().starts_with(&partial))
+            .collect();
+
+        match matches.as_slice() {
+            [] => {}
+            [single] => {
+                let completed = if single.contains(' ') {
+                    format!("\"{}\"", single)
+                } else {
+                    single.clone()
+                };
+                self.input_box
+                    .set_text(&format!("{}{} ", before, completed));
+            }
+            _ => {
+                let common = common_prefix(&matches);
+                if common.len() > partial.len() && !common.contains(' ') {
+                    self.input_box.set_text(&format!("{}{}", before, common));
+                }
+                self.print(Line::from(matches.join("  ")).dim());
+            }
+        }
+    }
+}
+
+/// Time between the messages of a burst at `rate` messages per second
+fn burst_interval(rate: f64) -> Result<Duration, String> {
+    if !(MIN_BURST_RATE..=MAX_BURST_RATE).contains(&rate) {
+        return Err(format!(
+            "rate must be between {MIN_BURST_RATE:.6} and {MAX_BURST_RATE} messages per second"
+        ));
+    }
+
+    Duration::try_from_secs_f64(1.0 / rate)
+        .ok()
+        .filter(|interval| !interval.is_zero())
+        .ok_or_else(|| format!("no interval for a rate of {rate}"))
+}
+
+/// Longest prefix shared by all of the strings
+fn common_prefix(strings: &[String]) -> String {
+    let Some(first) = strings.first() else {
+        return String::new();
+    };
+
+    first
+        .chars()
+        .enumerate()
+        .take_while(|(i, c)| strings.iter().all(|s| s.chars().nth(*i) == Some(*c)))
+        .map(|(_, c)| c)
+        .collect()
 }
 
 impl Pane for DevConsole {}
@@ -107,21 +396,27 @@ impl Pane for DevConsole {}
 impl Component for DevConsole {
     fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
         Self {
-            _state: state.clone(),
+            state: state.clone(),
             action_tx: action_tx.clone(),
             input_box: InputBox::new(state, action_tx),
+            history: CommandHistory::load(get_data_dir().join(HISTORY_FILE)),
+            output: vec![Line::from("Type 'help' for a list of commands").dim()],
+            output_scroll: 0,
         }
     }
 
     fn name(&self) -> &str {
-        "Message Input"
+        "Dev Console"
     }
 
-    fn move_with_state(self, _state: &State) -> Self
+    fn move_with_state(self, state: &State) -> Self
     where
         Self: Sized,
     {
-        Self { ..self }
+        Self {
+            state: state.clone(),
+            ..self
+        }
     }
 
     fn handle_key_event(&mut self, key: KeyEvent) {
@@ -131,6 +426,15 @@ impl Component for DevConsole {
 
         match key.code {
             KeyCode::Enter => self.handle_command(),
+            KeyCode::Tab => self.complete(),
+            KeyCode::Up => self.recall_previous(),
+            KeyCode::Down => self.recall_next(),
+            KeyCode::PageUp => {
+                self.output_scroll = (self.output_scroll + 5).min(self.output.len());
+            }
+            KeyCode::PageDown => {
+                self.output_scroll = self.output_scroll.saturating_sub(5);
+            }
             _ => self.input_box.handle_key_event(key),
         }
     }
@@ -139,19 +443,149 @@ impl Component for DevConsole {
 pub struct RenderProps {
     pub area: Rect,
     pub border_color: Color,
+    pub text_color: Color,
 }
 
 impl ComponentRender<RenderProps> for DevConsole {
-    fn render(&self, frame: &mut ratatui::prelude::Frame, props: RenderProps) {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
         frame.render_widget(Clear, props.area);
+
+        let vertical = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]);
+        let [output_area, input_area] = vertical.areas(props.area);
+
+        // Keep the newest output at the bottom of the scrollback
+        let visible_lines = output_area.height.saturating_sub(2) as usize;
+        let scroll = self
+            .output
+            .len()
+            .saturating_sub(visible_lines)
+            .saturating_sub(self.output_scroll);
+
+        let output = Paragraph::new(self.output.clone())
+            .scroll((scroll as u16, 0))
+            .block(
+                Block::bordered()
+                    .title(self.name())
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(props.border_color)),
+            );
+        frame.render_widget(output, output_area);
+
         self.input_box.render(
             frame,
             input_box::RenderProps {
-                title: "Dev Console".into(),
-                area: props.area,
+                title: "Command".into(),
+                area: input_area,
                 border_color: props.border_color,
+                text_color: props.text_color,
                 show_cursor: true,
             },
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::state::{Chat, ConversationList};
+
+    fn console(names: &[&str]) -> (tempfile::TempDir, DevConsole) {
+        let contacts: Vec<Contact> = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Contact::new(name.to_string(), format!("+1555000000{i}")))
+            .collect();
+        let state = State::new(
+            Chat::new(contacts[0].clone(), Vec::new()),
+            ConversationList::new(contacts),
+        );
+        let (action_tx, _) = mpsc::unbounded_channel();
+        let directory = tempfile::tempdir().unwrap();
+        let console = DevConsole {
+            history: CommandHistory::load(directory.path().join(HISTORY_FILE)),
+            ..DevConsole::new(&state, action_tx)
+        };
+        (directory, console)
+    }
+
+    /// What the input box holds after completing `text`, and the last line
+    /// printed
+    fn complete(console: &mut DevConsole, text: &str) -> (String, String) {
+        console.input_box.set_text(text);
+        console.complete();
+        let printed = console.output.last().unwrap().to_string();
+        (console.input_box.text().to_string(), printed)
+    }
+
+    #[test]
+    fn completes_subcommands() {
+        let (_directory, mut console) = console(&["Joe Smith"]);
+
+        assert_eq!(complete(&mut console, "ty").0, "typing ");
+        assert_eq!(complete(&mut console, "DIS").0, "disconnect ");
+        // Several complete as far as they agree and are listed
+        assert_eq!(
+            complete(&mut console, "se"),
+            (String::from("send-"), String::from("send-to  send-from"))
+        );
+        assert_eq!(
+            complete(&mut console, "rea"),
+            (String::from("rea"), String::from("read  react"))
+        );
+        assert_eq!(complete(&mut console, "nothing").0, "nothing");
+    }
+
+    #[test]
+    fn completes_contact_names() {
+        let (_directory, mut console) =
+            console(&["Joe Smith", "Ben Boy", "Ben Bat", "Becky", "Joe Smith"]);
+
+        // Names with spaces are quoted to stay one argument
+        assert_eq!(
+            complete(&mut console, "typing jo").0,
+            "typing \"Joe Smith\" "
+        );
+        assert_eq!(complete(&mut console, "typing bec").0, "typing Becky ");
+        // The word being completed starts at an open quote
+        assert_eq!(
+            complete(&mut console, "rename \"joe s").0,
+            "rename \"Joe Smith\" "
+        );
+        assert_eq!(
+            complete(&mut console, "burst \"Ben Bo").0,
+            "burst \"Ben Boy\" "
+        );
+
+        // A common prefix with a space in it would split the argument
+        assert_eq!(
+            complete(&mut console, "typing ben"),
+            (String::from("typing ben"), String::from("Ben Boy  Ben Bat"))
+        );
+        assert_eq!(
+            complete(&mut console, "typing b"),
+            (
+                String::from("typing Be"),
+                String::from("Ben Boy  Ben Bat  Becky")
+            )
+        );
+    }
+
+    #[test]
+    fn burst_interval_is_the_inverse_of_the_rate() {
+        assert_eq!(burst_interval(10.0), Ok(Duration::from_millis(100)));
+        assert_eq!(burst_interval(MAX_BURST_RATE), Ok(Duration::from_millis(1)));
+        assert_eq!(
+            burst_interval(MIN_BURST_RATE).map(|interval| interval.as_secs()),
+            Ok(24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn burst_interval_rejects_rates_out_of_bounds() {
+        for rate in [0.0, -1.0, 1e-300, 1e300, f64::NAN, f64::INFINITY] {
+            assert!(burst_interval(rate).is_err(), "rate {rate}");
+        }
+    }
+}
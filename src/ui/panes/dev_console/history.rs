@@ -0,0 +1,178 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
+
+use tracing::{event, Level};
+
+/// Only the most recent commands are kept, in memory and on disk
+const MAX_HISTORY: usize = 500;
+
+/// Commands previously entered in the console, persisted to a file so they
+/// survive restarts
+pub struct CommandHistory {
+    path: PathBuf,
+    entries: Vec<String>,
+    /// Entry currently recalled with Up/Down, `None` when editing a new command
+    position: Option<usize>,
+}
+
+impl CommandHistory {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let lines: Vec<String> = contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect();
+                lines[lines.len().saturating_sub(MAX_HISTORY)..].to_vec()
+            }
+            Err(e) => {
+                event!(
+                    Level::DEBUG,
+                    "No command history loaded from {:?}: {}",
+                    path,
+                    e
+                );
+                Vec::new()
+            }
+        };
+
+        Self {
+            path,
+            entries,
+            position: None,
+        }
+    }
+
+    pub fn push(&mut self, command: &str) {
+        self.position = None;
+
+        if command.is_empty() || self.entries.last().is_some_and(|last| last == command) {
+            return;
+        }
+
+        self.entries.push(String::from(command));
+
+        // Appending until the history is full, then writing out only what is
+        // kept so that the file does not grow forever
+        let result = if self.entries.len() > MAX_HISTORY {
+            let overflow = self.entries.len() - MAX_HISTORY;
+            self.entries.drain(..overflow);
+            let mut contents = self.entries.join("\n");
+            contents.push('\n');
+            std::fs::write(&self.path, contents)
+        } else {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|mut file| writeln!(file, "{}", command))
+        };
+        if let Err(e) = result {
+            event!(
+                Level::WARN,
+                "Could not save command history to {:?}: {}",
+                self.path,
+                e
+            );
+        }
+    }
+
+    /// Steps back to an older command
+    pub fn previous(&mut self) -> Option<&str> {
+        let position = match self.position {
+            Some(position) => position.saturating_sub(1),
+            None => self.entries.len().checked_sub(1)?,
+        };
+
+        self.position = Some(position);
+        self.entries.get(position).map(String::as_str)
+    }
+
+    /// Steps forward to a newer command, returning `None` once past the newest
+    pub fn next(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+
+        if position < self.entries.len() {
+            self.position = Some(position);
+            self.entries.get(position).map(String::as_str)
+        } else {
+            self.position = None;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> (tempfile::TempDir, CommandHistory) {
+        let directory = tempfile::tempdir().unwrap();
+        let history = CommandHistory::load(directory.path().join("history"));
+        (directory, history)
+    }
+
+    #[test]
+    fn steps_through_commands() {
+        let (_directory, mut history) = history();
+        assert_eq!(history.previous(), None);
+        assert_eq!(history.next(), None);
+
+        for command in ["deliver", "read", "read", "", "fail"] {
+            history.push(command);
+        }
+
+        assert_eq!(history.previous(), Some("fail"));
+        assert_eq!(history.previous(), Some("read"));
+        assert_eq!(history.previous(), Some("deliver"));
+        // The oldest stays put
+        assert_eq!(history.previous(), Some("deliver"));
+        assert_eq!(history.next(), Some("read"));
+        assert_eq!(history.next(), Some("fail"));
+        assert_eq!(history.next(), None);
+        assert_eq!(history.previous(), Some("fail"));
+
+        // Entering a command starts from the newest again
+        history.push("reconnect");
+        assert_eq!(history.previous(), Some("reconnect"));
+    }
+
+    #[test]
+    fn persists_commands() {
+        let (directory, mut history) = history();
+        history.push("deliver");
+        history.push("typing \"Joe Smith\"");
+
+        let mut loaded = CommandHistory::load(directory.path().join("history"));
+        assert_eq!(loaded.previous(), Some("typing \"Joe Smith\""));
+        assert_eq!(loaded.previous(), Some("deliver"));
+    }
+
+    #[test]
+    fn keeps_the_file_to_the_most_recent_commands() {
+        let (directory, _) = history();
+        let path = directory.path().join("history");
+        // Left behind by a version that never trimmed it
+        let old: Vec<String> = (0..MAX_HISTORY * 2).map(|i| format!("old {i}")).collect();
+        std::fs::write(&path, old.join("\n")).unwrap();
+
+        let mut history = CommandHistory::load(path.clone());
+        history.push("new");
+        let lines: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), MAX_HISTORY);
+        assert_eq!(lines[0], format!("old {}", MAX_HISTORY + 1));
+        assert_eq!(lines.last().map(String::as_str), Some("new"));
+
+        for i in 0..10 {
+            history.push(&format!("newer {i}"));
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), MAX_HISTORY);
+        assert!(contents.ends_with("newer 9\n"));
+        assert_eq!(CommandHistory::load(path).entries, history.entries);
+    }
+}
@@ -1 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod dev_console;
+mod history;
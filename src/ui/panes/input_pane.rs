@@ -1,4 +1,6 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::cell::Cell;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, MouseButton, MouseEvent, MouseEventKind};
 use ratatui::{prelude::*, Frame};
 use tokio::sync::mpsc::UnboundedSender;
 
@@ -15,10 +17,25 @@ pub struct InputPane {
     state: State,
     action_tx: UnboundedSender<Action>,
     input_box: InputBox,
+    area: Cell<Rect>,
 }
 
 impl Pane for InputPane {
-    // Doesn't have to override anything
+    fn is_typing(&self) -> bool {
+        true
+    }
+
+    fn area(&self) -> Rect {
+        self.area.get()
+    }
+
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
+            // Account for the left border of the input box
+            let column = mouse.column.saturating_sub(self.area.get().x + 1);
+            self.input_box.click(column);
+        }
+    }
 }
 
 impl InputPane {
@@ -39,6 +56,7 @@ impl Component for InputPane {
             state: state.clone(),
             action_tx: action_tx.clone(),
             input_box: InputBox::new(state, action_tx),
+            area: Cell::new(Rect::default()),
         }
     }
 
@@ -46,11 +64,15 @@ impl Component for InputPane {
         "Message Input"
     }
 
-    fn move_with_state(self, _state: &State) -> Self
+    fn move_with_state(self, state: &State) -> Self
     where
         Self: Sized,
     {
-        Self { ..self }
+        // Messages go to whichever conversation is focused now
+        Self {
+            state: state.clone(),
+            ..self
+        }
     }
 
     fn handle_key_event(&mut self, key: KeyEvent) {
@@ -72,17 +94,20 @@ impl Component for InputPane {
 pub struct RenderProps {
     pub area: Rect,
     pub border_color: Color,
+    pub text_color: Color,
     pub show_cursor: bool,
 }
 
 impl ComponentRender<RenderProps> for InputPane {
     fn render(&self, frame: &mut Frame, props: RenderProps) {
+        self.area.set(props.area);
         self.input_box.render(
             frame,
             input_box::RenderProps {
                 title: "Message Input".into(),
                 area: props.area,
                 border_color: props.border_color,
+                text_color: props.text_color,
                 show_cursor: props.show_cursor,
             },
         )
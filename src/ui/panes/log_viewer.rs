@@ -0,0 +1,165 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{prelude::*, widgets::*, Frame};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::Level;
+
+use crate::logging::{log_filter, LogRecord, LOG_BUFFER};
+use crate::state::{action::Action, State};
+use crate::ui::components::{
+    input_box::{self, InputBox},
+    Component, ComponentRender,
+};
+
+use super::Pane;
+
+/// Shows the events kept in the in-memory log buffer, newest at the bottom
+pub struct LogViewer {
+    /// Most verbose level that is shown
+    min_level: Level,
+    search_box: InputBox,
+    is_searching: bool,
+    /// Number of lines scrolled up from the newest event
+    scroll: usize,
+}
+
+impl LogViewer {
+    fn matches(&self, record: &LogRecord) -> bool {
+        if record.level > self.min_level {
+            return false;
+        }
+
+        let search = self.search_box.text().to_lowercase();
+        search.is_empty()
+            || record.message.to_lowercase().contains(&search)
+            || record.target.to_lowercase().contains(&search)
+    }
+}
+
+fn level_color(level: &Level) -> Color {
+    match *level {
+        Level::ERROR => Color::Red,
+        Level::WARN => Color::Yellow,
+        Level::INFO => Color::Green,
+        Level::DEBUG => Color::Blue,
+        Level::TRACE => Color::Magenta,
+    }
+}
+
+fn record_line(record: &LogRecord) -> Line<'static> {
+    Line::from(vec![
+        record.timestamp.format("%H:%M:%S%.3f ").to_string().dim(),
+        format!("{:>5} ", record.level).fg(level_color(&record.level)),
+        format!("{}: ", record.target).dim(),
+        record.message.clone().into(),
+    ])
+}
+
+impl Pane for LogViewer {}
+
+impl Component for LogViewer {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        Self {
+            min_level: Level::TRACE,
+            search_box: InputBox::new(state, action_tx),
+            is_searching: false,
+            scroll: 0,
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Logs"
+    }
+
+    fn move_with_state(self, _state: &State) -> Self
+    where
+        Self: Sized,
+    {
+        Self { ..self }
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        if self.is_searching {
+            match key.code {
+                KeyCode::Enter => self.is_searching = false,
+                _ => self.search_box.handle_key_event(key),
+            }
+            self.scroll = 0;
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('1') => self.min_level = Level::ERROR,
+            KeyCode::Char('2') => self.min_level = Level::WARN,
+            KeyCode::Char('3') => self.min_level = Level::INFO,
+            KeyCode::Char('4') => self.min_level = Level::DEBUG,
+            KeyCode::Char('5') => self.min_level = Level::TRACE,
+            KeyCode::Char('/') => self.is_searching = true,
+            KeyCode::Char('c') => self.search_box.reset(),
+            KeyCode::Char('k') => self.scroll = self.scroll.saturating_add(1),
+            KeyCode::Char('j') => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Char('G') => self.scroll = 0,
+            _ => {}
+        }
+    }
+}
+
+pub struct RenderProps {
+    pub area: Rect,
+    pub border_color: Color,
+    pub text_color: Color,
+}
+
+impl ComponentRender<RenderProps> for LogViewer {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
+        frame.render_widget(Clear, props.area);
+
+        let vertical = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]);
+        let [logs_area, search_area] = vertical.areas(props.area);
+
+        let lines: Vec<Line> = LOG_BUFFER
+            .records()
+            .iter()
+            .filter(|record| self.matches(record))
+            .map(record_line)
+            .collect();
+
+        // Keep the newest events at the bottom unless scrolled up
+        let visible_lines = logs_area.height.saturating_sub(2) as usize;
+        let top = lines
+            .len()
+            .saturating_sub(visible_lines)
+            .saturating_sub(self.scroll);
+
+        let title = format!(
+            "{} [{} and above] filter: {}",
+            self.name(),
+            self.min_level,
+            log_filter().unwrap_or_default()
+        );
+        let logs = Paragraph::new(lines).scroll((top as u16, 0)).block(
+            Block::bordered()
+                .title(title)
+                .title_bottom("1-5 level  / search  c clear  j/k scroll  G newest")
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(props.border_color)),
+        );
+        frame.render_widget(logs, logs_area);
+
+        self.search_box.render(
+            frame,
+            input_box::RenderProps {
+                title: "Search".into(),
+                area: search_area,
+                border_color: props.border_color,
+                text_color: props.text_color,
+                show_cursor: self.is_searching,
+            },
+        );
+    }
+}
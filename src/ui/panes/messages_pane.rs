@@ -1,39 +1,384 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::Arc,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind};
 use ratatui::{prelude::*, widgets::*, Frame};
+use regex::{Regex, RegexBuilder};
 use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, Level};
 
-use crate::state::{Message, MessageDirection};
 use crate::state::{action::Action, State};
+use crate::state::{ConnectionStatus, DeliveryStatus, Message};
 
-use crate::ui::components::{Component, ComponentRender};
+use crate::ui::components::{
+    input_box::{self, InputBox},
+    Component, ComponentRender,
+};
 
 use super::Pane;
 
 struct Props {
-    messages: Vec<Message>,
+    messages: Arc<[Message]>,
+    contact_name: String,
+    is_typing: bool,
+    connection: ConnectionStatus,
+    /// Message to scroll to, picked from search results
+    focused_message: Option<String>,
 }
 
 impl From<&State> for Props {
     fn from(state: &State) -> Self {
         Self {
             messages: state.chat.messages.clone(),
+            contact_name: state.chat.contact.name.clone(),
+            is_typing: state.typing.contains(&state.chat.contact.phone),
+            connection: state.connection.clone(),
+            focused_message: state.chat.focused_message.clone(),
         }
     }
 }
 
+/// Messages measured beyond each end of the visible ones, so that scrolling
+/// into them finds their heights cached
+const LAYOUT_MARGIN: usize = 20;
+
+/// Matches of `/` in the focused conversation
+#[derive(Default)]
+struct Find {
+    case_sensitive: bool,
+    regex: bool,
+    /// Every match as (message, range of its content), in transcript order
+    matches: Vec<(usize, Range<usize>)>,
+    /// Index in `matches` of the one jumped to
+    current: usize,
+    /// Why the text could not be searched for, an invalid regex
+    error: Option<String>,
+}
+
+impl Find {
+    fn pattern(&self, text: &str) -> Result<Regex, regex::Error> {
+        let pattern = if self.regex {
+            text.to_string()
+        } else {
+            regex::escape(text)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .build()
+    }
+
+    fn update(&mut self, text: &str, messages: &[Message]) {
+        self.matches.clear();
+        self.error = None;
+        if text.is_empty() {
+            return;
+        }
+
+        let pattern = match self.pattern(text) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                event!(Level::DEBUG, "Cannot find {}: {}", text, e);
+                self.error = Some(String::from("invalid regex"));
+                return;
+            }
+        };
+        for (index, message) in messages.iter().enumerate() {
+            self.matches.extend(
+                pattern
+                    .find_iter(&message.content)
+                    // Empty matches have nothing to highlight
+                    .filter(|found| !found.is_empty())
+                    .map(|found| (index, found.range())),
+            );
+        }
+        self.current = self.current.min(self.matches.len().saturating_sub(1));
+    }
+
+    /// Ranges of the matches in a message, the current one apart
+    fn in_message(&self, index: usize) -> (Vec<Range<usize>>, Option<Range<usize>>) {
+        let start = self.matches.partition_point(|(i, _)| *i < index);
+        let end = self.matches.partition_point(|(i, _)| *i <= index);
+        let ranges = self.matches[start..end]
+            .iter()
+            .map(|(_, range)| range.clone())
+            .collect();
+        let current = (start..end)
+            .contains(&self.current)
+            .then(|| self.matches[self.current].1.clone());
+        (ranges, current)
+    }
+}
+
+/// Splits the content of a message into spans, with the matches in it styled
+fn content_spans(
+    content: &str,
+    matches: &[Range<usize>],
+    current: Option<&Range<usize>>,
+    highlight: Style,
+) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut cursor = 0;
+    for range in matches {
+        spans.push(Span::from(content[cursor..range.start].to_string()));
+        let style = if current == Some(range) {
+            highlight.reversed()
+        } else {
+            highlight
+        };
+        spans.push(Span::styled(content[range.clone()].to_string(), style));
+        cursor = range.end;
+    }
+    spans.push(Span::from(content[cursor..].to_string()));
+    spans
+}
+
+fn message_text(
+    message: &Message,
+    matches: &[Range<usize>],
+    current: Option<&Range<usize>>,
+    highlight: Style,
+) -> Text<'static> {
+    let mut content = Line::default();
+    if let Some(sender) = &message.sender {
+        content.push_span(format!("{}: ", sender.name).bold());
+    }
+    for span in content_spans(&message.content, matches, current, highlight) {
+        content.push_span(span);
+    }
+
+    if message.sent_by_me() {
+        let status = match &message.status {
+            DeliveryStatus::Sent => " ✓".dim(),
+            DeliveryStatus::Delivered => " ✓✓".dim(),
+            DeliveryStatus::Read => " read".dim(),
+            DeliveryStatus::Failed(reason) => format!(" failed: {}", reason).red(),
+        };
+        content.push_span(status);
+    }
+
+    let mut text = Text::from(content);
+    if !message.reactions.is_empty() {
+        let reactions = message
+            .reactions
+            .iter()
+            .map(|r| format!("{} {}", r.reaction, r.from.name))
+            .collect::<Vec<_>>()
+            .join("  ");
+        text.push_line(Line::from(reactions).dim());
+    }
+
+    let alignment = if message.sent_by_me() {
+        Alignment::Right
+    } else {
+        Alignment::Left
+    };
+    text.alignment(alignment)
+}
+
+/// Styling the matches does not change how a message wraps, so heights are
+/// measured without them
+fn message_paragraph(
+    message: &Message,
+    matches: &[Range<usize>],
+    current: Option<&Range<usize>>,
+    highlight: Style,
+) -> Paragraph<'static> {
+    Paragraph::new(message_text(message, matches, current, highlight)).wrap(Wrap { trim: false })
+}
+
 pub struct MessagesPane {
     props: Props,
+    /// Index of the first message shown in the transcript
+    scroll_offset: usize,
+    area: Cell<Rect>,
+    /// Wrapped heights of the messages that were laid out, by message id and
+    /// width
+    heights: RefCell<HashMap<(String, u16), u16>>,
+    /// The focused message the transcript last scrolled to, so that it only
+    /// jumps there once
+    scrolled_to: Option<String>,
+    find_box: InputBox,
+    is_finding: bool,
+    find: Find,
+    /// Where the transcript was scrolled to when finding started
+    find_origin: usize,
+}
+
+impl MessagesPane {
+    fn height(&self, message: &Message, width: u16) -> u16 {
+        let key = (message.id.clone(), width);
+        *self.heights.borrow_mut().entry(key).or_insert_with(|| {
+            let lines = message_paragraph(message, &[], None, Style::default()).line_count(width);
+            u16::try_from(lines).unwrap_or(u16::MAX)
+        })
+    }
+
+    /// Drops the heights of messages that are gone or changed since they
+    /// were measured
+    fn forget_changed(&self, old: &[Message], new: &[Message]) {
+        let old: HashMap<&str, &Message> = old.iter().map(|m| (m.id.as_str(), m)).collect();
+        let unchanged: HashSet<&str> = new
+            .iter()
+            .filter(|m| old.get(m.id.as_str()) == Some(m))
+            .map(|m| m.id.as_str())
+            .collect();
+        self.heights
+            .borrow_mut()
+            .retain(|(id, _), _| unchanged.contains(id.as_str()));
+    }
+
+    /// Drops the heights measured at other widths, which resizing leaves
+    /// behind
+    fn forget_other_widths(&self, width: u16) {
+        let mut heights = self.heights.borrow_mut();
+        if heights.keys().any(|(_, measured)| *measured != width) {
+            heights.retain(|(_, measured), _| *measured == width);
+        }
+    }
+
+    /// Scrolls the focused message to the top once it has been loaded
+    fn scroll_to_focused(&mut self) {
+        let Some(id) = &self.props.focused_message else {
+            self.scrolled_to = None;
+            return;
+        };
+        if self.scrolled_to.as_ref() == Some(id) {
+            return;
+        }
+        if let Some(index) = self.props.messages.iter().position(|m| &m.id == id) {
+            self.scroll_offset = index;
+            self.scrolled_to = Some(id.clone());
+        }
+    }
+
+    fn start_finding(&mut self) {
+        self.is_finding = true;
+        self.find_origin = self.scroll_offset;
+        self.find_box.reset();
+        self.find = Find {
+            case_sensitive: self.find.case_sensitive,
+            regex: self.find.regex,
+            ..Find::default()
+        };
+    }
+
+    fn stop_finding(&mut self) {
+        self.is_finding = false;
+        self.find_box.reset();
+        self.find.update("", &[]);
+    }
+
+    /// Finds the text again, jumping to the first match from where finding
+    /// started
+    fn update_find(&mut self) {
+        self.find.update(self.find_box.text(), &self.props.messages);
+        let origin = self.find_origin;
+        self.find.current = self
+            .find
+            .matches
+            .iter()
+            .position(|(index, _)| *index >= origin)
+            .unwrap_or(0);
+        self.jump_to_current();
+    }
+
+    fn jump_to_current(&mut self) {
+        match self.find.matches.get(self.find.current) {
+            Some((index, _)) => self.scroll_offset = *index,
+            None => self.scroll_offset = self.find_origin,
+        }
+    }
+
+    fn next_match(&mut self) {
+        let count = self.find.matches.len();
+        if count > 0 {
+            self.find.current = (self.find.current + 1) % count;
+            self.jump_to_current();
+        }
+    }
+
+    fn previous_match(&mut self) {
+        let count = self.find.matches.len();
+        if count > 0 {
+            self.find.current = (self.find.current + count - 1) % count;
+            self.jump_to_current();
+        }
+    }
+
+    fn handle_find_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Enter => self.is_finding = false,
+            KeyCode::Esc => {
+                self.stop_finding();
+                self.scroll_offset = self.find_origin;
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.find.case_sensitive = !self.find.case_sensitive;
+                self.update_find();
+            }
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.find.regex = !self.find.regex;
+                self.update_find();
+            }
+            _ => {
+                self.find_box.handle_key_event(key);
+                self.update_find();
+            }
+        }
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        let max_offset = self.props.messages.len().saturating_sub(1);
+        self.scroll_offset = self.scroll_offset.saturating_add(lines).min(max_offset);
+    }
 }
 
 impl Pane for MessagesPane {
+    fn is_typing(&self) -> bool {
+        self.is_finding
+    }
+
+    fn unfocus(&mut self) {
+        // The matches stay highlighted, only typing ends
+        self.is_finding = false;
+    }
 
+    fn area(&self) -> Rect {
+        self.area.get()
+    }
+
+    fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.scroll_up(1),
+            MouseEventKind::ScrollDown => self.scroll_down(1),
+            _ => {}
+        }
+    }
 }
 
 impl Component for MessagesPane {
-    fn new(state: &State, _action_tx: UnboundedSender<Action>) -> Self {
-        Self {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        let mut pane = Self {
             props: Props::from(state),
-        }
+            scroll_offset: 0,
+            area: Cell::new(Rect::default()),
+            heights: RefCell::new(HashMap::new()),
+            scrolled_to: None,
+            find_box: InputBox::new(state, action_tx),
+            is_finding: false,
+            find: Find::default(),
+            find_origin: 0,
+        };
+        pane.scroll_to_focused();
+        pane
     }
 
     fn name(&self) -> &str {
@@ -44,37 +389,242 @@ impl Component for MessagesPane {
     where
         Self: Sized,
     {
-        Self {
-            props: Props::from(state),
+        let props = Props::from(state);
+        let changed = !Arc::ptr_eq(&self.props.messages, &props.messages);
+        if changed {
+            self.forget_changed(&self.props.messages, &props.messages);
         }
+        let mut pane = Self {
+            scroll_offset: self
+                .scroll_offset
+                .min(props.messages.len().saturating_sub(1)),
+            props,
+            ..self
+        };
+        if changed {
+            // Keeps the current match, new messages only add to the end
+            pane.find.update(pane.find_box.text(), &pane.props.messages);
+        }
+        pane.scroll_to_focused();
+        pane
     }
 
     fn handle_key_event(&mut self, key: KeyEvent) {
         if key.kind != KeyEventKind::Press {
             return;
         }
+
+        if self.is_finding {
+            self.handle_find_key(key);
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('/') => self.start_finding(),
+            KeyCode::Char('n') => self.next_match(),
+            KeyCode::Char('N') => self.previous_match(),
+            KeyCode::Esc => self.stop_finding(),
+            _ => {}
+        }
     }
 }
 
 pub struct RenderProps {
     pub area: Rect,
     pub border_color: Color,
+    pub text_color: Color,
+    pub highlight_color: Color,
 }
 
 impl ComponentRender<RenderProps> for MessagesPane {
     fn render(&self, frame: &mut Frame, props: RenderProps) {
-        let block = List::new(self.props.messages.iter().map(|x|
-                match x.direction {
-                    MessageDirection::To => ListItem::new(Text::from(x.content.clone()).alignment(Alignment::Right)),
-                    MessageDirection::From => ListItem::new(Text::from(x.content.clone()).alignment(Alignment::Left))
-                }
-            )).block(
-                Block::bordered()
-                    .title(self.name())
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(props.border_color)),
+        self.area.set(props.area);
+
+        let mut area = props.area;
+        if self.is_finding {
+            let vertical = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]);
+            let [transcript_area, find_area] = vertical.areas(props.area);
+            area = transcript_area;
+
+            let mut title = String::from("Find");
+            if self.find.case_sensitive {
+                title.push_str(" [case]");
+            }
+            if self.find.regex {
+                title.push_str(" [regex]");
+            }
+            if let Some(error) = &self.find.error {
+                title.push_str(&format!(" - {}", error));
+            }
+            title.push_str(" - Alt-c case, Alt-r regex");
+            self.find_box.render(
+                frame,
+                input_box::RenderProps {
+                    title,
+                    area: find_area,
+                    border_color: props.border_color,
+                    text_color: props.text_color,
+                    show_cursor: true,
+                },
+            );
+        }
+
+        let mut title = vec![Span::from(self.name())];
+        if !self.find_box.is_empty() {
+            let position = match self.find.matches.len() {
+                0 => String::from("0/0"),
+                count => format!("{}/{}", self.find.current + 1, count),
+            };
+            title.push(format!(" - /{} {}", self.find_box.text(), position).into());
+        }
+        if self.props.is_typing {
+            title.push(format!(" - {} is typing...", self.props.contact_name).italic());
+        }
+        if let ConnectionStatus::Disconnected(reason) = &self.props.connection {
+            title.push(format!(" [disconnected: {}]", reason).red());
+        }
+
+        let block = Block::bordered()
+            .title(Line::from(title))
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(props.border_color));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        // Nothing fits, and every message would measure nothing
+        if inner.width == 0 {
+            return;
+        }
+        self.forget_other_widths(inner.width);
+
+        // Only the messages from the scroll offset down to the bottom of the
+        // pane are laid out, however long the conversation
+        let messages = &self.props.messages;
+        let start = self.scroll_offset.min(messages.len());
+        let mut y = inner.top();
+        let mut end = start;
+        while end < messages.len() && y < inner.bottom() {
+            let message = &messages[end];
+            let height = self.height(message, inner.width);
+            let area = Rect {
+                y,
+                height: height.min(inner.bottom() - y),
+                ..inner
+            };
+            let (matches, current) = self.find.in_message(end);
+            let highlight = Style::default().fg(props.highlight_color).bold();
+            let paragraph = message_paragraph(message, &matches, current.as_ref(), highlight);
+            frame.render_widget(paragraph, area);
+            y = y.saturating_add(height);
+            end += 1;
+        }
+
+        let before = start.saturating_sub(LAYOUT_MARGIN);
+        let after = (end + LAYOUT_MARGIN).min(messages.len());
+        for message in messages[before..start].iter().chain(&messages[end..after]) {
+            self.height(message, inner.width);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use ratatui::backend::TestBackend;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::state::{Chat, Contact, ConversationList, MessageDirection};
+
+    fn pane(count: usize) -> MessagesPane {
+        let contact = Contact::new(String::from("Joe"), String::from("+15551234567"));
+        let messages = (0..count)
+            .map(|i| {
+                let timestamp = DateTime::from_timestamp(i as i64, 0).unwrap().naive_utc();
+                Message::new(
+                    contact.clone(),
+                    format!("message {i}"),
+                    timestamp,
+                    MessageDirection::From,
+                )
+                .with_id(format!("{i}"))
+            })
+            .collect();
+        let state = State::new(
+            Chat::new(contact.clone(), messages),
+            ConversationList::new(vec![contact]),
+        );
+        let (action_tx, _) = mpsc::unbounded_channel();
+        MessagesPane::new(&state, action_tx)
+    }
+
+    /// The lines of the transcript after rendering the pane `width` columns
+    /// wide and `height` lines high, without the borders
+    fn render(pane: &MessagesPane, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|frame| {
+                pane.render(
+                    frame,
+                    RenderProps {
+                        area: frame.size(),
+                        border_color: Color::White,
+                        text_color: Color::White,
+                        highlight_color: Color::Yellow,
+                    },
+                )
+            })
+            .unwrap();
+
+        let buffer = terminal.backend().buffer();
+        (1..height.saturating_sub(1))
+            .map(|y| {
+                let line: String = (1..width.saturating_sub(1))
+                    .map(|x| buffer.get(x, y).symbol())
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    fn measured(pane: &MessagesPane) -> Vec<(usize, u16)> {
+        let mut measured: Vec<(usize, u16)> = pane
+            .heights
+            .borrow()
+            .keys()
+            .map(|(id, width)| (id.parse().unwrap(), *width))
+            .collect();
+        measured.sort();
+        measured
+    }
+
+    #[test]
+    fn lays_out_only_around_the_visible_messages() {
+        let mut pane = pane(100);
+        pane.scroll_offset = 50;
+
+        assert_eq!(
+            render(&pane, 20, 5),
+            ["message 50", "message 51", "message 52"]
         );
+        let expected: Vec<(usize, u16)> = (30..73).map(|i| (i, 18)).collect();
+        assert_eq!(measured(&pane), expected);
+
+        // Scrolling moves the window, resizing forgets the old width
+        pane.scroll_down(48);
+        assert_eq!(render(&pane, 14, 5), ["message 98", "message 99", ""]);
+        let expected: Vec<(usize, u16)> = (78..100).map(|i| (i, 12)).collect();
+        assert_eq!(measured(&pane), expected);
+
+        // Too long for the width, each message wraps
+        assert_eq!(render(&pane, 9, 6), ["message", "98", "message", "99"]);
+    }
+
+    #[test]
+    fn lays_out_nothing_without_room() {
+        let mut pane = pane(100);
+        pane.scroll_offset = 50;
 
-        frame.render_widget(block, props.area);
+        assert!(render(&pane, 2, 5).iter().all(|line| line.is_empty()));
+        assert!(measured(&pane).is_empty());
     }
 }
@@ -1,11 +1,26 @@
+use crossterm::event::MouseEvent;
+use ratatui::layout::Rect;
+
 use super::components::Component;
 
+pub mod command_palette;
+pub mod conversation_switcher;
 pub mod conversations;
+#[cfg(debug_assertions)]
+pub mod dev_console;
 pub mod input_pane;
+pub mod log_viewer;
 pub mod messages_pane;
-pub mod dev_console;
+pub mod new_conversation;
+pub mod search_results;
 
 pub trait Pane: Component {
+    /// Whether keys go to text the pane is editing, rather than to the
+    /// bindings of the router
+    fn is_typing(&self) -> bool {
+        false
+    }
+
     fn focus(&mut self) {
         // Default Implementation does nothing
     }
@@ -13,4 +28,13 @@ pub trait Pane: Component {
     fn unfocus(&mut self) {
         // Default Implementation does nothing
     }
+
+    /// The area the pane was last rendered into, used for mouse hit-testing
+    fn area(&self) -> Rect {
+        Rect::default()
+    }
+
+    fn handle_mouse_event(&mut self, _mouse: MouseEvent) {
+        // Default Implementation does nothing
+    }
 }
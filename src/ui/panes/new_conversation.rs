@@ -0,0 +1,254 @@
+use std::collections::HashSet;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{prelude::*, widgets::*, Frame};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, Level};
+
+use crate::search::fuzzy_match;
+use crate::state::{action::Action, normalize_handle, Contact, State};
+use crate::ui::components::{
+    input_box::{self, InputBox},
+    Component, ComponentRender,
+};
+
+use super::Pane;
+
+/// Most completions listed under what was typed
+const COMPLETION_LIMIT: usize = 20;
+
+/// Someone to complete to
+#[derive(Clone)]
+struct Known {
+    contact: Contact,
+    /// Whether there is a conversation with them already, rather than only
+    /// an address book entry
+    is_conversation: bool,
+}
+
+/// Popup to message anyone by name, number or email, completing from the
+/// people chatty knows of
+pub struct NewConversation {
+    action_tx: UnboundedSender<Action>,
+    /// Everyone that can be completed, the conversations first
+    known: Vec<Known>,
+    input_box: InputBox,
+    completions: Vec<Known>,
+    /// Index in the completions, with the typed recipient as the row after
+    /// them
+    list_state: ListState,
+}
+
+/// The people to complete from, the address book without those there are
+/// conversations with
+fn known_contacts(state: &State) -> Vec<Known> {
+    let conversations = &state.conversations.contacts;
+    let handles: HashSet<String> = conversations
+        .iter()
+        .map(|contact| normalize_handle(&contact.phone))
+        .collect();
+
+    let conversations = conversations.iter().map(|contact| Known {
+        contact: contact.clone(),
+        is_conversation: true,
+    });
+    let address_book = state
+        .address_book
+        .iter()
+        .filter(|contact| !handles.contains(&normalize_handle(&contact.phone)))
+        .map(|contact| Known {
+            contact: contact.clone(),
+            is_conversation: false,
+        });
+    conversations.chain(address_book).collect()
+}
+
+/// How well someone matches what was typed: names fuzzily, handles when they
+/// contain it, ignoring formatting
+fn completion_score(text: &str, contact: &Contact) -> Option<i64> {
+    let handle = normalize_handle(text);
+    let by_handle = (!handle.is_empty() && normalize_handle(&contact.phone).contains(&handle))
+        .then_some(i64::MAX);
+    by_handle.or_else(|| fuzzy_match(text, &contact.name).map(|found| found.score))
+}
+
+impl NewConversation {
+    pub fn reset(&mut self) {
+        self.input_box.reset();
+        self.update_completions();
+    }
+
+    fn typed(&self) -> &str {
+        self.input_box.text().trim()
+    }
+
+    fn rows(&self) -> usize {
+        self.completions.len() + usize::from(!self.typed().is_empty())
+    }
+
+    fn update_completions(&mut self) {
+        let text = self.typed();
+        let mut completions: Vec<(i64, &Known)> = if text.is_empty() {
+            Vec::new()
+        } else {
+            self.known
+                .iter()
+                .filter_map(|known| Some((completion_score(text, &known.contact)?, known)))
+                .collect()
+        };
+        // Stable, so equal scores keep the conversations first, most recent
+        // first
+        completions.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.completions = completions
+            .into_iter()
+            .map(|(_, known)| known.clone())
+            .take(COMPLETION_LIMIT)
+            .collect();
+
+        let selected = (self.rows() > 0).then_some(0);
+        self.list_state.select(selected);
+    }
+
+    /// Focuses the selected conversation, starting it when it is new. Returns
+    /// whether there was one.
+    pub fn open_selected(&self) -> bool {
+        let Some(selected) = self.list_state.selected() else {
+            return false;
+        };
+
+        let action = match self.completions.get(selected) {
+            Some(known) if known.is_conversation => {
+                Action::FocusConversation(known.contact.clone())
+            }
+            Some(known) => Action::StartConversation(known.contact.clone()),
+            None if !self.typed().is_empty() => {
+                let handle = self.typed().to_string();
+                Action::StartConversation(Contact::new(handle.clone(), handle))
+            }
+            None => return false,
+        };
+
+        event!(Level::INFO, "Opening new conversation: {:?}", action);
+        let _ = self.action_tx.send(action);
+        true
+    }
+
+    fn select_next(&mut self) {
+        let next = self.list_state.selected().map_or(0, |i| i + 1);
+        if next < self.rows() {
+            self.list_state.select(Some(next));
+        }
+    }
+}
+
+impl Pane for NewConversation {
+    fn is_typing(&self) -> bool {
+        true
+    }
+}
+
+impl Component for NewConversation {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        Self {
+            action_tx: action_tx.clone(),
+            known: known_contacts(state),
+            input_box: InputBox::new(state, action_tx),
+            completions: Vec::new(),
+            list_state: ListState::default(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "New Conversation"
+    }
+
+    fn move_with_state(self, state: &State) -> Self
+    where
+        Self: Sized,
+    {
+        // Completions are only updated while typing, so that they do not move
+        // under the selection
+        Self {
+            known: known_contacts(state),
+            ..self
+        }
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Down => self.select_next(),
+            KeyCode::Char('n') if control => self.select_next(),
+            KeyCode::Up => self.list_state.select_previous(),
+            KeyCode::Char('p') if control => self.list_state.select_previous(),
+            _ => {
+                self.input_box.handle_key_event(key);
+                self.update_completions();
+            }
+        }
+    }
+}
+
+pub struct RenderProps {
+    pub area: Rect,
+    pub border_color: Color,
+    pub text_color: Color,
+}
+
+impl ComponentRender<RenderProps> for NewConversation {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
+        let vertical = Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]);
+        let [input_area, list_area] = vertical.areas(props.area);
+
+        frame.render_widget(Clear, props.area);
+        self.input_box.render(
+            frame,
+            input_box::RenderProps {
+                title: format!("{}: name, number or email", self.name()),
+                area: input_area,
+                border_color: props.border_color,
+                text_color: props.text_color,
+                show_cursor: true,
+            },
+        );
+
+        let mut items: Vec<ListItem> = self
+            .completions
+            .iter()
+            .map(|known| {
+                let contact = &known.contact;
+                let mut line = Line::from(contact.name.clone());
+                if contact.phone != contact.name {
+                    line.push_span(format!("  {}", contact.phone).dim());
+                }
+                if !known.is_conversation {
+                    line.push_span(" (address book)".italic().dim());
+                }
+                ListItem::new(line)
+            })
+            .collect();
+        if !self.typed().is_empty() {
+            items.push(ListItem::new(Line::from(vec![
+                "Message ".italic(),
+                self.typed().to_string().bold(),
+            ])));
+        }
+
+        let list = List::new(items)
+            .block(
+                Block::bordered()
+                    .title_bottom("↑/↓ move  Enter open  Esc close")
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(props.border_color)),
+            )
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        let mut list_state = self.list_state.clone();
+        frame.render_stateful_widget(list, list_area, &mut list_state);
+    }
+}
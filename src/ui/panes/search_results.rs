@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{prelude::*, widgets::*, Frame};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{event, Level};
+
+use crate::state::{action::Action, SearchHit, SearchResults, State};
+use crate::ui::components::{Component, ComponentRender};
+
+use super::Pane;
+
+/// Characters of context shown before the first match in a snippet
+const SNIPPET_CONTEXT: usize = 30;
+/// Characters of a message shown in a snippet
+const SNIPPET_LENGTH: usize = 120;
+
+/// Lists the messages found by `:search`, newest first, and focuses the one
+/// picked in its conversation
+pub struct SearchResultsPane {
+    action_tx: UnboundedSender<Action>,
+    /// The query sent to the store, whose results are not in yet while the
+    /// state has a different one
+    pending: String,
+    results: Option<Arc<SearchResults>>,
+    list_state: ListState,
+}
+
+impl SearchResultsPane {
+    /// Waits for the results of a query that was just sent
+    pub fn expect(&mut self, query: String) {
+        self.pending = query;
+        self.list_state.select(None);
+    }
+
+    /// Results of the pending query, if they are in
+    fn results(&self) -> Option<&SearchResults> {
+        self.results
+            .as_deref()
+            .filter(|results| results.query == self.pending)
+    }
+
+    /// Focuses the selected message, returns whether there was one
+    pub fn open_selected(&self) -> bool {
+        let Some(hit) = self
+            .list_state
+            .selected()
+            .and_then(|selected| self.results()?.hits.get(selected))
+        else {
+            return false;
+        };
+
+        event!(Level::INFO, "Focusing message {}", hit.message.id);
+        let _ = self
+            .action_tx
+            .send(Action::FocusMessage(hit.message.clone()));
+        true
+    }
+}
+
+/// The part of the message around its first match, with the matches in it
+/// highlighted
+fn snippet(hit: &SearchHit, highlight: Style) -> Line<'static> {
+    let content = &hit.message.content;
+    let first = hit.highlights.first().map_or(0, |range| range.start);
+    let start = content[..first]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT - 1)
+        .map_or(0, |(i, _)| i);
+    let end = content[start..]
+        .char_indices()
+        .nth(SNIPPET_LENGTH)
+        .map_or(content.len(), |(i, _)| start + i);
+
+    // Messages can span lines, snippets do not
+    let flat = |text: &str| text.replace(['\n', '\r'], " ");
+
+    let mut line = Line::default();
+    if start > 0 {
+        line.push_span("…".dim());
+    }
+    let mut cursor = start;
+    for range in &hit.highlights {
+        if range.start >= end {
+            break;
+        }
+        if range.end <= cursor {
+            continue;
+        }
+        let from = range.start.max(cursor);
+        let to = range.end.min(end);
+        line.push_span(flat(&content[cursor..from]));
+        line.push_span(Span::styled(flat(&content[from..to]), highlight));
+        cursor = to;
+    }
+    line.push_span(flat(&content[cursor..end]));
+    if end < content.len() {
+        line.push_span("…".dim());
+    }
+    line
+}
+
+fn hit_item(hit: &SearchHit, highlight: Style) -> ListItem<'static> {
+    let message = &hit.message;
+    let from = match (&message.sender, message.sent_by_me()) {
+        (_, true) => String::from("me"),
+        (Some(sender), false) => sender.name.clone(),
+        (None, false) => message.contact.name.clone(),
+    };
+    let header = Line::from(vec![
+        message.contact.name.clone().bold(),
+        format!(" · {} · ", from).dim(),
+        message.timestamp.format("%Y-%m-%d %H:%M").to_string().dim(),
+    ]);
+    ListItem::new(vec![header, snippet(hit, highlight)])
+}
+
+impl Pane for SearchResultsPane {}
+
+impl Component for SearchResultsPane {
+    fn new(state: &State, action_tx: UnboundedSender<Action>) -> Self {
+        Self {
+            action_tx,
+            pending: String::new(),
+            results: state.search.clone(),
+            list_state: ListState::default(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "Search"
+    }
+
+    fn move_with_state(self, state: &State) -> Self
+    where
+        Self: Sized,
+    {
+        let mut pane = Self {
+            results: state.search.clone(),
+            ..self
+        };
+        if pane.list_state.selected().is_none()
+            && pane
+                .results()
+                .is_some_and(|results| !results.hits.is_empty())
+        {
+            pane.list_state.select(Some(0));
+        }
+        pane
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+
+        let hits = self.results().map_or(0, |results| results.hits.len());
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => {
+                let next = self.list_state.selected().map_or(0, |i| i + 1);
+                if next < hits {
+                    self.list_state.select(Some(next));
+                }
+            }
+            KeyCode::Char('k') | KeyCode::Up => self.list_state.select_previous(),
+            _ => {}
+        }
+    }
+}
+
+pub struct RenderProps {
+    pub area: Rect,
+    pub border_color: Color,
+    pub highlight_color: Color,
+}
+
+impl ComponentRender<RenderProps> for SearchResultsPane {
+    fn render(&self, frame: &mut Frame, props: RenderProps) {
+        frame.render_widget(Clear, props.area);
+
+        let block = Block::bordered()
+            .title_bottom("j/k move  Enter open  Esc close")
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(props.border_color));
+
+        let Some(results) = self.results() else {
+            let title = format!("{}: {}", self.name(), self.pending);
+            let searching = Paragraph::new("Searching…".italic()).block(block.title(title));
+            frame.render_widget(searching, props.area);
+            return;
+        };
+
+        let mut title = format!(
+            "{}: {} - {} found",
+            self.name(),
+            results.query,
+            results.total
+        );
+        if results.total > results.hits.len() {
+            title.push_str(&format!(", showing the newest {}", results.hits.len()));
+        }
+        let block = block.title(title);
+
+        if results.hits.is_empty() {
+            let empty = Paragraph::new("No messages match".dim()).block(block);
+            frame.render_widget(empty, props.area);
+            return;
+        }
+
+        let highlight = Style::default().fg(props.highlight_color).bold();
+        let list = List::new(results.hits.iter().map(|hit| hit_item(hit, highlight)))
+            .block(block)
+            .highlight_symbol(">")
+            .highlight_spacing(HighlightSpacing::Always);
+
+        let mut list_state = self.list_state.clone();
+        frame.render_stateful_widget(list, props.area, &mut list_state);
+    }
+}
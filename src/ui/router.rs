@@ -1,14 +1,21 @@
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEvent, MouseEventKind};
 use ratatui::{prelude::*, Frame};
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::{event, Level};
 
 use crate::state::{action::Action, State};
 
+use super::panes::command_palette::{self, CommandOutcome, CommandPalette};
+use super::panes::conversation_switcher::{self, ConversationSwitcher};
 use super::panes::conversations::conversations_pane;
+#[cfg(debug_assertions)]
 use super::panes::dev_console::dev_console::{self, DevConsole};
+use super::panes::log_viewer::{self, LogViewer};
+use super::panes::new_conversation::{self, NewConversation};
+use super::panes::search_results::{self, SearchResultsPane};
 use super::panes::{input_pane, messages_pane, Pane};
 use super::popup_area;
+use super::theme::Theme;
 
 use crate::ui::components::component::Component;
 use crate::ui::components::component::ComponentRender;
@@ -18,9 +25,30 @@ enum ActivePane {
     Input,
     Messages,
     Contacts,
+    CommandPalette,
+    Logs,
+    Search,
+    Switcher,
+    NewConversation,
 
     #[cfg(debug_assertions)]
-    Popup,
+    DevConsole,
+}
+
+impl ActivePane {
+    fn is_popup(&self) -> bool {
+        match self {
+            ActivePane::Input | ActivePane::Messages | ActivePane::Contacts => false,
+            ActivePane::CommandPalette
+            | ActivePane::Logs
+            | ActivePane::Search
+            | ActivePane::Switcher
+            | ActivePane::NewConversation => true,
+
+            #[cfg(debug_assertions)]
+            ActivePane::DevConsole => true,
+        }
+    }
 }
 
 pub struct AppRouter {
@@ -29,11 +57,17 @@ pub struct AppRouter {
     input_pane: input_pane::InputPane,
     messages_pane: messages_pane::MessagesPane,
     conversations_pane: conversations_pane::ConversationsPane,
+    command_palette: CommandPalette,
+    log_viewer: LogViewer,
+    search_results: SearchResultsPane,
+    switcher: ConversationSwitcher,
+    new_conversation: NewConversation,
 
     #[cfg(debug_assertions)]
     dev_console: DevConsole,
 
     pre_popup_active_pane: ActivePane,
+    theme: Theme,
 }
 
 impl AppRouter {
@@ -42,21 +76,66 @@ impl AppRouter {
             ActivePane::Input => &self.input_pane,
             ActivePane::Messages => &self.messages_pane,
             ActivePane::Contacts => &self.conversations_pane,
+            ActivePane::CommandPalette => &self.command_palette,
+            ActivePane::Logs => &self.log_viewer,
+            ActivePane::Search => &self.search_results,
+            ActivePane::Switcher => &self.switcher,
+            ActivePane::NewConversation => &self.new_conversation,
 
             #[cfg(debug_assertions)]
-            ActivePane::Popup => &self.dev_console,
+            ActivePane::DevConsole => &self.dev_console,
         }
     }
 
     fn get_active_pane_mut(&mut self) -> &mut dyn Pane {
-        match self.active_pane {
+        let pane = self.active_pane.clone();
+        self.get_pane_mut(&pane)
+    }
+
+    fn get_pane_mut(&mut self, pane: &ActivePane) -> &mut dyn Pane {
+        match pane {
             ActivePane::Input => &mut self.input_pane,
             ActivePane::Messages => &mut self.messages_pane,
             ActivePane::Contacts => &mut self.conversations_pane,
+            ActivePane::CommandPalette => &mut self.command_palette,
+            ActivePane::Logs => &mut self.log_viewer,
+            ActivePane::Search => &mut self.search_results,
+            ActivePane::Switcher => &mut self.switcher,
+            ActivePane::NewConversation => &mut self.new_conversation,
 
             #[cfg(debug_assertions)]
-            ActivePane::Popup => &mut self.dev_console,
+            ActivePane::DevConsole => &mut self.dev_console,
+        }
+    }
+
+    /// Finds the pane that was last rendered under the given terminal cell
+    fn pane_at(&self, column: u16, row: u16) -> Option<ActivePane> {
+        let position = Position::new(column, row);
+        [
+            (ActivePane::Input, self.input_pane.area()),
+            (ActivePane::Messages, self.messages_pane.area()),
+            (ActivePane::Contacts, self.conversations_pane.area()),
+        ]
+        .into_iter()
+        .find(|(_, area)| area.contains(position))
+        .map(|(pane, _)| pane)
+    }
+
+    pub fn handle_mouse_event(&mut self, mouse: MouseEvent) {
+        // Popups are modal, the panes underneath do not receive mouse input
+        if self.active_pane.is_popup() {
+            return;
         }
+
+        let Some(pane) = self.pane_at(mouse.column, mouse.row) else {
+            return;
+        };
+
+        if let MouseEventKind::Down(_) = mouse.kind {
+            self.focus(pane.clone());
+        }
+
+        self.get_pane_mut(&pane).handle_mouse_event(mouse);
     }
 
     fn focus(&mut self, pane: ActivePane) {
@@ -72,6 +151,45 @@ impl AppRouter {
         event!(Level::INFO, "Focusing pane {:?}", self.active_pane);
         self.get_active_pane_mut().focus();
     }
+
+    fn open_popup(&mut self, popup: ActivePane) {
+        self.pre_popup_active_pane = self.active_pane.clone();
+        self.active_pane = popup;
+    }
+
+    fn close_popup(&mut self) {
+        self.active_pane = self.pre_popup_active_pane.clone();
+    }
+
+    /// Whether the log viewer is open, the only pane that changes without
+    /// input or a new state
+    pub fn shows_logs(&self) -> bool {
+        self.active_pane == ActivePane::Logs
+    }
+
+    fn submit_command(&mut self) {
+        match self.command_palette.submit() {
+            CommandOutcome::Done => self.close_popup(),
+            CommandOutcome::SetTheme(name) => {
+                event!(Level::INFO, "Switching to theme {:?}", name);
+                self.theme = Theme::from(name);
+                self.close_popup();
+            }
+            CommandOutcome::OpenLogs => self.active_pane = ActivePane::Logs,
+            CommandOutcome::OpenSearch(query) => {
+                self.search_results.expect(query);
+                self.active_pane = ActivePane::Search;
+            }
+            CommandOutcome::Failed => {}
+        }
+    }
+
+    fn open_search_result(&mut self) {
+        if self.search_results.open_selected() {
+            self.close_popup();
+            self.focus(ActivePane::Messages);
+        }
+    }
 }
 
 impl Component for AppRouter {
@@ -85,10 +203,16 @@ impl Component for AppRouter {
                 state,
                 action_sender.clone(),
             ),
+            command_palette: CommandPalette::new(state, action_sender.clone()),
+            log_viewer: LogViewer::new(state, action_sender.clone()),
+            search_results: SearchResultsPane::new(state, action_sender.clone()),
+            switcher: ConversationSwitcher::new(state, action_sender.clone()),
+            new_conversation: NewConversation::new(state, action_sender.clone()),
             #[cfg(debug_assertions)]
             dev_console: DevConsole::new(state, action_sender.clone()),
 
             pre_popup_active_pane: ActivePane::Input,
+            theme: Theme::default(),
         }
     }
 
@@ -104,6 +228,10 @@ impl Component for AppRouter {
             input_pane: self.input_pane.move_with_state(state),
             messages_pane: self.messages_pane.move_with_state(state),
             conversations_pane: self.conversations_pane.move_with_state(state),
+            command_palette: self.command_palette.move_with_state(state),
+            search_results: self.search_results.move_with_state(state),
+            switcher: self.switcher.move_with_state(state),
+            new_conversation: self.new_conversation.move_with_state(state),
 
             #[cfg(debug_assertions)]
             dev_console: self.dev_console.move_with_state(state),
@@ -142,29 +270,68 @@ impl Component for AppRouter {
                 }
             }
             KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                if self.active_pane != ActivePane::Contacts {
+                if self.active_pane != ActivePane::Contacts && !self.active_pane.is_popup() {
                     self.focus(ActivePane::Contacts);
                 }
             }
-            #[cfg(debug_assertions)]
-            KeyCode::Char('d')
+
+            // Panes being typed into need ':', so the palette can only be
+            // opened from the others
+            KeyCode::Char(':')
+                if !self.active_pane.is_popup() && !self.get_active_pane().is_typing() =>
+            {
+                self.command_palette.reset();
+                self.open_popup(ActivePane::CommandPalette);
+            }
+            KeyCode::Enter if self.active_pane == ActivePane::CommandPalette => {
+                self.submit_command();
+            }
+            KeyCode::Enter if self.active_pane == ActivePane::Search => {
+                self.open_search_result();
+            }
+            KeyCode::Char('p')
                 if key.modifiers.contains(KeyModifiers::CONTROL)
-                    && self.active_pane != ActivePane::Popup =>
+                    && !self.active_pane.is_popup() =>
             {
-                self.pre_popup_active_pane = self.active_pane.clone();
-                self.active_pane = ActivePane::Popup;
+                self.switcher.reset();
+                self.open_popup(ActivePane::Switcher);
             }
-            #[cfg(debug_assertions)]
-            KeyCode::Esc if self.active_pane == ActivePane::Popup => {
-                self.active_pane = self.pre_popup_active_pane.clone();
+            KeyCode::Enter if self.active_pane == ActivePane::Switcher => {
+                if self.switcher.open_selected() {
+                    self.close_popup();
+                }
+            }
+            KeyCode::Char('n')
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !self.active_pane.is_popup() =>
+            {
+                self.new_conversation.reset();
+                self.open_popup(ActivePane::NewConversation);
+            }
+            KeyCode::Enter if self.active_pane == ActivePane::NewConversation => {
+                if self.new_conversation.open_selected() {
+                    // The first message is written right away
+                    self.close_popup();
+                    self.focus(ActivePane::Input);
+                }
+            }
+            KeyCode::Char('g')
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !self.active_pane.is_popup() =>
+            {
+                self.open_popup(ActivePane::Logs);
             }
 
-            // TODO: In the future, will need a better way to handle this, not
-            // all popups would want to close after enter is hit
             #[cfg(debug_assertions)]
-            KeyCode::Enter if self.active_pane == ActivePane::Popup => {
-                self.get_active_pane_mut().handle_key_event(key);
-                self.active_pane = self.pre_popup_active_pane.clone();
+            KeyCode::Char('d')
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !self.active_pane.is_popup() =>
+            {
+                self.open_popup(ActivePane::DevConsole);
+            }
+
+            KeyCode::Esc if self.active_pane.is_popup() => {
+                self.close_popup();
             }
 
             _ => self.get_active_pane_mut().handle_key_event(key),
@@ -183,11 +350,8 @@ impl ComponentRender<()> for AppRouter {
             frame,
             input_pane::RenderProps {
                 area: input_area,
-                border_color: if self.active_pane == ActivePane::Input {
-                    Color::LightRed
-                } else {
-                    Color::White
-                },
+                border_color: self.theme.border(self.active_pane == ActivePane::Input),
+                text_color: self.theme.input_text,
                 show_cursor: self.active_pane == ActivePane::Input,
             },
         );
@@ -195,32 +359,84 @@ impl ComponentRender<()> for AppRouter {
             frame,
             messages_pane::RenderProps {
                 area: messages_area,
-                border_color: if self.active_pane == ActivePane::Messages {
-                    Color::LightRed
-                } else {
-                    Color::White
-                },
+                border_color: self.theme.border(self.active_pane == ActivePane::Messages),
+                text_color: self.theme.input_text,
+                highlight_color: self.theme.highlight,
             },
         );
         self.conversations_pane.render(
             frame,
             conversations_pane::RenderProps {
                 area: conversation_area,
-                border_color: if self.active_pane == ActivePane::Contacts {
-                    Color::LightRed
-                } else {
-                    Color::White
-                },
+                border_color: self.theme.border(self.active_pane == ActivePane::Contacts),
             },
         );
 
+        if self.active_pane == ActivePane::CommandPalette {
+            self.command_palette.render(
+                frame,
+                command_palette::RenderProps {
+                    area: popup_area(frame.size(), 60, 40),
+                    border_color: self.theme.popup_border,
+                    text_color: self.theme.input_text,
+                    error_color: self.theme.error_text,
+                },
+            );
+        }
+
+        if self.active_pane == ActivePane::Logs {
+            self.log_viewer.render(
+                frame,
+                log_viewer::RenderProps {
+                    area: popup_area(frame.size(), 90, 80),
+                    border_color: self.theme.popup_border,
+                    text_color: self.theme.input_text,
+                },
+            );
+        }
+
+        if self.active_pane == ActivePane::Switcher {
+            self.switcher.render(
+                frame,
+                conversation_switcher::RenderProps {
+                    area: popup_area(frame.size(), 60, 50),
+                    border_color: self.theme.popup_border,
+                    text_color: self.theme.input_text,
+                    highlight_color: self.theme.highlight,
+                },
+            );
+        }
+
+        if self.active_pane == ActivePane::NewConversation {
+            self.new_conversation.render(
+                frame,
+                new_conversation::RenderProps {
+                    area: popup_area(frame.size(), 60, 50),
+                    border_color: self.theme.popup_border,
+                    text_color: self.theme.input_text,
+                },
+            );
+        }
+
+        if self.active_pane == ActivePane::Search {
+            self.search_results.render(
+                frame,
+                search_results::RenderProps {
+                    area: popup_area(frame.size(), 80, 70),
+                    border_color: self.theme.popup_border,
+                    highlight_color: self.theme.highlight,
+                },
+            );
+        }
+
         #[cfg(debug_assertions)]
-        if self.active_pane == ActivePane::Popup {
+        if self.active_pane == ActivePane::DevConsole {
             self.dev_console.render(
                 frame,
                 dev_console::RenderProps {
-                    area: popup_area(frame.size(), 60, 20),
-                    border_color: Color::LightGreen,
+                    area: popup_area(frame.size(), 70, 60),
+                    border_color: self.theme.popup_border,
+                    text_color: self.theme.input_text,
                 },
             );
         }
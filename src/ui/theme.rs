@@ -0,0 +1,67 @@
+use clap::ValueEnum;
+use ratatui::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ThemeName {
+    Dark,
+    Light,
+}
+
+/// Colors shared by all panes so the look can be switched at runtime
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub focused_border: Color,
+    pub unfocused_border: Color,
+    pub popup_border: Color,
+    pub input_text: Color,
+    pub error_text: Color,
+    /// Matches of a search in the text they were found in
+    pub highlight: Color,
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Self {
+            focused_border: Color::LightRed,
+            unfocused_border: Color::White,
+            popup_border: Color::LightGreen,
+            input_text: Color::Yellow,
+            error_text: Color::LightRed,
+            highlight: Color::LightYellow,
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            focused_border: Color::Red,
+            unfocused_border: Color::DarkGray,
+            popup_border: Color::Green,
+            input_text: Color::Blue,
+            error_text: Color::Red,
+            highlight: Color::Magenta,
+        }
+    }
+
+    pub fn border(&self, focused: bool) -> Color {
+        if focused {
+            self.focused_border
+        } else {
+            self.unfocused_border
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl From<ThemeName> for Theme {
+    fn from(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Dark => Self::dark(),
+            ThemeName::Light => Self::light(),
+        }
+    }
+}